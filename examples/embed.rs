@@ -0,0 +1,34 @@
+//! Drive an editor without a terminal: feed it keys, then print what it would draw.
+
+use blue::Editor;
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use ratatui::{buffer::Buffer, layout::Rect};
+
+fn main() -> anyhow::Result<()> {
+    let mut editor = Editor::try_from("hello world\ngoodbye world\n")?;
+    let area = Rect::new(0, 0, 40, 4);
+
+    // Select the first word, delete it, and insert another in its place.
+    for code in [KeyCode::Char('e'), KeyCode::Char('d'), KeyCode::Char('i')]
+        .into_iter()
+        .chain("howdy".chars().map(KeyCode::Char))
+        .chain([KeyCode::Esc])
+    {
+        let event = Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+        editor.handle_event(area, &event)?;
+    }
+
+    let mut buffer = Buffer::empty(area);
+    editor.render(area, &mut buffer);
+    for y in area.top()..area.bottom() {
+        let row = (area.left()..area.right())
+            .map(|x| buffer[(x, y)].symbol())
+            .collect::<String>();
+        println!("{}", row.trim_end());
+    }
+
+    println!("cursor: {:?}", editor.cursor_position(area));
+    println!("modified: {}", editor.is_modified());
+    print!("{}", editor.text());
+    Ok(())
+}
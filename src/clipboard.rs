@@ -0,0 +1,60 @@
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// The OSC 52 escape sequence which asks the terminal to copy `text` to the system clipboard.
+#[must_use]
+pub fn osc52(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", base64(text.as_bytes()))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0, |group, (i, byte)| {
+            group | u32::from(*byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(
+                    ALPHABET[(group >> (18 - 6 * i)) as usize & 0x3f],
+                ));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(encoded: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for chunk in encoded.as_bytes().chunks(4) {
+            let digits = chunk.iter().take_while(|digit| **digit != b'=');
+            let len = digits.clone().count();
+            let group = digits.enumerate().fold(0, |group, (i, digit)| {
+                let value = ALPHABET.iter().position(|char| char == digit).unwrap();
+                group | u32::try_from(value).unwrap() << (18 - 6 * i)
+            });
+            for i in 0..len - 1 {
+                bytes.push(u8::try_from(group >> (16 - 8 * i) & 0xff).unwrap());
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(osc52("hi"), "\x1b]52;c;aGk=\x07");
+
+        let text = "héllo 👋🏽\r\nwörld\n\t中文\n";
+        assert_eq!(decode(&base64(text.as_bytes())), text.as_bytes());
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{
+    editor::{LineNumbers, ListChars, Theme},
+    editorconfig::IndentStyle,
+};
+use anyhow::{Context as _, bail};
+use camino::{Utf8Path, Utf8PathBuf};
+use std::{env, fs};
+
+/// Settings read from a config file at startup. Anything left out keeps the editor's default.
+///
+/// The file is a flat list of TOML `key = value` pairs, like `indent-style = "space"`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    pub tab_width: Option<usize>,
+    pub indent_style: Option<IndentStyle>,
+    pub indent_width: Option<usize>,
+    pub scrolloff: Option<usize>,
+    pub line_numbers: Option<LineNumbers>,
+    pub theme: Option<Theme>,
+    pub final_newline: Option<bool>,
+    pub auto_pairs: Option<bool>,
+    pub list_chars: Option<ListChars>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/blue/config.toml`, falling back to `~/.config/blue/config.toml`.
+    #[must_use]
+    pub fn default_path() -> Option<Utf8PathBuf> {
+        let config_home = match env::var("XDG_CONFIG_HOME") {
+            Ok(config_home) if !config_home.is_empty() => Utf8PathBuf::from(config_home),
+            _ => Utf8PathBuf::from(env::var("HOME").ok()?).join(".config"),
+        };
+        Some(config_home.join("blue/config.toml"))
+    }
+
+    /// # Errors
+    ///
+    /// Fails if the file can't be read or isn't a valid config.
+    pub fn load(path: &Utf8Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        Self::parse(&contents).with_context(|| format!("Invalid config file '{path}'"))
+    }
+
+    fn parse(contents: &str) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                bail!("Expected a key and value: '{line}'");
+            };
+            let key = key.trim();
+            let value =
+                parse_value(value.trim()).with_context(|| format!("Invalid value for '{key}'"))?;
+            let number = || value.parse::<usize>().ok();
+            let valid = match key {
+                "tab-width" => number()
+                    .filter(|width| *width > 0)
+                    .map(|width| config.tab_width = Some(width)),
+                "indent-style" => match value {
+                    "tab" => Some(IndentStyle::Tab),
+                    "space" => Some(IndentStyle::Space),
+                    _ => None,
+                }
+                .map(|style| config.indent_style = Some(style)),
+                "indent-width" => number()
+                    .filter(|width| *width > 0)
+                    .map(|width| config.indent_width = Some(width)),
+                "scrolloff" => number().map(|scrolloff| config.scrolloff = Some(scrolloff)),
+                "line-numbers" => match value {
+                    "absolute" => Some(LineNumbers::Absolute),
+                    "relative" => Some(LineNumbers::Relative),
+                    _ => None,
+                }
+                .map(|line_numbers| config.line_numbers = Some(line_numbers)),
+                "theme" => (value == "light").then(|| config.theme = Some(Theme::Light)),
+                "final-newline" => value
+                    .parse()
+                    .ok()
+                    .map(|final_newline| config.final_newline = Some(final_newline)),
+                "auto-pairs" => value
+                    .parse()
+                    .ok()
+                    .map(|auto_pairs| config.auto_pairs = Some(auto_pairs)),
+                "list-chars" => {
+                    parse_list_chars(value).map(|list_chars| config.list_chars = Some(list_chars))
+                }
+                _ => bail!("Unknown key '{key}'"),
+            };
+            if valid.is_none() {
+                bail!("Invalid value for '{key}': '{value}'");
+            }
+        }
+        Ok(config)
+    }
+}
+
+// The contents of a quoted string, or a bare value without any trailing comment.
+fn parse_value(value: &str) -> anyhow::Result<&str> {
+    if let Some(rest) = value.strip_prefix('"') {
+        let Some((string, rest)) = rest.split_once('"') else {
+            bail!("Unterminated string");
+        };
+        let rest = rest.trim_start();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            bail!("Unexpected '{rest}' after string");
+        }
+        return Ok(string);
+    }
+    let value = value
+        .split_once('#')
+        .map_or(value, |(value, _)| value)
+        .trim_end();
+    if value.is_empty() {
+        bail!("Missing value");
+    }
+    Ok(value)
+}
+
+// Glyphs like `tab:>,trail:-,eol:$`. Any left out keep their defaults.
+fn parse_list_chars(value: &str) -> Option<ListChars> {
+    let mut list_chars = ListChars::default();
+    for item in value.split(',') {
+        let (name, glyph) = item.split_once(':')?;
+        let mut chars = glyph.chars();
+        let (Some(glyph), None) = (chars.next(), chars.next()) else {
+            return None;
+        };
+        match name {
+            "tab" => list_chars.tab = glyph,
+            "trail" => list_chars.trail = glyph,
+            "eol" => list_chars.eol = glyph,
+            _ => return None,
+        }
+    }
+    Some(list_chars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let config = Config::parse(
+            "# Indent with two spaces\n\
+             indent-style = \"space\"\n\
+             indent-width = 2 # like the rest of the project\n\
+             \n\
+             tab-width = 4\n\
+             scrolloff = 0\n\
+             line-numbers = \"relative\"\n\
+             theme = \"light\"\n\
+             final-newline = true\n\
+             auto-pairs = true\n\
+             list-chars = \"tab:>,eol:$\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                tab_width: Some(4),
+                indent_style: Some(IndentStyle::Space),
+                indent_width: Some(2),
+                scrolloff: Some(0),
+                line_numbers: Some(LineNumbers::Relative),
+                theme: Some(Theme::Light),
+                final_newline: Some(true),
+                auto_pairs: Some(true),
+                list_chars: Some(ListChars {
+                    tab: '>',
+                    eol: '$',
+                    ..ListChars::default()
+                }),
+            }
+        );
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        // Only the built-in theme exists so far.
+        assert_eq!(
+            Config::parse("theme = \"dark\"\n").unwrap_err().to_string(),
+            "Invalid value for 'theme': 'dark'"
+        );
+        assert!(Config::parse("indent-width = 0\n").is_err());
+        assert!(Config::parse("indent-style = \"tabs\n").is_err());
+        assert!(Config::parse("scrolloff\n").is_err());
+        assert!(Config::parse("list-chars = \"tab:->\"\n").is_err());
+    }
+}
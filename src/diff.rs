@@ -0,0 +1,305 @@
+use crop::{Rope, RopeSlice};
+use std::{cmp::min, iter, ops::Range};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sign {
+    Added,
+    Changed,
+    Removed,
+}
+
+/// Line-based diff of `old` against `new`, as the ranges of lines which differ.
+#[must_use]
+pub fn hunks(old: &Rope, new: &Rope) -> Vec<Hunk> {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+    let mut hunks = Vec::new();
+    let mut old_start = 0;
+    let mut new_start = 0;
+    for (old_line, new_line) in matches(&old, &new)
+        .into_iter()
+        .chain(iter::once((old.len(), new.len())))
+    {
+        if old_start < old_line || new_start < new_line {
+            hunks.push(Hunk {
+                old: old_start..old_line,
+                new: new_start..new_line,
+            });
+        }
+        old_start = old_line + 1;
+        new_start = new_line + 1;
+    }
+    hunks
+}
+
+/// Per-line signs for a text with `line_len` lines, given its hunks against the baseline.
+#[must_use]
+pub fn signs(hunks: &[Hunk], line_len: usize) -> Vec<Option<Sign>> {
+    let mut signs = vec![None; line_len];
+    for hunk in hunks {
+        if hunk.new.is_empty() {
+            let line = min(hunk.new.start, line_len.saturating_sub(1));
+            if let Some(sign @ None) = signs.get_mut(line) {
+                *sign = Some(Sign::Removed);
+            }
+        } else {
+            for (i, line) in hunk.new.clone().enumerate() {
+                signs[line] = Some(if i < hunk.old.len() {
+                    Sign::Changed
+                } else {
+                    Sign::Added
+                });
+            }
+        }
+    }
+    signs
+}
+
+// Myers' O(ND) diff in linear space, returning the indices of lines common to both sides.
+fn matches(old: &[RopeSlice], new: &[RopeSlice]) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    matches_between(old, new, 0, 0, &mut pairs);
+    pairs
+}
+
+// Push the matches between `old` and `new`, which start at `old_start` and `new_start` in the
+// whole texts. Lines common to both ends are matched first, then the rest is split in two around
+// the middle snake and each half is diffed in turn.
+fn matches_between(
+    mut old: &[RopeSlice],
+    mut new: &[RopeSlice],
+    mut old_start: usize,
+    mut new_start: usize,
+    pairs: &mut Vec<(usize, usize)>,
+) {
+    while let ([old_first, old_rest @ ..], [new_first, new_rest @ ..]) = (old, new)
+        && old_first == new_first
+    {
+        pairs.push((old_start, new_start));
+        (old, new) = (old_rest, new_rest);
+        old_start += 1;
+        new_start += 1;
+    }
+    let suffix = iter::zip(old.iter().rev(), new.iter().rev())
+        .take_while(|(old_line, new_line)| old_line == new_line)
+        .count();
+    let (old, new) = (&old[..old.len() - suffix], &new[..new.len() - suffix]);
+    if !old.is_empty() && !new.is_empty() {
+        let (snake_old, snake_new) = middle_snake(old, new);
+        matches_between(
+            &old[..snake_old.start],
+            &new[..snake_new.start],
+            old_start,
+            new_start,
+            pairs,
+        );
+        pairs.extend(
+            iter::zip(snake_old.clone(), snake_new.clone())
+                .map(|(old_line, new_line)| (old_start + old_line, new_start + new_line)),
+        );
+        matches_between(
+            &old[snake_old.end..],
+            &new[snake_new.end..],
+            old_start + snake_old.end,
+            new_start + snake_new.end,
+            pairs,
+        );
+    }
+    pairs.extend((0..suffix).map(|i| (old_start + old.len() + i, new_start + new.len() + i)));
+}
+
+// The middle snake of a shortest edit script: a run of matching lines, possibly empty, with half
+// the edits before it and half after. Searching from both ends at once only needs the furthest
+// point on each diagonal, rather than every step of the search. Neither side may be empty.
+fn middle_snake(old: &[RopeSlice], new: &[RopeSlice]) -> (Range<usize>, Range<usize>) {
+    debug_assert!(!old.is_empty() && !new.is_empty());
+    let (old_len, new_len) = (old.len(), new.len());
+    // Diagonal `old_line - new_line` is stored at index `diagonal + offset`, so it stays positive.
+    // From the end, diagonals are counted the same way on the reversed texts.
+    let max_edits = (old_len + new_len).div_ceil(2);
+    let offset = max_edits + 1;
+    // The index of the same diagonal in the other direction, if there is one.
+    let opposite = |diagonal: usize| (old_len + 2 * offset).checked_sub(diagonal + new_len);
+    let odd = (old_len + new_len) % 2 == 1;
+    let mut forward = vec![0; 2 * offset + 1];
+    let mut backward = vec![0; 2 * offset + 1];
+    for edits in 0..=max_edits {
+        for diagonal in (offset - edits..=offset + edits).step_by(2) {
+            let down = diagonal == offset - edits
+                || (diagonal != offset + edits && forward[diagonal - 1] < forward[diagonal + 1]);
+            let start = if down {
+                forward[diagonal + 1]
+            } else {
+                forward[diagonal - 1] + 1
+            };
+            let mut old_line = start;
+            let mut new_line = old_line + offset - diagonal;
+            let new_start = new_line;
+            while old_line < old_len && new_line < new_len && old[old_line] == new[new_line] {
+                old_line += 1;
+                new_line += 1;
+            }
+            forward[diagonal] = old_line;
+            // Stop where this meets the furthest the search from the end has reached.
+            let reverse = opposite(diagonal);
+            if odd
+                && let Some(reverse) = reverse
+                && (offset + 1 - edits..offset + edits).contains(&reverse)
+                && old_line + backward[reverse] >= old_len
+            {
+                return (start..old_line, new_start..new_line);
+            }
+        }
+        for diagonal in (offset - edits..=offset + edits).step_by(2) {
+            let down = diagonal == offset - edits
+                || (diagonal != offset + edits && backward[diagonal - 1] < backward[diagonal + 1]);
+            let start = if down {
+                backward[diagonal + 1]
+            } else {
+                backward[diagonal - 1] + 1
+            };
+            let mut old_line = start;
+            let mut new_line = old_line + offset - diagonal;
+            let new_start = new_line;
+            while old_line < old_len
+                && new_line < new_len
+                && old[old_len - 1 - old_line] == new[new_len - 1 - new_line]
+            {
+                old_line += 1;
+                new_line += 1;
+            }
+            backward[diagonal] = old_line;
+            let reverse = opposite(diagonal);
+            if !odd
+                && let Some(reverse) = reverse
+                && (offset - edits..=offset + edits).contains(&reverse)
+                && old_line + forward[reverse] >= old_len
+            {
+                return (
+                    old_len - old_line..old_len - start,
+                    new_len - new_line..new_len - new_start,
+                );
+            }
+        }
+    }
+    unreachable!("the searches from both ends always meet")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cmp::max;
+
+    #[test]
+    fn test() {
+        let old = Rope::from("a\nb\nc\nd\ne\n");
+
+        assert_eq!(hunks(&old, &old), []);
+
+        let new = Rope::from("a\nB\nc\nd\ne\nf\n");
+        let new_hunks = hunks(&old, &new);
+        assert_eq!(
+            new_hunks,
+            [
+                Hunk {
+                    old: 1..2,
+                    new: 1..2
+                },
+                Hunk {
+                    old: 5..5,
+                    new: 5..6
+                },
+            ]
+        );
+        assert_eq!(
+            signs(&new_hunks, new.line_len()),
+            [
+                None,
+                Some(Sign::Changed),
+                None,
+                None,
+                None,
+                Some(Sign::Added)
+            ]
+        );
+
+        let new = Rope::from("a\nd\ne\n");
+        let new_hunks = hunks(&old, &new);
+        assert_eq!(
+            new_hunks,
+            [Hunk {
+                old: 1..3,
+                new: 1..1
+            }]
+        );
+        assert_eq!(
+            signs(&new_hunks, new.line_len()),
+            [None, Some(Sign::Removed), None]
+        );
+
+        let new = Rope::new();
+        let new_hunks = hunks(&old, &new);
+        assert_eq!(
+            new_hunks,
+            [Hunk {
+                old: 0..5,
+                new: 0..0
+            }]
+        );
+        assert_eq!(signs(&new_hunks, new.line_len()), []);
+    }
+
+    #[test]
+    fn test_matches() {
+        // Compare against the longest common subsequence's length, found the slow way, for texts
+        // made from a few letters so there are lots of ways to match them up.
+        let mut seed = 1_u32;
+        let mut letters = |len: usize| {
+            (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    char::from(b'a' + u8::try_from(seed >> 16 & 3).unwrap())
+                })
+                .flat_map(|letter| [letter, '\n'])
+                .collect::<String>()
+        };
+        for (old_len, new_len) in [
+            (0, 3),
+            (1, 1),
+            (5, 2),
+            (8, 13),
+            (20, 20),
+            (31, 17),
+            (40, 64),
+        ] {
+            let old = Rope::from(letters(old_len));
+            let new = Rope::from(letters(new_len));
+            let old = old.lines().collect::<Vec<_>>();
+            let new = new.lines().collect::<Vec<_>>();
+            let pairs = matches(&old, &new);
+            assert!(
+                pairs
+                    .windows(2)
+                    .all(|pair| pair[0].0 < pair[1].0 && pair[0].1 < pair[1].1)
+            );
+            assert!(pairs.iter().all(|(i, j)| old[*i] == new[*j]));
+            let mut lengths = vec![vec![0; new.len() + 1]; old.len() + 1];
+            for i in 0..old.len() {
+                for j in 0..new.len() {
+                    lengths[i + 1][j + 1] = if old[i] == new[j] {
+                        lengths[i][j] + 1
+                    } else {
+                        max(lengths[i][j + 1], lengths[i + 1][j])
+                    };
+                }
+            }
+            assert_eq!(pairs.len(), lengths[old.len()][new.len()]);
+        }
+    }
+}
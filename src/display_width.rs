@@ -3,39 +3,46 @@ use std::cmp::max;
 use unicode_segmentation::UnicodeSegmentation as _;
 use unicode_width::UnicodeWidthStr;
 
+/// How many terminal columns text takes up when it starts a line, with tabs advancing to the next
+/// multiple of `tab_width`.
 pub trait DisplayWidth {
-    fn display_width(&self) -> usize;
+    fn display_width(&self, tab_width: usize) -> usize;
 }
 
 impl DisplayWidth for char {
-    fn display_width(&self) -> usize {
-        self.to_string().as_str().display_width()
+    fn display_width(&self, tab_width: usize) -> usize {
+        self.to_string().as_str().display_width(tab_width)
     }
 }
 
 impl DisplayWidth for &str {
-    fn display_width(&self) -> usize {
-        self.graphemes(true).map(grapheme_width).sum()
+    fn display_width(&self, tab_width: usize) -> usize {
+        self.graphemes(true).fold(0, |column, grapheme| {
+            column + grapheme_width(grapheme, column, tab_width)
+        })
     }
 }
 
 impl DisplayWidth for RopeSlice<'_> {
-    fn display_width(&self) -> usize {
-        self.graphemes()
-            .map(|grapheme| grapheme_width(grapheme.as_ref()))
-            .sum()
+    fn display_width(&self, tab_width: usize) -> usize {
+        self.graphemes().fold(0, |column, grapheme| {
+            column + grapheme_width(grapheme.as_ref(), column, tab_width)
+        })
     }
 }
 
 impl DisplayWidth for Rope {
-    fn display_width(&self) -> usize {
-        self.byte_slice(..).display_width()
+    fn display_width(&self, tab_width: usize) -> usize {
+        self.byte_slice(..).display_width(tab_width)
     }
 }
 
-fn grapheme_width(grapheme: &str) -> usize {
+/// How many columns `grapheme` takes up when drawn at display column `column`. A tab reaches the
+/// next tab stop, so it's narrower after other text.
+#[must_use]
+pub fn grapheme_width(grapheme: &str, column: usize, tab_width: usize) -> usize {
     if let Some('\t') = grapheme.chars().next() {
-        return 8;
+        return tab_width - column % tab_width;
     }
     max(1, UnicodeWidthStr::width(grapheme))
 }
@@ -46,16 +53,20 @@ mod tests {
 
     #[test]
     fn test() {
-        assert_eq!("".display_width(), 0);
-        assert_eq!("‍".display_width(), 1); // zwj
-        assert_eq!('\x00'.display_width(), 1); // null
-        assert_eq!("\x00".display_width(), 1); // null
-        assert_eq!("\u{200B}".display_width(), 1); // zws
-        assert_eq!("abc".display_width(), 3);
-        assert_eq!("🇯🇵".display_width(), 2);
-        assert_eq!("👩🏻‍❤️‍💋‍👩🏻".display_width(), 2);
-        assert_eq!('\t'.display_width(), 8);
-        assert_eq!("\t".display_width(), 8);
-        assert_eq!("\n".display_width(), 1);
+        assert_eq!("".display_width(8), 0);
+        assert_eq!("‍".display_width(8), 1); // zwj
+        assert_eq!('\x00'.display_width(8), 1); // null
+        assert_eq!("\x00".display_width(8), 1); // null
+        assert_eq!("\u{200B}".display_width(8), 1); // zws
+        assert_eq!("abc".display_width(8), 3);
+        assert_eq!("🇯🇵".display_width(8), 2);
+        assert_eq!("👩🏻‍❤️‍💋‍👩🏻".display_width(8), 2);
+        assert_eq!('\t'.display_width(8), 8);
+        assert_eq!("\t".display_width(8), 8);
+        assert_eq!("\n".display_width(8), 1);
+        assert_eq!("a\tb".display_width(4), 5);
+        assert_eq!("abcd\tb".display_width(4), 9);
+        assert_eq!(grapheme_width("\t", 6, 4), 2);
+        assert_eq!(grapheme_width("日", 6, 4), 2);
     }
 }
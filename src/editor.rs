@@ -1,37 +1,190 @@
 use crate::{
-    display_width::DisplayWidth as _,
-    graphemes::{ceil_grapheme_boundary, next_grapheme_boundary, prev_grapheme_boundary},
+    config::Config,
+    diff::{self, Hunk},
+    display_width::{DisplayWidth as _, grapheme_width},
+    editorconfig::{self, IndentStyle},
+    filetype,
+    fold::{self, Fold},
+    graphemes::{
+        ceil_grapheme_boundary, floor_grapheme_boundary, next_grapheme_boundary,
+        prev_grapheme_boundary,
+    },
+    highlight::{self, Highlight, Rule},
+    history::{History, Snapshot},
+    indent::{self, Indent},
+    session::{self, Session},
 };
 use camino::{Utf8Path, Utf8PathBuf};
-use clap::Parser as _;
+use clap::{CommandFactory as _, Parser as _};
 use crop::Rope;
+use pathdiff::diff_utf8_paths;
+use regex::{Regex, RegexBuilder};
 use std::{
+    cell::{Cell, Ref, RefCell},
     cmp::{max, min},
-    fs, iter, mem,
-    process::ExitCode,
+    collections::HashMap,
+    env, fmt, fs,
+    io::{self, Read, Write as _},
+    iter, mem,
+    ops::{Range, RangeInclusive},
+    process::{self, Child, ExitCode, Output, Stdio},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
+#[expect(clippy::struct_excessive_bools)]
 pub struct Editor {
-    pub pwd: Option<Utf8PathBuf>,
-    pub path: Option<Utf8PathBuf>,
-    pub modified: bool,
-    pub text: Rope,
-    pub anchor: usize,
-    pub head: usize,
+    pub(crate) pwd: Option<Utf8PathBuf>,
+    pub(crate) path: Option<Utf8PathBuf>,
+    pub(crate) filetype: Option<String>,
+    /// Highlighting rules for the current file type, compiled when it's set.
+    pub(crate) highlight_rules: Vec<Rule>,
+    pub(crate) modified: bool,
+    pub(crate) text: Rope,
+    baseline: Rope,
+    /// The hunks between `baseline` and `text`, worked out the first time they're needed after
+    /// either changes.
+    hunks: RefCell<Option<Vec<Hunk>>>,
+    dirty: Cell<Option<Range<usize>>>,
+    /// Each line's highlighted spans, kept from one frame to the next until it's edited.
+    highlights: RefCell<Vec<Option<Vec<Highlight>>>>,
+    pub(crate) anchor: usize,
+    pub(crate) head: usize,
+    desired_column: usize,
+    /// Selections besides the primary one in `anchor` and `head`, sorted and not overlapping.
+    pub(crate) selections: Vec<Selection>,
+    pub(crate) vertical_scroll: usize,
+    /// Manual folds, sorted and not overlapping.
+    pub(crate) folds: Vec<Fold>,
+    /// Named cursor positions, which move with the text around them.
+    pub(crate) marks: HashMap<char, usize>,
+    /// Cursor positions from before big jumps, oldest first, which also move with the text.
+    jumps: Vec<usize>,
+    /// Where `jump_back` and `jump_forward` are in `jumps`. It's `jumps.len()` until jumping back.
+    jump_index: usize,
+    /// Lines kept visible above and below the cursor.
+    pub(crate) scrolloff: usize,
+    history: History,
+    pub(crate) register: String,
+    /// The text typed so far in the current Insert session.
+    pub(crate) inserting: String,
+    /// The text typed in the last Insert session, like Vim's `.` register.
+    pub(crate) inserted: String,
+    pub(crate) clipboard: Clipboard,
+    /// Text to copy to the system clipboard, which is sent to the terminal before the next draw.
+    pub(crate) pending_clipboard: Option<String>,
+    /// A command line that runs a shell command, which is run after the next draw so the status
+    /// bar says it's running while it blocks.
+    pending_command: Option<String>,
+    /// How long a shell command can run before it's killed, or zero for no limit.
+    pub(crate) shell_timeout: Duration,
+    pub(crate) relative_path: bool,
+    pub(crate) eol_cursor: EolCursor,
+    pub(crate) cursor_styles: CursorStyles,
+    pub(crate) expand_tab: bool,
+    pub(crate) indent_width: usize,
+    pub(crate) trim_trailing_whitespace: bool,
+    /// Type brackets and quotes in pairs in Insert mode.
+    pub(crate) auto_pairs: bool,
+    /// End the file with exactly one newline when saving.
+    pub(crate) final_newline: bool,
+    /// How lines end in the file. The text itself always uses `\n`.
+    pub(crate) line_ending: LineEnding,
+    /// How many columns a tab takes up.
+    pub(crate) tab_width: usize,
+    pub(crate) line_numbers: LineNumbers,
+    pub(crate) theme: Theme,
+    /// Draw whitespace with the glyphs in `list_chars`.
+    pub(crate) list: bool,
+    pub(crate) list_chars: ListChars,
+    /// The config file's settings, which new buffers start from.
+    config: Config,
+    /// Prefix added and removed by `toggle_comment`.
+    pub(crate) comment_token: String,
+    pub(crate) mode: Mode,
+    /// Digits typed in Normal mode, which repeat the next command.
+    pub(crate) count: Option<usize>,
+    pub(crate) command: Rope,
+    pub(crate) command_cursor: usize,
+    /// The matches from completing with several to choose from, which Tab and Shift-Tab cycle
+    /// through.
+    pub(crate) completion: Option<Completion>,
+    pub(crate) last_search: Option<String>,
+    /// The pattern, replacement, and whether to confirm from the last `:substitute`, repeated by
+    /// `:&`.
+    pub(crate) last_substitute: Option<(String, String, bool)>,
+    /// A substitution waiting in Confirm mode for an answer about the selected match.
+    pub(crate) confirm: Option<Confirm>,
+    /// Confine searches to the selection they started from.
+    pub(crate) search_in_selection: bool,
+    /// Refuse to change the text, for looking at files without editing them by accident.
+    pub(crate) read_only: bool,
+    /// Keep the undo history of each file in `undo_dir` when it's saved or the editor exits. A
+    /// file's history is picked up from there when it's opened either way, as long as the file
+    /// hasn't changed since.
+    pub(crate) undofile: bool,
+    undo_dir: Option<Utf8PathBuf>,
+    /// Match searches regardless of case.
+    pub(crate) ignore_case: bool,
+    /// Match searches regardless of case, unless the query has an uppercase letter.
+    pub(crate) smart_case: bool,
+    /// Only match searches which start and end on word boundaries.
+    pub(crate) whole_word: bool,
+    search_range: Option<Range<usize>>,
+    /// The selection and scroll from before the search being typed, to go back to if it's
+    /// cancelled.
+    search_origin: Option<(Selection, usize)>,
+    pub(crate) message: Option<Result<String, String>>,
+    pub(crate) exit_code: Option<ExitCode>,
+    /// Open files besides the current one, in order. The current one sits between
+    /// `buffers[..buffer_index]` and `buffers[buffer_index..]`.
+    buffers: Vec<Buffer>,
+    buffer_index: usize,
+}
+
+/// An open file other than the current one, with the state which belongs to it.
+#[expect(clippy::struct_excessive_bools)]
+struct Buffer {
+    path: Option<Utf8PathBuf>,
+    filetype: Option<String>,
+    highlight_rules: Vec<Rule>,
+    comment_token: String,
+    modified: bool,
+    text: Rope,
+    baseline: Rope,
+    anchor: usize,
+    head: usize,
     desired_column: usize,
-    pub vertical_scroll: usize,
-    pub mode: Mode,
-    pub command: Rope,
-    pub command_cursor: usize,
-    pub message: Option<Result<String, String>>,
-    pub exit_code: Option<ExitCode>,
+    selections: Vec<Selection>,
+    vertical_scroll: usize,
+    folds: Vec<Fold>,
+    marks: HashMap<char, usize>,
+    jumps: Vec<usize>,
+    jump_index: usize,
+    history: History,
+    expand_tab: bool,
+    indent_width: usize,
+    tab_width: usize,
+    trim_trailing_whitespace: bool,
+    final_newline: bool,
+    line_ending: LineEnding,
 }
 
 impl Editor {
+    /// An editor with an empty text and no file.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the editor can't be set up, like `try_from`.
     pub fn new() -> anyhow::Result<Self> {
         Self::try_from(Rope::new())
     }
 
+    /// Open the file at `path`, or an empty text which will be saved there if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file exists but can't be read as UTF-8.
     pub fn open(path: impl AsRef<Utf8Path>) -> anyhow::Result<Self> {
         let exists = path.as_ref().try_exists()?;
         let path = if exists {
@@ -39,36 +192,692 @@ impl Editor {
         } else {
             path.as_ref().to_path_buf()
         };
-        let rope = if exists {
-            let string = fs::read_to_string(&path)?;
-            Rope::from(string)
+        let (rope, line_ending) = if exists {
+            read_text(&path)?
         } else {
-            Rope::new()
+            (Rope::new(), LineEnding::Lf)
         };
         let mut editor = Self::try_from(rope)?;
+        editor.line_ending = line_ending;
+        editor.set_filetype(filetype::detect(&path).map(String::from));
         editor.path = Some(path);
+        editor.apply_file_settings()?;
+        editor.load_history();
+        Ok(editor)
+    }
+
+    // Settings for this file in particular, from its indentation, whether it ends with a newline,
+    // and `.editorconfig`.
+    fn apply_file_settings(&mut self) -> anyhow::Result<()> {
+        match indent::detect(&self.text) {
+            None => {}
+            Some(Indent::Tabs) => self.expand_tab = false,
+            Some(Indent::Spaces(width)) => {
+                self.expand_tab = true;
+                self.indent_width = width;
+            }
+        }
+        if let Some(last) = self.text.bytes().next_back() {
+            self.final_newline = last == b'\n';
+        }
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let absolute_path = Utf8PathBuf::try_from(std::path::absolute(path)?)?;
+        match editorconfig::properties(&absolute_path) {
+            Ok(properties) => self.apply_editorconfig(&properties),
+            Err(error) => {
+                self.message = Some(Err(format!("Failed to read .editorconfig: {error}")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Use the settings from a config file. Indentation and final newlines detected in the file, or
+    /// set by `.editorconfig`, still take precedence.
+    pub fn set_config(&mut self, config: Config) {
+        if let Some(tab_width) = config.tab_width {
+            self.tab_width = tab_width;
+        }
+        if let Some(indent_style) = config.indent_style {
+            self.expand_tab = indent_style == IndentStyle::Space;
+        }
+        if let Some(indent_width) = config.indent_width {
+            self.indent_width = indent_width;
+        }
+        if let Some(scrolloff) = config.scrolloff {
+            self.scrolloff = scrolloff;
+        }
+        if let Some(line_numbers) = config.line_numbers {
+            self.line_numbers = line_numbers;
+        }
+        if let Some(theme) = config.theme {
+            self.theme = theme;
+        }
+        if let Some(final_newline) = config.final_newline {
+            self.final_newline = final_newline;
+        }
+        if let Some(auto_pairs) = config.auto_pairs {
+            self.auto_pairs = auto_pairs;
+        }
+        if let Some(list_chars) = config.list_chars {
+            self.list_chars = list_chars;
+        }
+        self.config = config;
+        if self.path.is_some()
+            && let Err(error) = self.apply_file_settings()
+        {
+            self.message = Some(Err(error.to_string()));
+        }
+    }
+
+    /// Open the file at `path`, resolved against the working directory, in a new buffer after the
+    /// current one. With `replace`, it takes the current buffer's place instead, throwing away any
+    /// unsaved changes. A file which is already open is switched to instead, and with `replace` it's
+    /// reloaded.
+    fn edit(&mut self, path: &Utf8Path, replace: bool) {
+        let path = match &self.pwd {
+            Some(pwd) => pwd.join(path),
+            None => path.to_path_buf(),
+        };
+        let canonical_path = path.canonicalize_utf8().unwrap_or_else(|_| path.clone());
+        if let Some(index) = self.buffer_with_path(&canonical_path) {
+            self.activate_buffer(index);
+            if replace {
+                self.reload();
+            }
+            return;
+        }
+        let mut opened = match Self::open(&path) {
+            Ok(opened) => opened,
+            Err(error) => {
+                self.message = Some(Err(format!("Failed to open '{path}': {error}")));
+                return;
+            }
+        };
+        opened.set_config(self.config.clone());
+        self.message = opened.message.take();
+        let previous = self.swap_buffer(opened.into_buffer());
+        if !replace {
+            self.buffers.insert(self.buffer_index, previous);
+            self.buffer_index += 1;
+        }
+    }
+
+    /// Open the file at `path` in a new buffer after the others, like `open`, but stay on the
+    /// current one. A file which is already open isn't opened again.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file exists but can't be read as UTF-8.
+    pub fn open_buffer(&mut self, path: impl AsRef<Utf8Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let canonical_path = path
+            .canonicalize_utf8()
+            .unwrap_or_else(|_| path.to_path_buf());
+        if self.buffer_with_path(&canonical_path).is_some() {
+            return Ok(());
+        }
+        let mut opened = Self::open(path)?;
+        opened.set_config(self.config.clone());
+        if let Some(message) = opened.message.take() {
+            self.message = Some(message);
+        }
+        self.buffers.push(opened.into_buffer());
+        Ok(())
+    }
+
+    /// Read the file again, throwing away unsaved changes. The selection stays where it was as far
+    /// as the new text allows, and reloading can be undone.
+    pub(crate) fn reload(&mut self) {
+        let Some(path) = &self.path else {
+            self.message = Some(Err(String::from("No file name")));
+            return;
+        };
+        let text = match read_text(path) {
+            Ok((text, line_ending)) => {
+                self.line_ending = line_ending;
+                text
+            }
+            Err(error) => {
+                self.message = Some(Err(format!("Failed to read '{path}': {error}")));
+                return;
+            }
+        };
+        if text != self.text {
+            let clamp = |offset| floor_grapheme_boundary(&text.byte_slice(..), offset);
+            let snapshot = Snapshot {
+                text: text.clone(),
+                anchor: clamp(self.anchor),
+                head: clamp(self.head),
+                desired_column: self.desired_column,
+            };
+            self.history.record(self.snapshot());
+            self.restore(snapshot);
+            self.vertical_scroll =
+                min(self.vertical_scroll, self.text.line_len().saturating_sub(1));
+        }
+        self.baseline = text;
+        *self.hunks.get_mut() = None;
+        self.history.mark_saved();
+        self.modified = false;
+    }
+
+    // Take the state belonging to this editor's file, leaving the editor itself behind.
+    fn into_buffer(self) -> Buffer {
+        Buffer {
+            path: self.path,
+            filetype: self.filetype,
+            highlight_rules: self.highlight_rules,
+            comment_token: self.comment_token,
+            modified: self.modified,
+            text: self.text,
+            baseline: self.baseline,
+            anchor: self.anchor,
+            head: self.head,
+            desired_column: self.desired_column,
+            selections: self.selections,
+            vertical_scroll: self.vertical_scroll,
+            folds: self.folds,
+            marks: self.marks,
+            jumps: self.jumps,
+            jump_index: self.jump_index,
+            history: self.history,
+            expand_tab: self.expand_tab,
+            indent_width: self.indent_width,
+            tab_width: self.tab_width,
+            trim_trailing_whitespace: self.trim_trailing_whitespace,
+            final_newline: self.final_newline,
+            line_ending: self.line_ending,
+        }
+    }
+
+    // Make `buffer` the current one, returning the buffer it replaced. Options, registers, and
+    // the last search are shared by every buffer, so they stay put.
+    fn swap_buffer(&mut self, buffer: Buffer) -> Buffer {
+        let previous = Buffer {
+            path: mem::replace(&mut self.path, buffer.path),
+            filetype: mem::replace(&mut self.filetype, buffer.filetype),
+            highlight_rules: mem::replace(&mut self.highlight_rules, buffer.highlight_rules),
+            comment_token: mem::replace(&mut self.comment_token, buffer.comment_token),
+            modified: mem::replace(&mut self.modified, buffer.modified),
+            text: mem::replace(&mut self.text, buffer.text),
+            baseline: mem::replace(&mut self.baseline, buffer.baseline),
+            anchor: mem::replace(&mut self.anchor, buffer.anchor),
+            head: mem::replace(&mut self.head, buffer.head),
+            desired_column: mem::replace(&mut self.desired_column, buffer.desired_column),
+            selections: mem::replace(&mut self.selections, buffer.selections),
+            vertical_scroll: mem::replace(&mut self.vertical_scroll, buffer.vertical_scroll),
+            folds: mem::replace(&mut self.folds, buffer.folds),
+            marks: mem::replace(&mut self.marks, buffer.marks),
+            jumps: mem::replace(&mut self.jumps, buffer.jumps),
+            jump_index: mem::replace(&mut self.jump_index, buffer.jump_index),
+            history: mem::replace(&mut self.history, buffer.history),
+            expand_tab: mem::replace(&mut self.expand_tab, buffer.expand_tab),
+            indent_width: mem::replace(&mut self.indent_width, buffer.indent_width),
+            tab_width: mem::replace(&mut self.tab_width, buffer.tab_width),
+            trim_trailing_whitespace: mem::replace(
+                &mut self.trim_trailing_whitespace,
+                buffer.trim_trailing_whitespace,
+            ),
+            final_newline: mem::replace(&mut self.final_newline, buffer.final_newline),
+            line_ending: mem::replace(&mut self.line_ending, buffer.line_ending),
+        };
+        *self.dirty.get_mut() = Some(0..self.text.byte_len());
+        *self.hunks.get_mut() = None;
+        self.confirm = None;
+        self.search_range = None;
+        previous
+    }
+
+    /// How many files are open, counting the current one.
+    pub(crate) fn buffer_count(&self) -> usize {
+        self.buffers.len() + 1
+    }
+
+    /// The current buffer's position among the open ones, from 0.
+    pub(crate) fn buffer_index(&self) -> usize {
+        self.buffer_index
+    }
+
+    // The position among the open buffers of the one editing the file at `path`, if any.
+    fn buffer_with_path(&self, path: &Utf8Path) -> Option<usize> {
+        if self.path.as_deref() == Some(path) {
+            return Some(self.buffer_index);
+        }
+        let index = self
+            .buffers
+            .iter()
+            .position(|buffer| buffer.path.as_deref() == Some(path))?;
+        Some(if index < self.buffer_index {
+            index
+        } else {
+            index + 1
+        })
+    }
+
+    // Switch to the buffer at `index` among the open ones.
+    fn activate_buffer(&mut self, index: usize) {
+        debug_assert!(index < self.buffer_count());
+        if index == self.buffer_index {
+            return;
+        }
+        let before = index < self.buffer_index;
+        let buffer = self.buffers.remove(if before { index } else { index - 1 });
+        let previous = self.swap_buffer(buffer);
+        let previous_index = if before {
+            self.buffer_index - 1
+        } else {
+            self.buffer_index
+        };
+        self.buffers.insert(previous_index, previous);
+        self.buffer_index = index;
+    }
+
+    /// Switch to the next buffer, wrapping around after the last one.
+    pub(crate) fn buffer_next(&mut self) {
+        self.activate_buffer((self.buffer_index + 1) % self.buffer_count());
+    }
+
+    /// Switch to the previous buffer, wrapping around before the first one.
+    pub(crate) fn buffer_prev(&mut self) {
+        let count = self.buffer_count();
+        self.activate_buffer((self.buffer_index + count - 1) % count);
+    }
+
+    // Close the current buffer and switch to the next one, or the previous one if it was last.
+    // Returns false when it's the only buffer, which can't be closed.
+    fn close_buffer(&mut self) -> bool {
+        if self.buffers.is_empty() {
+            return false;
+        }
+        let index = min(self.buffer_index, self.buffers.len() - 1);
+        let buffer = self.buffers.remove(index);
+        self.swap_buffer(buffer);
+        self.buffer_index = index;
+        true
+    }
+
+    // Close the current buffer, or exit with `exit_code` if it's the last one.
+    fn quit(&mut self, exit_code: Option<u8>) {
+        if !self.modified {
+            self.save_history();
+        }
+        if !self.close_buffer() {
+            self.exit_code = Some(exit_code.map_or(ExitCode::SUCCESS, ExitCode::from));
+        }
+    }
+
+    // Exit with `exit_code`, unless a buffer has unsaved changes and this isn't forced.
+    fn quit_all(&mut self, exit_code: Option<u8>, force: bool) {
+        let mut unsaved = self
+            .buffers
+            .iter()
+            .filter(|buffer| buffer.modified)
+            .map(|buffer| self.display_path(buffer.path.as_deref()))
+            .collect::<Vec<_>>();
+        if self.modified {
+            let index = self.buffers[..self.buffer_index]
+                .iter()
+                .filter(|buffer| buffer.modified)
+                .count();
+            unsaved.insert(index, self.display_path(self.path.as_deref()));
+        }
+        if !force && !unsaved.is_empty() {
+            self.message = Some(Err(format!("Unsaved changes in {}", unsaved.join(", "))));
+            return;
+        }
+        if !self.modified {
+            self.save_history();
+        }
+        if self.undofile
+            && let Some(undo_dir) = &self.undo_dir
+        {
+            for buffer in &self.buffers {
+                if !buffer.modified
+                    && let Some(path) = &buffer.path
+                {
+                    // There's nowhere left to say it failed.
+                    let _ = write_undo_file(undo_dir, path, &buffer.history, &buffer.text);
+                }
+            }
+        }
+        self.exit_code = Some(exit_code.map_or(ExitCode::SUCCESS, ExitCode::from));
+    }
+
+    // List the open buffers in the status bar, with the current one in brackets.
+    fn list_buffers(&mut self) {
+        let name = |path: Option<&Utf8Path>, modified: bool| {
+            let modified = if modified { "*" } else { "" };
+            format!("{}{modified}", self.display_path(path))
+        };
+        let mut list = self
+            .buffers
+            .iter()
+            .map(|buffer| name(buffer.path.as_deref(), buffer.modified))
+            .collect::<Vec<_>>();
+        let current = format!("[{}]", name(self.path.as_deref(), self.modified));
+        list.insert(self.buffer_index, current);
+        self.message = Some(Ok(list.join(" ")));
+    }
+
+    /// How `path` is shown to the user, relative to the working directory if that's shorter.
+    pub(crate) fn display_path(&self, path: Option<&Utf8Path>) -> String {
+        match (&self.pwd, path) {
+            (_, None) => String::from("*scratch*"),
+            (Some(pwd), Some(path)) if self.relative_path => match diff_utf8_paths(path, pwd) {
+                Some(relative_path) if relative_path.as_str().len() <= path.as_str().len() => {
+                    relative_path.to_string()
+                }
+                _ => path.to_string(),
+            },
+            (_, Some(path)) => path.to_string(),
+        }
+    }
+
+    /// Reopen the file from a saved session, with its selection and scroll position.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the session's file can't be opened, like `open`.
+    pub fn from_session(session: &Session) -> anyhow::Result<Self> {
+        let mut editor = match &session.path {
+            Some(path) => Self::open(path)?,
+            None => Self::new()?,
+        };
+        let text = editor.text.byte_slice(..);
+        editor.anchor = floor_grapheme_boundary(&text, session.anchor);
+        editor.head = floor_grapheme_boundary(&text, session.head);
+        editor.vertical_scroll = min(
+            session.vertical_scroll,
+            editor.text.line_len().saturating_sub(1),
+        );
+        editor.update_desired_column();
         Ok(editor)
     }
 
+    #[must_use]
+    pub fn session(&self) -> Session {
+        Session {
+            path: self.path.clone(),
+            anchor: self.anchor,
+            head: self.head,
+            vertical_scroll: self.vertical_scroll,
+        }
+    }
+
+    #[must_use]
+    pub fn text(&self) -> &Rope {
+        &self.text
+    }
+
+    #[must_use]
+    pub fn path(&self) -> Option<&Utf8Path> {
+        self.path.as_deref()
+    }
+
+    /// Whether the text has changed since it was last loaded or saved.
+    #[must_use]
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    #[must_use]
+    pub fn mode(&self) -> &Mode {
+        &self.mode
+    }
+
+    /// The message shown in the status bar, either information or an error.
+    #[must_use]
+    pub fn message(&self) -> Option<&Result<String, String>> {
+        self.message.as_ref()
+    }
+
+    pub fn set_message(&mut self, message: Result<String, String>) {
+        self.message = Some(message);
+    }
+
+    /// The exit code requested by a command like `:quit`, once the editor should close.
+    #[must_use]
+    pub fn exit_code(&self) -> Option<ExitCode> {
+        self.exit_code
+    }
+
+    /// Text yanked while using the system clipboard, which the caller should copy to it.
+    pub fn take_pending_clipboard(&mut self) -> Option<String> {
+        self.pending_clipboard.take()
+    }
+
+    /// Run the command line left by `execute_command` for after the next draw, returning whether
+    /// there was one.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the command does.
+    pub fn run_pending_command(&mut self) -> anyhow::Result<bool> {
+        let Some(command) = self.pending_command.take() else {
+            return Ok(false);
+        };
+        self.message = None;
+        self.run_command(&command)?;
+        Ok(true)
+    }
+
+    /// The cursor style for the current mode, as configured with `guicursor`.
+    #[must_use]
+    pub fn cursor_style(&self) -> CursorStyle {
+        self.cursor_styles.get(&self.mode)
+    }
+
+    /// Set the working directory, which paths are shown relative to.
+    pub fn set_pwd(&mut self, pwd: Utf8PathBuf) {
+        self.pwd = Some(pwd);
+    }
+
+    fn apply_editorconfig(&mut self, properties: &editorconfig::Properties) {
+        match properties.indent_style {
+            None => {}
+            Some(IndentStyle::Tab) => self.expand_tab = false,
+            Some(IndentStyle::Space) => self.expand_tab = true,
+        }
+        if let Some(width) = properties.indent_width().filter(|width| *width > 0) {
+            self.indent_width = width;
+        }
+        if let Some(width) = properties.tab_width.filter(|width| *width > 0) {
+            self.tab_width = width;
+        }
+        if let Some(line_ending) = properties.end_of_line {
+            self.line_ending = line_ending;
+        }
+        if let Some(trim_trailing_whitespace) = properties.trim_trailing_whitespace {
+            self.trim_trailing_whitespace = trim_trailing_whitespace;
+        }
+        if let Some(insert_final_newline) = properties.insert_final_newline {
+            self.final_newline = insert_final_newline;
+        }
+        if let Some(charset) = &properties.charset
+            && charset != "utf-8"
+        {
+            self.message = Some(Err(format!("Unsupported charset '{charset}'")));
+        }
+    }
+
+    /// Refuse to change the text, or allow it again.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Set the file type, replacing the highlighting rules and comment token with its own.
+    pub fn set_filetype(&mut self, filetype: Option<String>) {
+        self.highlight_rules = highlight::rules(filetype.as_deref());
+        self.comment_token = String::from(filetype::comment_token(filetype.as_deref()));
+        self.highlights.get_mut().clear();
+        self.filetype = filetype;
+    }
+
+    /// Write the text to its file, if it has one.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be written.
     pub fn save(&mut self) -> anyhow::Result<()> {
+        if self.path.is_some() && self.trim_trailing_whitespace {
+            self.delete_trailing_whitespace();
+        }
+        if self.path.is_some() && self.final_newline {
+            self.ensure_final_newline();
+        }
         if let Some(path) = &self.path {
-            let bytes = self.text.bytes().collect::<Vec<_>>();
+            let bytes = match self.line_ending {
+                LineEnding::Lf => self.text.bytes().collect::<Vec<_>>(),
+                LineEnding::Crlf => self.text.to_string().replace('\n', "\r\n").into_bytes(),
+            };
             fs::write(path, bytes)?;
+            self.baseline = self.text.clone();
+            *self.hunks.get_mut() = None;
+            self.history.mark_saved();
             self.modified = false;
+            self.save_history();
         }
         Ok(())
     }
 
-    fn update_desired_column(&mut self) {
+    // Keep the undo history for next time, with `undofile`.
+    fn save_history(&mut self) {
+        if !self.undofile {
+            return;
+        }
+        let (Some(undo_dir), Some(path)) = (&self.undo_dir, &self.path) else {
+            return;
+        };
+        if let Err(error) = write_undo_file(undo_dir, path, &self.history, &self.text) {
+            self.message = Some(Err(format!("Failed to write undo file: {error}")));
+        }
+    }
+
+    // Pick up the undo history kept for this file, unless it's changed since.
+    fn load_history(&mut self) {
+        let (Some(undo_dir), Some(path)) = (&self.undo_dir, &self.path) else {
+            return;
+        };
+        if let Some(file) = undo_file(undo_dir, path)
+            && let Ok(contents) = fs::read_to_string(file)
+            && let Some(history) = History::from_undo_file(&contents, &self.text)
+        {
+            self.history = history;
+        }
+    }
+
+    // Save to the file's own path, saying why not in the status bar if it can't. Returns whether it
+    // was saved.
+    fn write(&mut self) -> bool {
+        let Some(path) = self.path.clone() else {
+            self.message = Some(Err(String::from("No file name")));
+            return false;
+        };
+        if let Err(error) = self.save() {
+            self.message = Some(Err(format!("Failed to write '{path}': {error}")));
+            return false;
+        }
+        true
+    }
+
+    // Save to `path`, resolved against the working directory, and keep saving there. Missing
+    // directories are created, and failures are reported in the status bar.
+    fn save_as(&mut self, path: &Utf8Path) {
+        let path = match &self.pwd {
+            Some(pwd) => pwd.join(path),
+            None => path.to_path_buf(),
+        };
+        if let Some(parent) = path.parent()
+            && let Err(error) = fs::create_dir_all(parent)
+        {
+            self.message = Some(Err(format!("Failed to write '{path}': {error}")));
+            return;
+        }
+        let previous = self.path.replace(path.clone());
+        if let Err(error) = self.save() {
+            self.path = previous;
+            self.message = Some(Err(format!("Failed to write '{path}': {error}")));
+        }
+    }
+
+    /// Changed lines relative to the text as it was last loaded or saved.
+    pub(crate) fn hunks(&self) -> Ref<'_, [Hunk]> {
+        if self.hunks.borrow().is_none() {
+            let hunks = if self.modified {
+                diff::hunks(&self.baseline, &self.text)
+            } else {
+                Vec::new()
+            };
+            *self.hunks.borrow_mut() = Some(hunks);
+        }
+        Ref::map(self.hunks.borrow(), |hunks| {
+            hunks.as_deref().unwrap_or_default()
+        })
+    }
+
+    /// The text inserted for one level of indentation.
+    pub(crate) fn indent_unit(&self) -> String {
+        if self.expand_tab {
+            " ".repeat(self.indent_width)
+        } else {
+            String::from("\t")
+        }
+    }
+
+    fn delete_trailing_whitespace(&mut self) {
+        let snapshot = self.snapshot();
+        let mut changed = false;
+        for line_index in (0..self.text.line_len()).rev() {
+            let line = self.text.line(line_index).to_string();
+            let line_byte_index = self.text.byte_of_line(line_index);
+            let start = line_byte_index + line.trim_end_matches([' ', '\t']).len();
+            let end = line_byte_index + line.len();
+            if start == end {
+                continue;
+            }
+            self.apply_edit(start..end, "");
+            changed = true;
+        }
+        if changed {
+            self.history.record(snapshot);
+        }
+        self.update_desired_column();
+    }
+
+    // Leave exactly one newline at the end of a non-empty text, adding one or deleting extras.
+    fn ensure_final_newline(&mut self) {
+        let len = self.text.byte_len();
+        let newlines = self
+            .text
+            .bytes()
+            .rev()
+            .take_while(|byte| *byte == b'\n')
+            .count();
+        if len == 0 || newlines == 1 {
+            return;
+        }
+        let snapshot = self.snapshot();
+        if newlines == 0 {
+            self.apply_edit(len..len, "\n");
+        } else {
+            self.apply_edit(len - newlines + 1..len, "");
+        }
+        self.history.record(snapshot);
+        self.update_desired_column();
+    }
+
+    pub(crate) fn update_desired_column(&mut self) {
         let current_line_index = self.text.line_of_byte(self.head);
         let current_line_byte_index = self.text.byte_of_line(current_line_index);
         self.desired_column = self
             .text
             .byte_slice(current_line_byte_index..self.head)
-            .display_width();
+            .display_width(self.tab_width);
     }
 
-    pub fn extend_to(&mut self, byte_offset: usize) {
+    pub(crate) fn extend_to(&mut self, byte_offset: usize) {
         debug_assert!(self.text.is_grapheme_boundary(byte_offset));
         if self.is_backward() {
             self.head = byte_offset;
@@ -78,7 +887,7 @@ impl Editor {
         self.update_desired_column();
     }
 
-    pub fn extend_left(&mut self, count: usize) {
+    pub(crate) fn extend_left(&mut self, count: usize) {
         debug_assert!(self.text.is_grapheme_boundary(self.head));
         for _ in 0..count {
             match prev_grapheme_boundary(&self.text.byte_slice(..), self.head) {
@@ -89,7 +898,7 @@ impl Editor {
         self.update_desired_column();
     }
 
-    pub fn extend_right(&mut self, count: usize) {
+    pub(crate) fn extend_right(&mut self, count: usize) {
         debug_assert!(self.text.is_grapheme_boundary(self.head));
         for _ in 0..count {
             match next_grapheme_boundary(&self.text.byte_slice(..), self.head) {
@@ -100,132 +909,500 @@ impl Editor {
         self.update_desired_column();
     }
 
-    pub fn extend_up(&mut self, count: usize) {
+    pub(crate) fn extend_up(&mut self, count: usize) {
         debug_assert!(self.text.is_grapheme_boundary(self.head));
         for _ in 0..count {
             let current_line_index = self.text.line_of_byte(self.head);
             if current_line_index == 0 {
                 break;
             }
-            let target_line_index = current_line_index - 1;
-            let target_line_byte_index = self.text.byte_of_line(target_line_index);
-            let target_line_slice = self.text.line(target_line_index);
-            let mut target_line_prefix = 0;
-            let mut byte_offset = target_line_byte_index;
-            for grapheme in target_line_slice.graphemes() {
-                let grapheme_width = grapheme.as_ref().display_width();
-                if target_line_prefix + grapheme_width > self.desired_column {
-                    break;
-                }
-                target_line_prefix += grapheme_width;
-                byte_offset += grapheme.len();
-            }
-            self.head = byte_offset;
+            let target_line_index = fold::visible_line(&self.folds, current_line_index - 1);
+            self.head = self.byte_at_column(target_line_index, self.desired_column);
         }
     }
 
-    pub fn extend_down(&mut self, count: usize) {
+    pub(crate) fn extend_down(&mut self, count: usize) {
         debug_assert!(self.text.is_grapheme_boundary(self.head));
         for _ in 0..count {
             let current_line_index = self.text.line_of_byte(self.head);
-            let target_line_index = current_line_index + 1;
+            let target_line_index = fold::next_visible_line(&self.folds, current_line_index);
             if target_line_index >= self.text.line_len() {
                 self.head = self.text.byte_len();
                 break;
             }
-            let target_line_byte_index = self.text.byte_of_line(target_line_index);
-            let target_line_slice = self.text.line(target_line_index);
-            let mut target_line_prefix = 0;
-            let mut byte_offset = target_line_byte_index;
-            for grapheme in target_line_slice.graphemes() {
-                let grapheme_width = grapheme.as_ref().display_width();
-                if target_line_prefix + grapheme_width > self.desired_column {
-                    break;
-                }
-                target_line_prefix += grapheme_width;
-                byte_offset += grapheme.len();
-            }
-            self.head = byte_offset;
+            self.head = self.byte_at_column(target_line_index, self.desired_column);
         }
     }
 
-    pub fn extend_line_start(&mut self) {
-        debug_assert!(self.text.is_grapheme_boundary(self.head));
-        let line_index = self.text.line_of_byte(self.head);
-        let line_start_byte_index = self.text.byte_of_line(line_index);
-        self.head = line_start_byte_index;
-        if self.is_forward() {
-            self.extend_right(1);
+    // The offset in line `line_index` of the last grapheme that starts at or before display
+    // column `column`, or the line's end if it's shorter.
+    fn byte_at_column(&self, line_index: usize, column: usize) -> usize {
+        let mut prefix = 0;
+        let mut byte_offset = self.text.byte_of_line(line_index);
+        for grapheme in self.text.line(line_index).graphemes() {
+            let width = grapheme_width(grapheme.as_ref(), prefix, self.tab_width);
+            if prefix + width > column {
+                break;
+            }
+            prefix += width;
+            byte_offset += grapheme.len();
         }
-        self.update_desired_column();
+        byte_offset
     }
 
-    pub fn extend_line_end(&mut self) {
-        debug_assert!(self.text.is_grapheme_boundary(self.head));
-        if self.head >= self.text.byte_len() {
-            return;
-        }
-        let line_index = self.text.line_of_byte(self.head);
-        let line_start_byte_index = self.text.byte_of_line(line_index);
-        let line = self.text.line(line_index);
-        let line_end_byte_index = line_start_byte_index + line.byte_len();
-        self.head = line_end_byte_index;
-        if self.is_backward() {
-            self.extend_left(1);
+    /// Select the cursor's line, including its terminator. When whole lines are already selected,
+    /// extend the selection to the next line instead.
+    pub(crate) fn select_line(&mut self) {
+        let start = min(self.anchor, self.head);
+        let end = max(self.anchor, self.head);
+        let is_line_start =
+            |offset| self.text.byte_of_line(self.text.line_of_byte(offset)) == offset;
+        if start < end
+            && is_line_start(start)
+            && (end == self.text.byte_len() || is_line_start(end))
+        {
+            if end < self.text.byte_len() {
+                let lines = self.text.line_of_byte(start)..=self.text.line_of_byte(end);
+                self.select_lines(lines);
+            }
+        } else {
+            let line = self.text.line_of_byte(self.cursor());
+            self.select_lines(line..=line);
         }
-        self.update_desired_column();
     }
 
-    pub fn move_to(&mut self, byte_offset: usize) {
-        self.extend_to(byte_offset);
-        self.reduce();
-        self.extend_left(1);
-        self.flip_forward();
+    /// Move to the start of line `number`, counting from one. Numbers past the end go to the last
+    /// line.
+    pub(crate) fn goto_line(&mut self, number: usize) {
+        self.push_jump();
+        let line_index = min(
+            number.saturating_sub(1),
+            self.text.line_len().saturating_sub(1),
+        );
+        self.move_to(self.text.byte_of_line(line_index));
     }
 
-    pub fn move_left(&mut self, count: usize) {
-        self.extend_left(count);
-        self.reduce();
+    /// Move to the bracket matching the one under the cursor, skipping over nested pairs. Does
+    /// nothing if the cursor isn't on a bracket.
+    pub(crate) fn goto_matching_bracket(&mut self) {
+        let cursor = self.cursor();
+        let bracket = self.text.byte_slice(cursor..).bytes().next();
+        if bracket.and_then(bracket_pair).is_none() {
+            return;
+        }
+        if let Some(target) = self.matching_bracket(cursor, usize::MAX) {
+            self.push_jump();
+            self.move_to(target);
+        } else {
+            self.message = Some(Err(String::from("No matching bracket")));
+        }
     }
 
-    pub fn move_right(&mut self, count: usize) {
-        self.extend_right(count);
-        self.reduce();
+    /// The offset of the bracket matching the one at `byte_offset`, skipping over nested pairs and
+    /// looking at most `limit` bytes away. `None` if there isn't a bracket there, or it has no
+    /// match within the limit.
+    pub(crate) fn matching_bracket(&self, byte_offset: usize, limit: usize) -> Option<usize> {
+        let bracket = self.text.byte_slice(byte_offset..).bytes().next()?;
+        let (open, close) = bracket_pair(bracket)?;
+        // Brackets are ASCII, so scanning bytes can't match part of a wider character.
+        let mut depth = 0_usize;
+        let mut matches = |byte| {
+            if byte == bracket {
+                depth += 1;
+            } else if byte == open || byte == close {
+                depth -= 1;
+            }
+            depth == 0
+        };
+        if bracket == open {
+            self.text
+                .byte_slice(byte_offset..)
+                .bytes()
+                .take(limit.saturating_add(1))
+                .position(&mut matches)
+                .map(|index| byte_offset + index)
+        } else {
+            self.text
+                .byte_slice(..=byte_offset)
+                .bytes()
+                .rev()
+                .take(limit.saturating_add(1))
+                .position(&mut matches)
+                .map(|index| byte_offset - index)
+        }
     }
 
-    pub fn move_up(&mut self, count: usize) {
-        self.extend_up(count);
-        self.reduce();
+    /// Remember the cursor's position as mark `name`.
+    pub(crate) fn set_mark(&mut self, name: char) {
+        self.marks.insert(name, self.cursor());
     }
 
-    pub fn move_down(&mut self, count: usize) {
-        self.extend_down(count);
-        self.reduce();
+    /// Move to the position remembered as mark `name`.
+    pub(crate) fn goto_mark(&mut self, name: char) {
+        let Some(&offset) = self.marks.get(&name) else {
+            self.message = Some(Err(format!("Mark '{name}' isn't set")));
+            return;
+        };
+        self.push_jump();
+        self.move_to_offset(offset);
     }
 
-    pub fn move_line_start(&mut self) {
-        self.extend_line_start();
-        self.reduce();
+    // Move to `offset`, which might be past the end of the text or inside a grapheme if the text
+    // was reloaded without moving it.
+    fn move_to_offset(&mut self, offset: usize) {
+        let offset =
+            floor_grapheme_boundary(&self.text.byte_slice(..), min(offset, self.text.byte_len()));
+        self.move_to(offset);
     }
 
-    pub fn move_line_end(&mut self) {
-        self.extend_line_end();
-        self.reduce();
+    /// Remember the cursor's position before a big jump, forgetting any positions jumped back
+    /// from.
+    pub(crate) fn push_jump(&mut self) {
+        self.jumps.truncate(self.jump_index);
+        let cursor = self.cursor();
+        if self.jumps.last() != Some(&cursor) {
+            self.jumps.push(cursor);
+        }
+        if self.jumps.len() > JUMP_LIST_CAPACITY {
+            self.jumps.remove(0);
+        }
+        self.jump_index = self.jumps.len();
     }
 
-    pub fn command_mode_move_left(&mut self, count: usize) {
-        debug_assert!(self.mode == Mode::Command);
-        debug_assert!(self.command.is_grapheme_boundary(self.command_cursor));
-        for _ in 0..count {
-            match prev_grapheme_boundary(&self.command.byte_slice(..), self.command_cursor) {
-                Some(prev) if self.command_cursor != prev => self.command_cursor = prev,
-                _ => break,
-            }
+    /// Go back to the position before the last big jump.
+    pub(crate) fn jump_back(&mut self) {
+        if self.jump_index == self.jumps.len() {
+            // Remember where the jumping back started, so jumping forward can return here.
+            self.push_jump();
+            self.jump_index = self.jumps.len() - 1;
+        }
+        if self.jump_index == 0 {
+            return;
         }
+        self.jump_index -= 1;
+        self.move_to_offset(self.jumps[self.jump_index]);
     }
 
-    pub fn command_mode_move_right(&mut self, count: usize) {
-        debug_assert!(self.mode == Mode::Command);
+    /// Undo a `jump_back`.
+    pub(crate) fn jump_forward(&mut self) {
+        if self.jump_index + 1 >= self.jumps.len() {
+            return;
+        }
+        self.jump_index += 1;
+        self.move_to_offset(self.jumps[self.jump_index]);
+    }
+
+    /// Select the whole text, replacing any other selections.
+    pub(crate) fn select_all(&mut self) {
+        self.selections.clear();
+        self.anchor = 0;
+        self.head = self.text.byte_len();
+        self.update_desired_column();
+    }
+
+    /// Extend the selection to the start of the head's line. The anchor stays put, and the head
+    /// lands exactly on the line boundary.
+    pub(crate) fn extend_line_start(&mut self) {
+        debug_assert!(self.text.is_grapheme_boundary(self.head));
+        let line_index = self.text.line_of_byte(self.head);
+        self.head = self.text.byte_of_line(line_index);
+        self.update_desired_column();
+    }
+
+    /// Extend the selection to the first non-whitespace grapheme on the head's line, or to the line
+    /// start if the cursor is already there.
+    pub(crate) fn extend_smart_line_start(&mut self) {
+        debug_assert!(self.text.is_grapheme_boundary(self.head));
+        let line_index = self.text.line_of_byte(self.head);
+        let line_start = self.text.byte_of_line(line_index);
+        let indent = self
+            .text
+            .line(line_index)
+            .graphemes()
+            .take_while(|grapheme| grapheme.chars().all(char::is_whitespace))
+            .map(|grapheme| grapheme.len())
+            .sum::<usize>();
+        let first_non_whitespace = line_start + indent;
+        self.head = if self.cursor() == first_non_whitespace {
+            line_start
+        } else {
+            first_non_whitespace
+        };
+        self.update_desired_column();
+    }
+
+    /// Extend the selection to the end of the head's line, before its line terminator. The anchor
+    /// stays put, and the head lands exactly on the line boundary.
+    pub(crate) fn extend_line_end(&mut self) {
+        debug_assert!(self.text.is_grapheme_boundary(self.head));
+        if self.head >= self.text.byte_len() {
+            return;
+        }
+        let line_index = self.text.line_of_byte(self.head);
+        let line_start_byte_index = self.text.byte_of_line(line_index);
+        let line = self.text.line(line_index);
+        self.head = line_start_byte_index + line.byte_len();
+        self.update_desired_column();
+    }
+
+    pub(crate) fn move_to(&mut self, byte_offset: usize) {
+        self.extend_to(byte_offset);
+        self.reduce();
+        self.extend_left(1);
+        self.flip_forward();
+    }
+
+    pub(crate) fn move_left(&mut self, count: usize) {
+        self.extend_left(count);
+        self.reduce();
+    }
+
+    pub(crate) fn move_right(&mut self, count: usize) {
+        self.extend_right(count);
+        self.reduce();
+    }
+
+    pub(crate) fn move_up(&mut self, count: usize) {
+        self.extend_up(count);
+        self.reduce();
+    }
+
+    pub(crate) fn move_down(&mut self, count: usize) {
+        self.extend_down(count);
+        self.reduce();
+    }
+
+    pub(crate) fn move_line_start(&mut self) {
+        self.extend_line_start();
+        self.reduce();
+    }
+
+    pub(crate) fn move_smart_line_start(&mut self) {
+        self.extend_smart_line_start();
+        self.reduce();
+    }
+
+    pub(crate) fn move_line_end(&mut self) {
+        self.extend_line_end();
+        self.reduce();
+    }
+
+    /// Move to just after the grapheme under the cursor, where `a` appends. At EOF the cursor
+    /// stays put.
+    pub(crate) fn move_after_cursor(&mut self) {
+        self.head = next_grapheme_boundary(&self.text.byte_slice(..), self.cursor())
+            .unwrap_or(self.text.byte_len());
+        self.reduce();
+        self.update_desired_column();
+    }
+
+    /// Select from the cursor to the start of the next word, including the whitespace before it.
+    pub(crate) fn move_next_word_start(&mut self, count: usize) {
+        self.next_word_start(count, false);
+    }
+
+    /// Like `move_next_word_start`, but only whitespace separates words.
+    pub(crate) fn move_next_long_word_start(&mut self, count: usize) {
+        self.next_word_start(count, true);
+    }
+
+    /// Select from the cursor to the end of the next word, including the whitespace before it.
+    pub(crate) fn move_next_word_end(&mut self, count: usize) {
+        self.next_word_end(count, false);
+    }
+
+    /// Like `move_next_word_end`, but only whitespace separates words.
+    pub(crate) fn move_next_long_word_end(&mut self, count: usize) {
+        self.next_word_end(count, true);
+    }
+
+    /// Select backward from the cursor to the start of the previous word.
+    pub(crate) fn move_prev_word_start(&mut self, count: usize) {
+        self.prev_word_start(count, false);
+    }
+
+    /// Like `move_prev_word_start`, but only whitespace separates words.
+    pub(crate) fn move_prev_long_word_start(&mut self, count: usize) {
+        self.prev_word_start(count, true);
+    }
+
+    fn next_word_start(&mut self, count: usize, long: bool) {
+        for _ in 0..count {
+            let Some(begin) = self.word_begin_forward(long) else {
+                break;
+            };
+            let category = self.category(begin, long);
+            let end = self.skip_forward(begin, long, |c| Some(c) == category);
+            self.anchor = begin;
+            self.head = self.skip_forward(end, long, |c| c == Category::Whitespace);
+        }
+        self.update_desired_column();
+    }
+
+    fn next_word_end(&mut self, count: usize, long: bool) {
+        for _ in 0..count {
+            let Some(begin) = self.word_begin_forward(long) else {
+                break;
+            };
+            let mut end = self.skip_forward(begin, long, |c| c == Category::Whitespace);
+            if let Some(category @ (Category::Word | Category::Punctuation)) =
+                self.category(end, long)
+            {
+                end = self.skip_forward(end, long, |c| c == category);
+            }
+            self.anchor = begin;
+            self.head = end;
+        }
+        self.update_desired_column();
+    }
+
+    fn prev_word_start(&mut self, count: usize, long: bool) {
+        let text = self.text.byte_slice(..);
+        for _ in 0..count {
+            let cursor = self.cursor();
+            let Some(prev) = prev_grapheme_boundary(&text, cursor) else {
+                break;
+            };
+            let mut begin = cursor;
+            if self.category(cursor, long) != self.category(prev, long) {
+                begin = prev;
+            }
+            while self.category(begin, long) == Some(Category::LineBreak) {
+                match prev_grapheme_boundary(&text, begin) {
+                    Some(prev) => begin = prev,
+                    None => break,
+                }
+            }
+            let mut end = begin;
+            if self.category(end, long) == Some(Category::Whitespace) {
+                end = self.skip_backward(end, long, |c| c == Category::Whitespace);
+                if let Some(prev) = prev_grapheme_boundary(&text, end)
+                    && matches!(
+                        self.category(prev, long),
+                        Some(Category::Word | Category::Punctuation)
+                    )
+                {
+                    end = prev;
+                }
+            }
+            if let Some(category @ (Category::Word | Category::Punctuation)) =
+                self.category(end, long)
+            {
+                end = self.skip_backward(end, long, |c| c == category);
+            }
+            self.anchor = next_grapheme_boundary(&text, begin).unwrap_or(begin);
+            self.head = end;
+        }
+        self.update_desired_column();
+    }
+
+    // Where a forward word motion starts: the grapheme after the cursor if it starts a new word,
+    // skipping line breaks. `None` at the end of the text.
+    fn word_begin_forward(&self, long: bool) -> Option<usize> {
+        let cursor = self.cursor();
+        let next = next_grapheme_boundary(&self.text.byte_slice(..), cursor)?;
+        let begin = if self.category(cursor, long) == self.category(next, long) {
+            cursor
+        } else {
+            next
+        };
+        let begin = self.skip_forward(begin, long, |c| c == Category::LineBreak);
+        (begin < self.text.byte_len()).then_some(begin)
+    }
+
+    fn category(&self, byte_offset: usize, long: bool) -> Option<Category> {
+        let char = self.text.byte_slice(byte_offset..).chars().next()?;
+        Some(if char == '\n' || char == '\r' {
+            Category::LineBreak
+        } else if char.is_whitespace() {
+            Category::Whitespace
+        } else if long || char.is_alphanumeric() || char == '_' {
+            Category::Word
+        } else {
+            Category::Punctuation
+        })
+    }
+
+    // Advance past graphemes matching `predicate`, stopping at the first which doesn't.
+    fn skip_forward(
+        &self,
+        mut byte_offset: usize,
+        long: bool,
+        predicate: impl Fn(Category) -> bool,
+    ) -> usize {
+        while let Some(category) = self.category(byte_offset, long)
+            && predicate(category)
+            && let Some(next) = next_grapheme_boundary(&self.text.byte_slice(..), byte_offset)
+        {
+            byte_offset = next;
+        }
+        byte_offset
+    }
+
+    // Step back over preceding graphemes matching `predicate`, stopping on the last which does.
+    fn skip_backward(
+        &self,
+        mut byte_offset: usize,
+        long: bool,
+        predicate: impl Fn(Category) -> bool,
+    ) -> usize {
+        while let Some(prev) = prev_grapheme_boundary(&self.text.byte_slice(..), byte_offset)
+            && self.category(prev, long).is_some_and(&predicate)
+        {
+            byte_offset = prev;
+        }
+        byte_offset
+    }
+
+    pub(crate) fn move_next_hunk(&mut self) {
+        let hunk_lines = self.hunk_lines();
+        let current_line = self.text.line_of_byte(self.head);
+        let target_line = hunk_lines
+            .iter()
+            .find(|line| **line > current_line)
+            .or(hunk_lines.first());
+        if let Some(target_line) = target_line {
+            self.move_to(self.text.byte_of_line(*target_line));
+        } else {
+            self.message = Some(Err(String::from("No changes")));
+        }
+    }
+
+    pub(crate) fn move_prev_hunk(&mut self) {
+        let hunk_lines = self.hunk_lines();
+        let current_line = self.text.line_of_byte(self.head);
+        let target_line = hunk_lines
+            .iter()
+            .rfind(|line| **line < current_line)
+            .or(hunk_lines.last());
+        if let Some(target_line) = target_line {
+            self.move_to(self.text.byte_of_line(*target_line));
+        } else {
+            self.message = Some(Err(String::from("No changes")));
+        }
+    }
+
+    fn hunk_lines(&self) -> Vec<usize> {
+        let last_line = self.text.line_len().saturating_sub(1);
+        self.hunks()
+            .iter()
+            .map(|hunk| min(hunk.new.start, last_line))
+            .collect()
+    }
+
+    pub(crate) fn command_mode_move_left(&mut self, count: usize) {
+        debug_assert!(matches!(self.mode, Mode::Command | Mode::Search));
+        debug_assert!(self.command.is_grapheme_boundary(self.command_cursor));
+        for _ in 0..count {
+            match prev_grapheme_boundary(&self.command.byte_slice(..), self.command_cursor) {
+                Some(prev) if self.command_cursor != prev => self.command_cursor = prev,
+                _ => break,
+            }
+        }
+    }
+
+    pub(crate) fn command_mode_move_right(&mut self, count: usize) {
+        debug_assert!(matches!(self.mode, Mode::Command | Mode::Search));
         debug_assert!(self.command.is_grapheme_boundary(self.command_cursor));
         for _ in 0..count {
             match next_grapheme_boundary(&self.command.byte_slice(..), self.command_cursor) {
@@ -235,8 +1412,8 @@ impl Editor {
         }
     }
 
-    pub fn command_mode_delete_before(&mut self) {
-        debug_assert!(self.mode == Mode::Command);
+    pub(crate) fn command_mode_delete_before(&mut self) {
+        debug_assert!(matches!(self.mode, Mode::Command | Mode::Search));
         debug_assert!(self.command.is_grapheme_boundary(self.command_cursor));
         if self.command_cursor > 0 {
             self.command.delete(0..self.command_cursor);
@@ -244,8 +1421,8 @@ impl Editor {
         }
     }
 
-    pub fn command_mode_delete_after(&mut self) {
-        debug_assert!(self.mode == Mode::Command);
+    pub(crate) fn command_mode_delete_after(&mut self) {
+        debug_assert!(matches!(self.mode, Mode::Command | Mode::Search));
         debug_assert!(self.command.is_grapheme_boundary(self.command_cursor));
         let end = self.command.byte_len();
         if self.command_cursor < end {
@@ -253,208 +1430,4888 @@ impl Editor {
         }
     }
 
-    pub fn is_forward(&self) -> bool {
-        self.anchor <= self.head
+    /// Complete the command name before the command cursor, or the path after commands which take
+    /// one. Paths are resolved against the working directory. A unique match is filled in.
+    /// Otherwise the matches' common prefix is, and completing again fills in each match in turn.
+    pub(crate) fn complete(&mut self) {
+        debug_assert!(self.mode == Mode::Command);
+        if self.cycle_completion(true) {
+            return;
+        }
+        let before = self.command.byte_slice(..self.command_cursor).to_string();
+        let (prefix, mut matches) = match before.rsplit_once(' ') {
+            None => {
+                let matches = command_names()
+                    .into_iter()
+                    .filter(|name| name.starts_with(&before))
+                    .collect();
+                (before.as_str(), matches)
+            }
+            Some((command, partial)) if PATH_COMMANDS.contains(&command.trim()) => {
+                self.path_completions(partial)
+            }
+            Some(_) => return,
+        };
+        matches.sort();
+        matches.dedup();
+        let Some(first) = matches.first() else {
+            self.message = Some(Err(String::from("No matches")));
+            return;
+        };
+        let common = matches
+            .iter()
+            .fold(first.as_str(), |common, name| common_prefix(common, name));
+        let completion = &common[prefix.len()..];
+        self.command.insert(self.command_cursor, completion);
+        self.command_cursor += completion.len();
+        self.completion = (matches.len() > 1).then(|| Completion {
+            start: before.len() - prefix.len(),
+            end: self.command_cursor,
+            command: self.command.clone(),
+            candidates: matches,
+            index: None,
+        });
     }
 
-    pub fn is_backward(&self) -> bool {
-        !self.is_forward()
+    /// Fill in the previous completion match, like `complete` but backwards.
+    pub(crate) fn complete_prev(&mut self) {
+        debug_assert!(self.mode == Mode::Command);
+        if !self.cycle_completion(false) {
+            self.complete();
+            self.cycle_completion(false);
+        }
     }
 
-    pub fn flip(&mut self) {
-        mem::swap(&mut self.anchor, &mut self.head);
+    /// Stop cycling through completion matches, keeping the one filled in. Returns whether there
+    /// was one.
+    pub(crate) fn accept_completion(&mut self) -> bool {
+        if self
+            .completion()
+            .is_some_and(|completion| completion.index.is_some())
+        {
+            self.completion = None;
+            true
+        } else {
+            false
+        }
     }
 
-    pub fn flip_forward(&mut self) {
-        if !self.is_forward() {
-            self.flip();
-        }
+    /// The completion matches being cycled through, unless the command has changed since.
+    pub(crate) fn completion(&self) -> Option<&Completion> {
+        self.completion.as_ref().filter(|completion| {
+            self.mode == Mode::Command
+                && completion.command == self.command
+                && completion.end == self.command_cursor
+        })
     }
 
-    pub fn reduce(&mut self) {
-        self.anchor = self.head;
+    // Replace the text being completed with the next or previous match, returning whether there
+    // were matches to cycle through.
+    fn cycle_completion(&mut self, forward: bool) -> bool {
+        if self.completion().is_none() {
+            self.completion = None;
+            return false;
+        }
+        let Some(completion) = &mut self.completion else {
+            return false;
+        };
+        let len = completion.candidates.len();
+        let index = match (completion.index, forward) {
+            (None, true) => 0,
+            (None, false) => len - 1,
+            (Some(index), true) => (index + 1) % len,
+            (Some(index), false) => (index + len - 1) % len,
+        };
+        let candidate = &completion.candidates[index];
+        self.command
+            .replace(completion.start..completion.end, candidate);
+        completion.index = Some(index);
+        completion.end = completion.start + candidate.len();
+        completion.command = self.command.clone();
+        self.command_cursor = completion.end;
+        true
     }
 
-    pub fn scroll_up(&mut self, distance: usize) {
-        debug_assert!(self.vertical_scroll < self.text.line_len());
-        self.vertical_scroll = self.vertical_scroll.saturating_sub(distance);
+    // The name being completed in `partial`, and the entries in its directory which start with it.
+    // Directories end with a slash, so completion can continue into them.
+    fn path_completions<'a>(&self, partial: &'a str) -> (&'a str, Vec<String>) {
+        let (dir, prefix) = partial.split_at(partial.rfind('/').map_or(0, |slash| slash + 1));
+        let dir = Utf8Path::new(if dir.is_empty() { "." } else { dir });
+        let dir = match &self.pwd {
+            Some(pwd) => pwd.join(dir),
+            None => dir.to_path_buf(),
+        };
+        let matches = dir
+            .read_dir_utf8()
+            .into_iter()
+            .flatten()
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                let name = entry.file_name();
+                // Hidden files only show up when asked for.
+                name.starts_with(prefix) && (!name.starts_with('.') || prefix.starts_with('.'))
+            })
+            .map(|entry| {
+                if entry.path().is_dir() {
+                    format!("{}/", entry.file_name())
+                } else {
+                    entry.file_name().to_string()
+                }
+            })
+            .collect();
+        (prefix, matches)
     }
 
-    pub fn scroll_down(&mut self, distance: usize) {
-        debug_assert!(self.vertical_scroll < self.text.line_len());
-        self.vertical_scroll = min(
-            self.text.line_len().saturating_sub(1),
-            self.vertical_scroll + distance,
+    pub(crate) fn push_count(&mut self, digit: char) {
+        let digit = digit
+            .to_digit(10)
+            .expect("Count should only contain digits") as usize;
+        self.count = Some(
+            self.count
+                .unwrap_or(0)
+                .saturating_mul(10)
+                .saturating_add(digit),
         );
     }
 
-    pub fn insert(&mut self, text: &str) {
-        self.text.insert(self.head, text);
-        self.head += text.len();
-        self.update_desired_column();
-        self.reduce();
-        self.modified = true;
+    /// The start of the grapheme the cursor is drawn on, which is before `head` in a forward
+    /// selection.
+    pub(crate) fn cursor(&self) -> usize {
+        self.primary().cursor(&self.text)
     }
 
-    pub fn delete_before(&mut self) {
-        if let Some(grapheme) = self.text.byte_slice(..self.head).graphemes().next_back() {
-            let start = self.head - grapheme.len();
-            let end = self.head;
-            self.text.delete(start..end);
-            self.head = start;
-            self.reduce();
-            self.modified = true;
-            debug_assert!(self.text.is_grapheme_boundary(self.anchor));
-            debug_assert!(self.text.is_grapheme_boundary(self.head));
+    #[must_use]
+    pub fn primary(&self) -> Selection {
+        Selection {
+            anchor: self.anchor,
+            head: self.head,
+            desired_column: self.desired_column,
         }
     }
 
-    pub fn delete(&mut self) {
-        let start = min(self.anchor, self.head);
-        let end = max(self.anchor, self.head);
-        self.text.delete(start..end);
-        self.head = start;
-        self.anchor = start;
-        self.modified = true;
-        debug_assert!(self.text.is_grapheme_boundary(self.anchor));
-        debug_assert!(self.text.is_grapheme_boundary(self.head));
+    fn set_primary(&mut self, selection: Selection) {
+        self.anchor = selection.anchor;
+        self.head = selection.head;
+        self.desired_column = selection.desired_column;
     }
 
-    #[expect(dead_code)]
-    pub fn delete_after(&mut self) {
-        if let Some(grapheme) = self.text.byte_slice(self.head..).graphemes().next() {
-            let start = self.head;
-            let end = start + grapheme.len();
-            self.text.delete(start..end);
-            self.modified = true;
-            debug_assert!(self.text.is_grapheme_boundary(self.anchor));
-            debug_assert!(self.text.is_grapheme_boundary(self.head));
+    /// Run `f` once for every selection, each time with that selection as the primary one. Edits
+    /// shift the other selections to follow the text, and count as a single undo step. Selections
+    /// which overlap afterwards are merged.
+    pub(crate) fn for_each_selection(&mut self, mut f: impl FnMut(&mut Self)) {
+        if self.selections.is_empty() {
+            f(self);
+            return;
         }
+        self.history.begin_group();
+        // The primary selection goes last, so anything it leaves behind, like the register, wins.
+        self.selections.push(self.primary());
+        for index in 0..self.selections.len() {
+            self.set_primary(self.selections[index]);
+            f(self);
+            self.selections[index] = self.primary();
+        }
+        let primary = self.selections.pop().unwrap();
+        self.set_primary(primary);
+        self.history.end_group();
+        self.merge_selections();
     }
 
-    pub fn execute_command(&mut self) -> anyhow::Result<()> {
-        #[derive(clap::Parser)]
-        #[clap(
-            disable_help_flag = true,
-            disable_help_subcommand = true,
-            override_usage = ""
-        )]
-        enum Command {
-            Echo {
-                #[clap(long)]
-                error: bool,
-                message: Vec<String>,
-            },
-            #[clap(alias = "w")]
-            Write,
-            #[clap(alias = "q")]
-            Quit { exit_code: Option<u8> },
-            #[clap(name = "quit!", alias = "q!")]
-            QuitForce { exit_code: Option<u8> },
-            #[clap(name = "write-quit", alias = "wq")]
-            WriteQuit { exit_code: Option<u8> },
-        }
-        let Ok(args) = shellwords::split(&self.command.to_string()) else {
-            self.message = Some(Err(String::from("Invalid command")));
-            self.command = Rope::new();
-            self.command_cursor = 0;
-            self.mode = Mode::Normal;
-            return Ok(());
-        };
-        if args.is_empty() {
-            self.command = Rope::new();
-            self.command_cursor = 0;
-            self.mode = Mode::Normal;
-            return Ok(());
-        }
-        let args = iter::once(String::from("blue")).chain(args);
-        let command = match Command::try_parse_from(args) {
-            Ok(command) => command,
-            Err(error) => {
-                let error = error.to_string();
-                let error = error
-                    .strip_prefix("error: ")
-                    .unwrap_or(&error)
-                    .lines()
-                    .next()
-                    .unwrap_or("");
-                self.message = Some(Err(error.to_string()));
-                self.command = Rope::new();
-                self.command_cursor = 0;
-                self.mode = Mode::Normal;
-                return Ok(());
-            }
-        };
-        match command {
-            Command::Echo { error, message } => {
-                if error {
-                    self.message = Some(Err(message.join(" ")));
-                } else {
-                    self.message = Some(Ok(message.join(" ")));
-                }
-            }
-            Command::Write => {
-                self.save()?;
-            }
-            Command::Quit { exit_code } => {
-                if self.modified {
-                    self.message = Some(Err(String::from("Unsaved changes")));
-                } else {
-                    self.exit_code = if let Some(exit_code) = exit_code {
-                        Some(ExitCode::from(exit_code))
+    fn merge_selections(&mut self) {
+        let primary = self.primary();
+        let mut selections = mem::take(&mut self.selections);
+        selections.push(primary);
+        selections.sort_by_key(|selection| (selection.start(), selection.end()));
+        let mut merged: Vec<(Selection, bool)> = Vec::new();
+        for selection in selections {
+            let is_primary = selection == primary;
+            match merged.last_mut() {
+                Some((last, last_is_primary))
+                    if selection.start() < last.end() || selection == *last =>
+                {
+                    let (start, end) = (last.start(), max(last.end(), selection.end()));
+                    (last.anchor, last.head) = if last.is_forward() {
+                        (start, end)
                     } else {
-                        Some(ExitCode::SUCCESS)
+                        (end, start)
                     };
+                    *last_is_primary |= is_primary;
                 }
+                _ => merged.push((selection, is_primary)),
             }
-            Command::QuitForce { exit_code } => {
-                self.exit_code = if let Some(exit_code) = exit_code {
-                    Some(ExitCode::from(exit_code))
-                } else {
-                    Some(ExitCode::SUCCESS)
-                };
-            }
-            Command::WriteQuit { exit_code } => {
-                self.save()?;
-                self.exit_code = if let Some(exit_code) = exit_code {
-                    Some(ExitCode::from(exit_code))
-                } else {
-                    Some(ExitCode::SUCCESS)
-                };
+        }
+        for (selection, is_primary) in merged {
+            if is_primary {
+                self.set_primary(selection);
+            } else {
+                self.selections.push(selection);
             }
         }
-        self.command = Rope::new();
-        self.command_cursor = 0;
-        self.mode = Mode::Normal;
-        Ok(())
     }
-}
 
-impl TryFrom<Rope> for Editor {
-    type Error = anyhow::Error;
-    fn try_from(rope: Rope) -> Result<Self, Self::Error> {
-        Ok(Self {
-            pwd: None,
-            path: None,
-            modified: false,
-            text: rope,
+    /// Add a selection on the line below the last one, over the same columns, and make it the
+    /// primary selection.
+    pub(crate) fn copy_selection_below(&mut self) {
+        let last = self
+            .selections
+            .iter()
+            .copied()
+            .chain([self.primary()])
+            .max_by_key(Selection::end)
+            .unwrap();
+        let below = |offset: usize| {
+            let line_index = self.text.line_of_byte(offset);
+            let column = self
+                .text
+                .byte_slice(self.text.byte_of_line(line_index)..offset)
+                .display_width(self.tab_width);
+            let target_line_index = fold::next_visible_line(&self.folds, line_index);
+            if target_line_index < self.text.line_len() {
+                Some(self.byte_at_column(target_line_index, column))
+            } else if target_line_index == self.text.line_len() && column == 0 {
+                Some(self.text.byte_len())
+            } else {
+                None
+            }
+        };
+        let (Some(anchor), Some(head)) = (below(last.anchor), below(last.head)) else {
+            self.message = Some(Err(String::from("No line below")));
+            return;
+        };
+        self.selections.push(self.primary());
+        self.anchor = anchor;
+        self.head = head;
+        self.update_desired_column();
+        self.merge_selections();
+    }
+
+    /// Drop every selection but the primary one.
+    pub(crate) fn keep_primary_selection(&mut self) {
+        self.selections.clear();
+    }
+
+    /// Make the next selection in the text the primary one, or the previous one if not `forward`,
+    /// wrapping around at the ends.
+    pub(crate) fn rotate_selections(&mut self, forward: bool) {
+        if self.selections.is_empty() {
+            return;
+        }
+        let primary = self.primary();
+        let len = self.selections.len();
+        // Where the primary selection sits among the others.
+        let index = self
+            .selections
+            .partition_point(|selection| selection.start() < primary.start());
+        let next = if forward {
+            index % len
+        } else {
+            (index + len - 1) % len
+        };
+        let selection = mem::replace(&mut self.selections[next], primary);
+        self.set_primary(selection);
+        self.selections
+            .sort_by_key(|selection| (selection.start(), selection.end()));
+    }
+
+    /// Move the text of each selection into the next one, or the previous one if not `forward`,
+    /// with the last wrapping around to the first. The selections are fitted to their new text.
+    pub(crate) fn rotate_contents(&mut self, forward: bool) {
+        if !self.check_writable() || self.selections.is_empty() {
+            return;
+        }
+        let primary = self.primary();
+        let mut selections = mem::take(&mut self.selections);
+        selections.push(primary);
+        selections.sort_by_key(|selection| (selection.start(), selection.end()));
+        let primary_index = selections
+            .iter()
+            .position(|selection| *selection == primary)
+            .unwrap();
+        let texts = selections
+            .iter()
+            .map(|selection| {
+                self.text
+                    .byte_slice(selection.start()..selection.end())
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        let len = selections.len();
+        let texts = (0..len)
+            .map(|index| {
+                let from = if forward {
+                    (index + len - 1) % len
+                } else {
+                    (index + 1) % len
+                };
+                texts[from].as_str()
+            })
+            .collect::<Vec<_>>();
+        self.history.record(self.snapshot());
+        // From the end, so the selections before each edit are still where they were.
+        for (selection, text) in iter::zip(&selections, &texts).rev() {
+            self.apply_edit(selection.start()..selection.end(), text);
+        }
+        let (mut added, mut removed) = (0, 0);
+        for (selection, text) in iter::zip(&mut selections, &texts) {
+            let start = selection.start() + added - removed;
+            let end = start + text.len();
+            removed += selection.end() - selection.start();
+            added += text.len();
+            (selection.anchor, selection.head) = if selection.is_forward() {
+                (start, end)
+            } else {
+                (end, start)
+            };
+        }
+        self.set_primary(selections.remove(primary_index));
+        self.selections = selections;
+        self.modified = true;
+    }
+
+    pub(crate) fn is_forward(&self) -> bool {
+        self.anchor <= self.head
+    }
+
+    pub(crate) fn is_backward(&self) -> bool {
+        !self.is_forward()
+    }
+
+    pub(crate) fn flip(&mut self) {
+        mem::swap(&mut self.anchor, &mut self.head);
+    }
+
+    pub(crate) fn flip_forward(&mut self) {
+        if !self.is_forward() {
+            self.flip();
+        }
+    }
+
+    /// Collapse the selection onto its head. The desired column is left alone, so motions built
+    /// on this keep their column when moving up and down.
+    pub(crate) fn reduce(&mut self) {
+        self.anchor = self.head;
+    }
+
+    /// Collapse the selection onto its anchor, where it was started.
+    pub(crate) fn reduce_to_anchor(&mut self) {
+        self.head = self.anchor;
+        self.update_desired_column();
+    }
+
+    /// Scroll the least amount needed to show the cursor's line in a viewport `height` lines
+    /// tall, keeping `scrolloff` lines around it where possible. Closed folds count as one line.
+    pub(crate) fn scroll_to_head(&mut self, height: usize) {
+        let line = fold::visible_line(&self.folds, self.text.line_of_byte(self.cursor()));
+        let height = max(1, height);
+        let margin = min(self.scrolloff, (height - 1) / 2);
+        let top = self.visible_line_above(line, margin);
+        let bottom = self.visible_line_below(line, margin);
+        if top < self.vertical_scroll {
+            self.vertical_scroll = top;
+        } else if fold::visible_lines(&self.folds, self.vertical_scroll)
+            .take(height)
+            .all(|line| line < bottom)
+        {
+            self.vertical_scroll = self.visible_line_above(bottom, height - 1);
+        }
+    }
+
+    /// Scroll so the cursor's line is at the top of the viewport, below `scrolloff` lines.
+    pub(crate) fn scroll_cursor_top(&mut self, height: usize) {
+        let line = fold::visible_line(&self.folds, self.text.line_of_byte(self.cursor()));
+        let margin = min(self.scrolloff, (max(1, height) - 1) / 2);
+        self.vertical_scroll = self.visible_line_above(line, margin);
+    }
+
+    /// Scroll so the cursor's line is in the middle of a viewport `height` lines tall.
+    pub(crate) fn scroll_cursor_center(&mut self, height: usize) {
+        let line = fold::visible_line(&self.folds, self.text.line_of_byte(self.cursor()));
+        self.vertical_scroll = self.visible_line_above(line, height.saturating_sub(1) / 2);
+    }
+
+    /// Scroll so the cursor's line is at the bottom of a viewport `height` lines tall, above
+    /// `scrolloff` lines.
+    pub(crate) fn scroll_cursor_bottom(&mut self, height: usize) {
+        let line = fold::visible_line(&self.folds, self.text.line_of_byte(self.cursor()));
+        let height = max(1, height);
+        let margin = min(self.scrolloff, (height - 1) / 2);
+        let bottom = self.visible_line_below(line, margin);
+        self.vertical_scroll = self.visible_line_above(bottom, height - 1);
+    }
+
+    // The visible line `distance` lines above `line`, or the first line.
+    fn visible_line_above(&self, mut line: usize, distance: usize) -> usize {
+        for _ in 0..distance {
+            let Some(previous) = line.checked_sub(1) else {
+                break;
+            };
+            line = fold::visible_line(&self.folds, previous);
+        }
+        line
+    }
+
+    // The visible line `distance` lines below `line`, or the last line.
+    fn visible_line_below(&self, mut line: usize, distance: usize) -> usize {
+        for _ in 0..distance {
+            let next = fold::next_visible_line(&self.folds, line);
+            if next >= self.text.line_len() {
+                break;
+            }
+            line = next;
+        }
+        line
+    }
+
+    pub(crate) fn scroll_up(&mut self, distance: usize) {
+        debug_assert!(self.vertical_scroll < self.text.line_len());
+        self.vertical_scroll = self.vertical_scroll.saturating_sub(distance);
+    }
+
+    pub(crate) fn scroll_down(&mut self, distance: usize) {
+        debug_assert!(self.vertical_scroll < self.text.line_len());
+        self.vertical_scroll = min(
+            self.text.line_len().saturating_sub(1),
+            self.vertical_scroll + distance,
+        );
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            text: self.text.clone(),
+            anchor: self.anchor,
+            head: self.head,
+            desired_column: self.desired_column,
+        }
+    }
+
+    /// Replace the bytes in `range` with `text`, shifting the selections to follow the text
+    /// around them.
+    fn apply_edit(&mut self, range: Range<usize>, text: &str) {
+        let start_line = self.text.line_of_byte(range.start);
+        let old_end_line = self.text.line_of_byte(range.end);
+        self.text.replace(range.clone(), text);
+        self.mark_dirty(range.clone(), text.len());
+        *self.hunks.get_mut() = None;
+        let new_end = range.start + text.len();
+        let selections = self
+            .selections
+            .iter_mut()
+            .flat_map(|selection| [&mut selection.anchor, &mut selection.head]);
+        for offset in [&mut self.anchor, &mut self.head]
+            .into_iter()
+            .chain(selections)
+            .chain(self.marks.values_mut())
+            .chain(self.jumps.iter_mut())
+        {
+            if *offset > range.end {
+                *offset = *offset - range.end + new_end;
+            } else if *offset > range.start {
+                *offset = new_end;
+            }
+        }
+        self.scroll_for_edit(start_line, old_end_line, self.text.line_of_byte(new_end));
+    }
+
+    // Grow the dirty range to cover `range`, which was just replaced by `len` bytes, shifting the
+    // existing dirty range to follow the text it covered.
+    fn mark_dirty(&mut self, range: Range<usize>, len: usize) {
+        let new_end = range.start + len;
+        let shift = |offset: usize| {
+            if offset <= range.start {
+                offset
+            } else if offset >= range.end {
+                offset - range.end + new_end
+            } else {
+                range.start
+            }
+        };
+        let dirty = self.dirty.get_mut();
+        *dirty = Some(match dirty.take() {
+            None => range.start..new_end,
+            Some(dirty) => min(shift(dirty.start), range.start)..max(shift(dirty.end), new_end),
+        });
+    }
+
+    /// The byte range which has changed since the last call, so highlighting only needs to
+    /// reprocess that and the visible lines.
+    pub(crate) fn take_dirty(&self) -> Option<Range<usize>> {
+        self.dirty.take()
+    }
+
+    /// The highlighted spans of the line at `line_index`. Lines are only highlighted again once
+    /// they've been edited, and lines after an edit keep theirs as they move up or down.
+    pub(crate) fn highlights(&self, line_index: usize) -> Vec<Highlight> {
+        let mut highlights = self.highlights.borrow_mut();
+        if let Some(dirty) = self.take_dirty() {
+            let first = self.text.line_of_byte(dirty.start);
+            let last = self.text.line_of_byte(dirty.end);
+            let unchanged_after = self.text.line_len().saturating_sub(last + 1);
+            match highlights.len().checked_sub(unchanged_after) {
+                Some(end) if end >= first => {
+                    highlights.splice(first..end, iter::repeat_n(None, last + 1 - first));
+                }
+                _ => highlights.clear(),
+            }
+        }
+        highlights.resize(self.text.line_len(), None);
+        highlights[line_index]
+            .get_or_insert_with(|| {
+                highlight::highlight(&self.highlight_rules, self.text.line(line_index))
+            })
+            .clone()
+    }
+
+    // Keep the lines on screen where they were when an edit above the viewport adds or removes
+    // lines, so the cursor doesn't appear to jump. Folds move with their lines too.
+    fn scroll_for_edit(&mut self, start_line: usize, old_end_line: usize, new_end_line: usize) {
+        fold::shift(
+            &mut self.folds,
+            start_line,
+            old_end_line,
+            new_end_line,
+            self.text.line_len(),
+        );
+        if old_end_line < self.vertical_scroll {
+            self.vertical_scroll = self.vertical_scroll + new_end_line - old_end_line;
+        } else if start_line < self.vertical_scroll {
+            self.vertical_scroll = start_line;
+        }
+        self.vertical_scroll = min(self.vertical_scroll, self.text.line_len().saturating_sub(1));
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        let old = mem::replace(&mut self.text, snapshot.text);
+        *self.hunks.get_mut() = None;
+        let prefix = iter::zip(old.bytes(), self.text.bytes())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix = iter::zip(old.bytes().rev(), self.text.bytes().rev())
+            .take(min(old.byte_len(), self.text.byte_len()) - prefix)
+            .take_while(|(a, b)| a == b)
+            .count();
+        self.mark_dirty(
+            prefix..old.byte_len() - suffix,
+            self.text.byte_len() - suffix - prefix,
+        );
+        self.scroll_for_edit(
+            old.line_of_byte(prefix),
+            old.line_of_byte(old.byte_len() - suffix),
+            self.text.line_of_byte(self.text.byte_len() - suffix),
+        );
+        self.anchor = snapshot.anchor;
+        self.head = snapshot.head;
+        self.desired_column = snapshot.desired_column;
+        // Snapshots only hold the primary selection.
+        self.selections.clear();
+        self.modified = !self.history.is_saved();
+    }
+
+    /// Undo the next insert separately from the last, like after leaving Insert mode or moving.
+    pub(crate) fn break_insert(&mut self) {
+        self.history.break_insert();
+    }
+
+    /// Take the last `len` bytes back out of `inserting`, after deleting what was just typed.
+    pub(crate) fn uninsert(&mut self, len: usize) {
+        let mut len = self.inserting.len().saturating_sub(len);
+        while !self.inserting.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.inserting.truncate(len);
+    }
+
+    /// Go back to Normal mode, keeping what was typed in `inserted`.
+    pub(crate) fn leave_insert_mode(&mut self) {
+        self.break_insert();
+        self.inserted = mem::take(&mut self.inserting);
+        self.mode = Mode::Normal;
+    }
+
+    pub(crate) fn undo(&mut self) {
+        if !self.check_writable() {
+            return;
+        }
+        if let Some(snapshot) = self.history.undo(self.snapshot()) {
+            self.restore(snapshot);
+        } else {
+            self.message = Some(Err(String::from("Nothing to undo")));
+        }
+    }
+
+    pub(crate) fn redo(&mut self) {
+        if !self.check_writable() {
+            return;
+        }
+        if let Some(snapshot) = self.history.redo(self.snapshot()) {
+            self.restore(snapshot);
+        } else {
+            self.message = Some(Err(String::from("Nothing to redo")));
+        }
+    }
+
+    /// Whether the text can be changed. If not, that's shown in the status bar instead.
+    pub(crate) fn check_writable(&mut self) -> bool {
+        if self.read_only {
+            self.message = Some(Err(String::from("Buffer is read-only")));
+        }
+        !self.read_only
+    }
+
+    pub(crate) fn insert(&mut self, text: &str) {
+        if !self.check_writable() {
+            return;
+        }
+        if text.chars().count() == 1 {
+            self.history
+                .record_insert(self.snapshot(), self.head, self.head + text.len());
+        } else {
+            self.history.record(self.snapshot());
+        }
+        self.apply_edit(self.head..self.head, text);
+        self.head += text.len();
+        self.update_desired_column();
+        self.reduce();
+        self.modified = true;
+    }
+
+    /// Insert a typed character. With `auto_pairs`, an opening bracket or quote brings its closing
+    /// half along, and typing a closing half that's already next steps over it instead.
+    pub(crate) fn insert_char(&mut self, char: char) {
+        if self.auto_pairs {
+            let next = self.text.byte_slice(self.head..).graphemes().next();
+            if AUTO_PAIRS.iter().any(|(_, close)| *close == char)
+                && next.is_some_and(|next| next.chars().eq([char]))
+            {
+                self.head += char.len_utf8();
+                self.reduce();
+                self.update_desired_column();
+                return;
+            }
+            let prev = self.text.byte_slice(..self.head).chars().next_back();
+            if let Some((open, close)) = AUTO_PAIRS.iter().find(|(open, _)| *open == char)
+                // An apostrophe in a word like "don't" doesn't start a string.
+                && !(open == close && prev.is_some_and(char::is_alphanumeric))
+            {
+                self.insert(&format!("{open}{close}"));
+                self.head -= close.len_utf8();
+                self.reduce();
+                self.update_desired_column();
+                return;
+            }
+        }
+        self.insert(&char.to_string());
+    }
+
+    /// Insert an empty line below the cursor's line, and move to it.
+    pub(crate) fn open_below(&mut self) {
+        if !self.check_writable() {
+            return;
+        }
+        let line_index = self.text.line_of_byte(self.cursor());
+        self.head = if line_index < self.text.line_len() {
+            self.text.byte_of_line(line_index) + self.text.line(line_index).byte_len()
+        } else {
+            self.text.byte_len()
+        };
+        self.reduce();
+        self.insert("\n");
+    }
+
+    /// Insert an empty line above the cursor's line, and move to it.
+    pub(crate) fn open_above(&mut self) {
+        if !self.check_writable() {
+            return;
+        }
+        let line_index = self.text.line_of_byte(self.cursor());
+        let start = self.text.byte_of_line(line_index);
+        self.head = start;
+        self.reduce();
+        self.insert("\n");
+        self.head = start;
+        self.reduce();
+        self.update_desired_column();
+    }
+
+    /// Join the selected lines, or the cursor's line and the one below, replacing each line break
+    /// and the indentation after it with a space. The cursor ends up on the last join.
+    pub(crate) fn join_lines(&mut self) {
+        if !self.check_writable() {
+            return;
+        }
+        let first = self.text.line_of_byte(min(self.anchor, self.cursor()));
+        let last = self.text.line_of_byte(max(self.anchor, self.cursor()));
+        let last = min(max(last, first + 1), self.text.line_len().saturating_sub(1));
+        if first >= last {
+            return;
+        }
+        self.history.record(self.snapshot());
+        let mut join = self.head;
+        for _ in first..last {
+            join = self.text.byte_of_line(first) + self.text.line(first).byte_len();
+            let next = self.text.byte_of_line(first + 1);
+            let indent = self
+                .text
+                .line(first + 1)
+                .chars()
+                .take_while(|char| matches!(char, ' ' | '\t'))
+                .map(char::len_utf8)
+                .sum::<usize>();
+            self.apply_edit(join..next + indent, " ");
+        }
+        self.head = join;
+        self.reduce();
+        self.update_desired_column();
+        self.modified = true;
+    }
+
+    /// Comment out the selected lines, or uncomment them if they're all commented already. The
+    /// comment token goes after each line's indentation, and blank lines are left alone.
+    pub(crate) fn toggle_comment(&mut self) {
+        if !self.check_writable() {
+            return;
+        }
+        if self.text.line_len() == 0 {
+            return;
+        }
+        let first = self.text.line_of_byte(min(self.anchor, self.cursor()));
+        let last = self.text.line_of_byte(max(self.anchor, self.cursor()));
+        let last = min(last, self.text.line_len() - 1);
+        // The offset of each non-blank line's content, after its indentation.
+        let contents = (first..=last)
+            .filter_map(|line_index| {
+                let line = self.text.line(line_index);
+                let indent = line
+                    .chars()
+                    .take_while(|char| matches!(char, ' ' | '\t'))
+                    .map(char::len_utf8)
+                    .sum::<usize>();
+                (indent < line.byte_len()).then(|| self.text.byte_of_line(line_index) + indent)
+            })
+            .collect::<Vec<_>>();
+        if contents.is_empty() {
+            return;
+        }
+        let token = self.comment_token.clone();
+        let is_commented = |offset: usize| {
+            let end = min(offset + token.len(), self.text.byte_len());
+            self.text.byte_slice(offset..end) == token.as_str()
+        };
+        let uncomment = contents.iter().all(|offset| is_commented(*offset));
+        self.history.record(self.snapshot());
+        // Going from the bottom up keeps the offsets above each edit valid.
+        for offset in contents.into_iter().rev() {
+            if uncomment {
+                let mut end = offset + token.len();
+                if self.text.byte_slice(end..).chars().next() == Some(' ') {
+                    end += 1;
+                }
+                self.apply_edit(offset..end, "");
+            } else {
+                self.apply_edit(offset..offset, &format!("{token} "));
+            }
+        }
+        self.update_desired_column();
+        self.modified = true;
+    }
+
+    pub(crate) fn delete_before(&mut self) {
+        if !self.check_writable() {
+            return;
+        }
+        if let Some(grapheme) = self.text.byte_slice(..self.head).graphemes().next_back() {
+            let start = self.head - grapheme.len();
+            let mut end = self.head;
+            // Deleting the opening half of an empty pair deletes the closing half too.
+            if self.auto_pairs
+                && let Some(next) = self.text.byte_slice(self.head..).graphemes().next()
+                && AUTO_PAIRS
+                    .iter()
+                    .any(|(open, close)| grapheme.chars().eq([*open]) && next.chars().eq([*close]))
+            {
+                end += next.len();
+            }
+            self.history.record(self.snapshot());
+            self.apply_edit(start..end, "");
+            self.head = start;
+            self.reduce();
+            self.modified = true;
+            debug_assert!(self.text.is_grapheme_boundary(self.anchor));
+            debug_assert!(self.text.is_grapheme_boundary(self.head));
+        }
+    }
+
+    /// Delete the word before the head and any whitespace after it, like Ctrl-w in a shell. This
+    /// doesn't go past the start of the line.
+    pub(crate) fn delete_word_before(&mut self) {
+        if !self.check_writable() {
+            return;
+        }
+        let start = self.word_before_start();
+        if start == self.head {
+            return;
+        }
+        self.history.record(self.snapshot());
+        self.apply_edit(start..self.head, "");
+        self.head = start;
+        self.reduce();
+        self.update_desired_column();
+        self.modified = true;
+    }
+
+    /// Where `delete_word_before` deletes back to: the start of the word before the head, along
+    /// with any whitespace after it.
+    pub(crate) fn word_before_start(&self) -> usize {
+        let start = self.skip_backward(self.head, false, |c| c == Category::Whitespace);
+        if let Some(prev) = prev_grapheme_boundary(&self.text.byte_slice(..), start)
+            && let Some(category @ (Category::Word | Category::Punctuation)) =
+                self.category(prev, false)
+        {
+            return self.skip_backward(start, false, |c| c == category);
+        }
+        start
+    }
+
+    pub(crate) fn delete(&mut self) {
+        if !self.check_writable() {
+            return;
+        }
+        let start = min(self.anchor, self.head);
+        let end = max(self.anchor, self.head);
+        if start == end {
+            return;
+        }
+        self.yank();
+        self.history.record(self.snapshot());
+        self.apply_edit(start..end, "");
+        self.head = start;
+        self.anchor = start;
+        self.modified = true;
+        debug_assert!(self.text.is_grapheme_boundary(self.anchor));
+        debug_assert!(self.text.is_grapheme_boundary(self.head));
+    }
+
+    /// Delete from the cursor to the end of its line, keeping the line terminator.
+    pub(crate) fn delete_to_line_end(&mut self) {
+        let cursor = self.cursor();
+        let line_index = self.text.line_of_byte(cursor);
+        if line_index >= self.text.line_len() {
+            return;
+        }
+        let line_end = self.text.byte_of_line(line_index) + self.text.line(line_index).byte_len();
+        if cursor >= line_end {
+            return;
+        }
+        self.anchor = cursor;
+        self.head = line_end;
+        self.delete();
+        self.update_desired_column();
+    }
+
+    /// Copy the selection into the register.
+    pub(crate) fn yank(&mut self) {
+        let start = min(self.anchor, self.head);
+        let end = max(self.anchor, self.head);
+        self.register = self.text.byte_slice(start..end).to_string();
+        if self.clipboard == Clipboard::System {
+            self.pending_clipboard = Some(self.register.clone());
+        }
+    }
+
+    /// Insert the register after the selection, and select the inserted text.
+    pub(crate) fn paste_after(&mut self) {
+        self.paste(max(self.anchor, self.head));
+    }
+
+    /// Insert the register before the selection, and select the inserted text.
+    pub(crate) fn paste_before(&mut self) {
+        self.paste(min(self.anchor, self.head));
+    }
+
+    /// Insert the text typed in the last Insert session after the selection, like `paste_after`.
+    pub(crate) fn paste_inserted(&mut self) {
+        if self.inserted.is_empty() {
+            self.message = Some(Err(String::from("Nothing inserted yet")));
+            return;
+        }
+        let register = mem::replace(&mut self.register, self.inserted.clone());
+        self.paste_after();
+        self.register = register;
+    }
+
+    /// Insert the register after the selection like `paste_after`, replacing the common
+    /// indentation of the pasted lines with the indentation of the cursor's line.
+    pub(crate) fn paste_reindent(&mut self) {
+        let line = self
+            .text
+            .line(self.text.line_of_byte(self.cursor()))
+            .to_string();
+        let target = self.indent_columns(&line);
+        let offset = max(self.anchor, self.head);
+        // A paste in the middle of a line leaves its first line where it lands.
+        let skip_first = self.text.byte_of_line(self.text.line_of_byte(offset)) != offset;
+        let common = self
+            .register
+            .split_inclusive('\n')
+            .skip(usize::from(skip_first))
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| self.indent_columns(line))
+            .min()
+            .unwrap_or_default();
+        let mut reindented = String::with_capacity(self.register.len());
+        for (index, line) in self.register.split_inclusive('\n').enumerate() {
+            let rest = line.trim_start_matches([' ', '\t']);
+            if (index == 0 && skip_first) || rest.trim().is_empty() {
+                reindented.push_str(if index == 0 { line } else { rest });
+                continue;
+            }
+            let columns = target + self.indent_columns(line) - common;
+            if self.expand_tab {
+                reindented.push_str(&" ".repeat(columns));
+            } else {
+                reindented.push_str(&"\t".repeat(columns / self.tab_width));
+                reindented.push_str(&" ".repeat(columns % self.tab_width));
+            }
+            reindented.push_str(rest);
+        }
+        let register = mem::replace(&mut self.register, reindented);
+        self.paste(offset);
+        self.register = register;
+    }
+
+    /// The width in columns of the leading whitespace of `line`, with tabs stopping at multiples
+    /// of `tab_width`.
+    fn indent_columns(&self, line: &str) -> usize {
+        line.chars()
+            .map_while(|char| match char {
+                ' ' => Some(1),
+                '\t' => Some(0),
+                _ => None,
+            })
+            .fold(0, |columns, width| {
+                if width == 0 {
+                    columns + self.tab_width - columns % self.tab_width
+                } else {
+                    columns + width
+                }
+            })
+    }
+
+    fn paste(&mut self, byte_offset: usize) {
+        if !self.check_writable() {
+            return;
+        }
+        if self.register.is_empty() {
+            self.message = Some(Err(String::from("Register is empty")));
+            return;
+        }
+        self.history.record(self.snapshot());
+        let register = mem::take(&mut self.register);
+        self.apply_edit(byte_offset..byte_offset, &register);
+        self.anchor = byte_offset;
+        self.head = byte_offset + register.len();
+        self.register = register;
+        self.update_desired_column();
+        self.modified = true;
+    }
+
+    pub(crate) fn delete_after(&mut self) {
+        if !self.check_writable() {
+            return;
+        }
+        if let Some(grapheme) = self.text.byte_slice(self.head..).graphemes().next() {
+            let start = self.head;
+            let end = start + grapheme.len();
+            self.history.record(self.snapshot());
+            self.apply_edit(start..end, "");
+            self.modified = true;
+            debug_assert!(self.text.is_grapheme_boundary(self.anchor));
+            debug_assert!(self.text.is_grapheme_boundary(self.head));
+        }
+    }
+
+    /// Toggle the case of the selection, or of the grapheme under the cursor and then advance past
+    /// it.
+    pub(crate) fn toggle_case(&mut self) {
+        self.change_case(|char, new| {
+            if char.is_lowercase() {
+                new.extend(char.to_uppercase());
+            } else if char.is_uppercase() {
+                new.extend(char.to_lowercase());
+            } else {
+                new.push(char);
+            }
+        });
+    }
+
+    /// Lowercase the selection, like `toggle_case`.
+    pub(crate) fn lowercase(&mut self) {
+        self.change_case(|char, new| new.extend(char.to_lowercase()));
+    }
+
+    /// Uppercase the selection, like `toggle_case`.
+    pub(crate) fn uppercase(&mut self) {
+        self.change_case(|char, new| new.extend(char.to_uppercase()));
+    }
+
+    // Rewrite each char of the selection with `convert`. Case changes can change the length, like
+    // `ß` becoming `SS`, so the selection is rebuilt around the new text.
+    fn change_case(&mut self, convert: impl Fn(char, &mut String)) {
+        if !self.check_writable() {
+            return;
+        }
+        let start = min(self.anchor, self.head);
+        let end = if self.anchor == self.head {
+            match next_grapheme_boundary(&self.text.byte_slice(..), self.head) {
+                Some(next) => next,
+                None => return,
+            }
+        } else {
+            max(self.anchor, self.head)
+        };
+        let old = self.text.byte_slice(start..end).to_string();
+        let mut new = String::with_capacity(old.len());
+        for char in old.chars() {
+            convert(char, &mut new);
+        }
+        if new != old {
+            self.history.record(self.snapshot());
+            self.apply_edit(start..end, &new);
+            self.modified = true;
+        }
+        let new_end = start + new.len();
+        if self.anchor == self.head {
+            self.head = new_end;
+            self.reduce();
+        } else if self.is_forward() {
+            self.anchor = start;
+            self.head = new_end;
+        } else {
+            self.anchor = new_end;
+            self.head = start;
+        }
+        self.update_desired_column();
+    }
+
+    /// Extend the selection to the next `char` on the cursor's line, or the previous one when
+    /// going backward. With `till`, the cursor stops next to it instead of on it.
+    pub(crate) fn extend_find(&mut self, char: char, forward: bool, till: bool) {
+        let text = self.text.byte_slice(..);
+        let cursor = self.cursor();
+        let line_index = self.text.line_of_byte(cursor);
+        let line_start = self.text.byte_of_line(line_index);
+        let line_end = line_start + self.text.line(line_index).byte_len();
+        let is_char = |grapheme: &str| grapheme.chars().eq([char]);
+        let target = if forward {
+            let start =
+                next_grapheme_boundary(&text, cursor).map_or(line_end, |next| min(next, line_end));
+            let mut offset = start;
+            self.text
+                .byte_slice(start..line_end)
+                .graphemes()
+                .find_map(|grapheme| {
+                    let found = is_char(&grapheme).then_some(offset);
+                    offset += grapheme.len();
+                    found
+                })
+                .map(|found| {
+                    if till {
+                        prev_grapheme_boundary(&text, found).unwrap_or(found)
+                    } else {
+                        found
+                    }
+                })
+        } else {
+            let mut offset = line_start;
+            self.text
+                .byte_slice(line_start..cursor)
+                .graphemes()
+                .filter_map(|grapheme| {
+                    let found = is_char(&grapheme).then_some(offset);
+                    offset += grapheme.len();
+                    found
+                })
+                .last()
+                .map(|found| {
+                    if till {
+                        next_grapheme_boundary(&text, found).unwrap_or(found)
+                    } else {
+                        found
+                    }
+                })
+        };
+        let Some(target) = target else {
+            self.message = Some(Err(format!("No '{char}' on this line")));
+            return;
+        };
+        // Land the cursor on `target`, which is the grapheme before the head when going forward.
+        self.head = if target >= self.anchor {
+            next_grapheme_boundary(&text, target).unwrap_or(target)
+        } else {
+            target
+        };
+        self.update_desired_column();
+    }
+
+    /// Wrap the selection in the pair `char` belongs to, like `(` and `)`, or in `char` itself if
+    /// it isn't part of a pair. The selection grows to cover the delimiters.
+    pub(crate) fn surround(&mut self, char: char) {
+        if !self.check_writable() {
+            return;
+        }
+        let (open, close) = match char {
+            '(' | ')' => ('(', ')'),
+            '[' | ']' => ('[', ']'),
+            '{' | '}' => ('{', '}'),
+            '<' | '>' => ('<', '>'),
+            _ => (char, char),
+        };
+        let start = min(self.anchor, self.head);
+        let end = max(self.anchor, self.head);
+        self.history.record(self.snapshot());
+        self.apply_edit(end..end, &close.to_string());
+        self.apply_edit(start..start, &open.to_string());
+        let end = end + open.len_utf8() + close.len_utf8();
+        if self.is_backward() {
+            (self.anchor, self.head) = (end, start);
+        } else {
+            (self.anchor, self.head) = (start, end);
+        }
+        self.update_desired_column();
+        self.modified = true;
+    }
+
+    /// Replace every grapheme in the selection with `char`, or just the one under a collapsed
+    /// cursor. Line breaks are kept, so lines aren't joined.
+    pub(crate) fn replace_with(&mut self, char: char) {
+        if !self.check_writable() {
+            return;
+        }
+        let start = min(self.anchor, self.head);
+        let end = if self.anchor == self.head {
+            match next_grapheme_boundary(&self.text.byte_slice(..), self.head) {
+                Some(next) => next,
+                None => return,
+            }
+        } else {
+            max(self.anchor, self.head)
+        };
+        let new = self
+            .text
+            .byte_slice(start..end)
+            .graphemes()
+            .map(|grapheme| {
+                if grapheme == "\n" || grapheme == "\r\n" {
+                    grapheme.to_string()
+                } else {
+                    char.to_string()
+                }
+            })
+            .collect::<String>();
+        if self.text.byte_slice(start..end) != new {
+            self.history.record(self.snapshot());
+            self.apply_edit(start..end, &new);
+            self.modified = true;
+        }
+        let new_end = start + new.len();
+        if self.anchor == self.head {
+            self.head = start;
+            self.reduce();
+        } else if self.is_forward() {
+            self.anchor = start;
+            self.head = new_end;
+        } else {
+            self.anchor = new_end;
+            self.head = start;
+        }
+        self.update_desired_column();
+    }
+
+    /// Add `count` levels of indentation to the selected lines, skipping empty lines.
+    pub(crate) fn indent(&mut self, count: usize) {
+        if !self.check_writable() {
+            return;
+        }
+        let indent = self.indent_unit().repeat(count);
+        let snapshot = self.snapshot();
+        let mut changed = false;
+        for line_index in self.selected_lines().rev() {
+            if self.text.line(line_index).byte_len() == 0 {
+                continue;
+            }
+            let line_start = self.text.byte_of_line(line_index);
+            self.apply_edit(line_start..line_start, &indent);
+            changed = true;
+        }
+        if changed {
+            self.history.record(snapshot);
+            self.update_desired_column();
+            self.modified = true;
+        }
+    }
+
+    /// Remove up to `count` levels of indentation from the selected lines. A level is a tab, or up
+    /// to `indent_width` spaces.
+    pub(crate) fn dedent(&mut self, count: usize) {
+        if !self.check_writable() {
+            return;
+        }
+        let snapshot = self.snapshot();
+        let mut changed = false;
+        for line_index in self.selected_lines().rev() {
+            let line = self.text.line(line_index).to_string();
+            let mut len = 0;
+            for _ in 0..count {
+                let rest = &line[len..];
+                len += if rest.starts_with('\t') {
+                    1
+                } else {
+                    rest.bytes()
+                        .take(self.indent_width)
+                        .take_while(|byte| *byte == b' ')
+                        .count()
+                };
+            }
+            if len > 0 {
+                let line_start = self.text.byte_of_line(line_index);
+                self.apply_edit(line_start..line_start + len, "");
+                changed = true;
+            }
+        }
+        if changed {
+            self.history.record(snapshot);
+            self.update_desired_column();
+            self.modified = true;
+        }
+    }
+
+    /// Pad the selected lines with spaces so the first `delimiter` on each line starts in the same
+    /// display column. Lines without the delimiter are left alone.
+    pub(crate) fn align(&mut self, delimiter: &str) {
+        if !self.check_writable() {
+            return;
+        }
+        let mut columns = Vec::new();
+        for line_index in self.selected_lines() {
+            let line = self.text.line(line_index);
+            if let Some(byte_offset) = line.to_string().find(delimiter) {
+                let column = line.byte_slice(..byte_offset).display_width(self.tab_width);
+                let byte_offset = self.text.byte_of_line(line_index) + byte_offset;
+                columns.push((byte_offset, column));
+            }
+        }
+        let Some(target_column) = columns.iter().map(|(_, column)| *column).max() else {
+            return;
+        };
+        let snapshot = self.snapshot();
+        let mut changed = false;
+        for (byte_offset, column) in columns.into_iter().rev() {
+            let padding = target_column - column;
+            if padding > 0 {
+                self.apply_edit(byte_offset..byte_offset, &" ".repeat(padding));
+                changed = true;
+            }
+        }
+        if !changed {
+            return;
+        }
+        self.history.record(snapshot);
+        self.update_desired_column();
+        self.modified = true;
+    }
+
+    /// Sort the selected lines, in reverse with `reverse`. With `numeric`, lines are compared by
+    /// the integer they start with, and lines without one come first.
+    pub(crate) fn sort_lines(&mut self, reverse: bool, numeric: bool) {
+        if !self.check_writable() {
+            return;
+        }
+        let lines = self.selected_lines();
+        if lines.start() == lines.end() {
+            return;
+        }
+        let old = lines
+            .clone()
+            .map(|line_index| self.text.line(line_index).to_string())
+            .collect::<Vec<_>>();
+        let mut new = old.clone();
+        new.sort_by(|a, b| {
+            let ordering = if numeric {
+                leading_number(a).cmp(&leading_number(b))
+            } else {
+                a.cmp(b)
+            };
+            if reverse {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        if new == old {
+            return;
+        }
+        // The last line's terminator stays put, so a text without a final newline keeps it that way.
+        let start = self.text.byte_of_line(*lines.start());
+        let end = self.text.byte_of_line(*lines.end()) + self.text.line(*lines.end()).byte_len();
+        self.history.record(self.snapshot());
+        self.apply_edit(start..end, &new.join("\n"));
+        self.select_lines(lines);
+        self.modified = true;
+    }
+
+    /// Show how many lines, words, graphemes, and bytes there are in the selection, or in the whole
+    /// text when only the cursor is selected.
+    pub(crate) fn word_count(&mut self) {
+        let text = self.text.byte_slice(self.selection_or_all());
+        let (mut words, mut graphemes) = (0, 0);
+        let mut in_word = false;
+        for grapheme in text.graphemes() {
+            let is_whitespace = grapheme.chars().all(char::is_whitespace);
+            if !is_whitespace && !in_word {
+                words += 1;
+            }
+            in_word = !is_whitespace;
+            graphemes += 1;
+        }
+        let counts = [
+            (text.line_len(), "line"),
+            (words, "word"),
+            (graphemes, "character"),
+            (text.byte_len(), "byte"),
+        ];
+        let counts = counts
+            .map(|(count, noun)| {
+                let s = if count == 1 { "" } else { "s" };
+                format!("{count} {noun}{s}")
+            })
+            .join(", ");
+        self.message = Some(Ok(counts));
+    }
+
+    // The selected bytes, or the whole text when only the cursor is selected. Moving leaves a
+    // grapheme selected, so that counts as only the cursor too.
+    fn selection_or_all(&self) -> Range<usize> {
+        let start = min(self.anchor, self.head);
+        let end = max(self.anchor, self.head);
+        if self
+            .text
+            .byte_slice(start..end)
+            .graphemes()
+            .nth(1)
+            .is_some()
+        {
+            start..end
+        } else {
+            0..self.text.byte_len()
+        }
+    }
+
+    fn selected_lines(&self) -> RangeInclusive<usize> {
+        let start = min(self.anchor, self.head);
+        let end = max(self.anchor, self.head);
+        let start_line = self.text.line_of_byte(start);
+        let end_line = match prev_grapheme_boundary(&self.text.byte_slice(..), end) {
+            Some(last) if start < end => self.text.line_of_byte(last),
+            _ => start_line,
+        };
+        start_line..=min(end_line, self.text.line_len().saturating_sub(1))
+    }
+
+    /// Fold the selected lines into a closed fold, replacing any folds they overlap. The cursor
+    /// moves to the fold's summary line.
+    pub(crate) fn fold(&mut self) {
+        let lines = self.selected_lines();
+        let (start, end) = (*lines.start(), *lines.end());
+        self.folds
+            .retain(|fold| fold.end < start || fold.start > end);
+        let index = self.folds.partition_point(|fold| fold.start < start);
+        self.folds.insert(
+            index,
+            Fold {
+                start,
+                end,
+                closed: true,
+            },
+        );
+        self.move_to(self.text.byte_of_line(start));
+    }
+
+    pub(crate) fn open_fold(&mut self) {
+        self.set_fold(|_| false);
+    }
+
+    pub(crate) fn close_fold(&mut self) {
+        self.set_fold(|_| true);
+    }
+
+    pub(crate) fn toggle_fold(&mut self) {
+        self.set_fold(|closed| !closed);
+    }
+
+    pub(crate) fn delete_fold(&mut self) {
+        let line = self.text.line_of_byte(self.cursor());
+        let length = self.folds.len();
+        self.folds.retain(|fold| !fold.contains(line));
+        if self.folds.len() == length {
+            self.message = Some(Err(String::from("No fold")));
+        }
+    }
+
+    // Open or close the fold under the cursor. Closing it moves the cursor to its summary line, so
+    // the cursor isn't hidden.
+    fn set_fold(&mut self, closed: impl FnOnce(bool) -> bool) {
+        let line = self.text.line_of_byte(self.cursor());
+        let Some(fold) = self.folds.iter_mut().find(|fold| fold.contains(line)) else {
+            self.message = Some(Err(String::from("No fold")));
+            return;
+        };
+        fold.closed = closed(fold.closed);
+        if fold.closed && line != fold.start {
+            let start = fold.start;
+            self.move_to(self.text.byte_of_line(start));
+        }
+    }
+
+    /// Run `command` on every line matching `pattern`, with the whole line selected.
+    pub(crate) fn global(&mut self, pattern: &str, command: &str) -> anyhow::Result<()> {
+        let Ok(regex) = Regex::new(pattern) else {
+            self.message = Some(Err(format!("Invalid pattern '{pattern}'")));
+            return Ok(());
+        };
+        if command.trim().is_empty() {
+            self.message = Some(Err(String::from("Missing command")));
+            return Ok(());
+        }
+        let line_indices = (0..self.text.line_len())
+            .filter(|line_index| regex.is_match(&self.text.line(*line_index).to_string()))
+            .collect::<Vec<_>>();
+        if line_indices.is_empty() {
+            self.message = Some(Err(String::from("Pattern not found")));
+            return Ok(());
+        }
+        // Go in reverse so edits don't shift the lines which are still to be visited.
+        self.history.begin_group();
+        for line_index in line_indices.into_iter().rev() {
+            self.select_lines(line_index..=line_index);
+            let result = self.run_command(command);
+            if result.is_err() || self.exit_code.is_some() {
+                self.history.end_group();
+                return result;
+            }
+        }
+        self.history.end_group();
+        Ok(())
+    }
+
+    /// Select whole lines, including the last line's terminator.
+    fn select_lines(&mut self, lines: RangeInclusive<usize>) {
+        self.anchor = self.text.byte_of_line(*lines.start());
+        self.head = if lines.end() + 1 < self.text.line_len() {
+            self.text.byte_of_line(lines.end() + 1)
+        } else {
+            self.text.byte_len()
+        };
+        self.update_desired_column();
+    }
+
+    /// Run `command` with the shell, giving it the selection on stdin, and replace the selection
+    /// with what it prints. If it fails, its stderr is shown and the text is left alone.
+    pub(crate) fn pipe(&mut self, command: &str) {
+        if !self.check_writable() {
+            return;
+        }
+        let start = min(self.anchor, self.head);
+        let end = max(self.anchor, self.head);
+        let input = self.text.byte_slice(start..end).to_string();
+        let output = process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .and_then(|child| wait_with_timeout(child, input, self.shell_timeout));
+        let output = match output {
+            Ok(Some(output)) => output,
+            Ok(None) => {
+                self.message = Some(Err(format!(
+                    "Killed '{command}' after {}s",
+                    self.shell_timeout.as_secs_f64()
+                )));
+                return;
+            }
+            Err(error) => {
+                self.message = Some(Err(format!("Failed to run '{command}': {error}")));
+                return;
+            }
+        };
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let stderr = stderr.trim_end();
+            self.message = Some(Err(if stderr.is_empty() {
+                format!("'{command}' failed with {}", output.status)
+            } else {
+                stderr.to_string()
+            }));
+            return;
+        }
+        let Ok(output) = String::from_utf8(output.stdout) else {
+            self.message = Some(Err(format!("'{command}' printed invalid UTF-8")));
+            return;
+        };
+        self.history.record(self.snapshot());
+        self.apply_edit(start..end, &output);
+        self.anchor = start;
+        self.head = start + output.len();
+        self.update_desired_column();
+        self.modified = true;
+    }
+
+    /// Replace matches for the regex `pattern` within the selection, or the whole text if only the
+    /// cursor is selected. The replacement can refer to capture groups like `$1`. With `confirm`,
+    /// each match is selected in turn and Confirm mode asks whether to replace it.
+    pub(crate) fn substitute(&mut self, pattern: &str, replacement: &str, confirm: bool) {
+        if !self.check_writable() {
+            return;
+        }
+        let Ok(regex) = Regex::new(pattern) else {
+            self.message = Some(Err(format!("Invalid pattern '{pattern}'")));
+            return;
+        };
+        self.last_substitute = Some((pattern.to_string(), replacement.to_string(), confirm));
+        let Range { start, end } = self.selection_or_all();
+        if confirm {
+            self.history.begin_group();
+            self.confirm = Some(Confirm {
+                regex,
+                replacement: replacement.to_string(),
+                candidate: start..start,
+                end,
+                found: 0,
+                replaced: 0,
+            });
+            self.confirm_next(start);
+            return;
+        }
+        let text = self.text.to_string();
+        let mut edits = Vec::new();
+        let mut offset = start;
+        while let Some((range, expanded)) = next_match(&regex, replacement, &text, offset, end) {
+            offset = range.end;
+            edits.push((range, expanded));
+        }
+        if edits.is_empty() {
+            self.message = Some(Err(String::from("Pattern not found")));
+            return;
+        }
+        self.history.record(self.snapshot());
+        for (range, expanded) in edits.iter().rev() {
+            self.apply_edit(range.clone(), expanded);
+        }
+        self.update_desired_column();
+        self.modified = true;
+        self.message = Some(Ok(replaced_message(edits.len())));
+    }
+
+    // Select the next match for the substitution being confirmed, or finish it when there are no
+    // more.
+    fn confirm_next(&mut self, offset: usize) {
+        let Some(confirm) = &mut self.confirm else {
+            return;
+        };
+        let text = self.text.to_string();
+        match next_match(&confirm.regex, "", &text, offset, confirm.end) {
+            Some((range, _)) => {
+                confirm.candidate = range.clone();
+                confirm.found += 1;
+                self.select_match(range);
+                self.mode = Mode::Confirm;
+            }
+            None => self.stop_confirm(),
+        }
+    }
+
+    /// Replace the selected match and move on to the next one.
+    pub(crate) fn confirm_replace(&mut self) {
+        let Some(confirm) = &mut self.confirm else {
+            return;
+        };
+        let text = self.text.to_string();
+        let range = confirm.candidate.clone();
+        let Some((_, expanded)) = next_match(
+            &confirm.regex,
+            &confirm.replacement,
+            &text,
+            range.start,
+            range.end,
+        ) else {
+            self.stop_confirm();
+            return;
+        };
+        confirm.end = confirm.end - range.len() + expanded.len();
+        confirm.replaced += 1;
+        self.history.record(self.snapshot());
+        self.apply_edit(range.clone(), &expanded);
+        self.modified = true;
+        self.confirm_next(range.start + expanded.len());
+    }
+
+    /// Leave the selected match as it is and move on to the next one.
+    pub(crate) fn confirm_skip(&mut self) {
+        if let Some(confirm) = &self.confirm {
+            self.confirm_next(confirm.candidate.end);
+        }
+    }
+
+    /// Replace the selected match and all the rest without asking.
+    pub(crate) fn confirm_replace_all(&mut self) {
+        while self.confirm.is_some() {
+            self.confirm_replace();
+        }
+    }
+
+    /// Finish the substitution, leaving the remaining matches alone.
+    pub(crate) fn stop_confirm(&mut self) {
+        let Some(confirm) = self.confirm.take() else {
+            return;
+        };
+        self.history.end_group();
+        self.mode = Mode::Normal;
+        self.update_desired_column();
+        self.message = Some(if confirm.found == 0 {
+            Err(String::from("Pattern not found"))
+        } else {
+            Ok(replaced_message(confirm.replaced))
+        });
+    }
+
+    /// Repeat the last substitution on the selected lines, which is just the cursor's line unless
+    /// the selection spans more. It only asks for confirmation again with `keep_flags`.
+    pub(crate) fn repeat_substitute(&mut self, keep_flags: bool) {
+        let Some((pattern, replacement, confirm)) = self.last_substitute.clone() else {
+            self.message = Some(Err(String::from("No previous substitution")));
+            return;
+        };
+        self.select_lines(self.selected_lines());
+        self.substitute(&pattern, &replacement, confirm && keep_flags);
+    }
+
+    #[expect(clippy::too_many_lines)]
+    fn set_option(&mut self, option: &str, value: Option<&str>) -> Result<(), String> {
+        if value.is_none() {
+            if let Some(flag) = self.flag_option(option) {
+                *flag = true;
+                return Ok(());
+            }
+            if let Some(flag) = option
+                .strip_prefix("no")
+                .and_then(|option| self.flag_option(option))
+            {
+                *flag = false;
+                return Ok(());
+            }
+            if let Some(flag) = option
+                .strip_suffix('!')
+                .and_then(|option| self.flag_option(option))
+            {
+                *flag = !*flag;
+                return Ok(());
+            }
+        }
+        match option {
+            "eol-cursor" => match value {
+                None => {
+                    let eol_cursor = match self.eol_cursor {
+                        EolCursor::Block => "block",
+                        EolCursor::Newline => "newline",
+                    };
+                    self.message = Some(Ok(format!("eol-cursor {eol_cursor}")));
+                }
+                Some("block") => self.eol_cursor = EolCursor::Block,
+                Some("newline") => self.eol_cursor = EolCursor::Newline,
+                Some(value) => return Err(format!("Invalid value for '{option}': '{value}'")),
+            },
+            "clipboard" => match value {
+                None => {
+                    let clipboard = match self.clipboard {
+                        Clipboard::Internal => "internal",
+                        Clipboard::System => "system",
+                    };
+                    self.message = Some(Ok(format!("clipboard {clipboard}")));
+                }
+                Some("internal") => self.clipboard = Clipboard::Internal,
+                Some("system") => self.clipboard = Clipboard::System,
+                Some(value) => return Err(format!("Invalid value for '{option}': '{value}'")),
+            },
+            "filetype" => match value {
+                None => {
+                    let filetype = self.filetype.as_deref().unwrap_or("none");
+                    self.message = Some(Ok(format!("filetype {filetype}")));
+                }
+                Some(value) => self.set_filetype(Some(String::from(value))),
+            },
+            "guicursor" => match value {
+                None => self.message = Some(Ok(format!("guicursor {}", self.cursor_styles))),
+                Some(value) => {
+                    self.cursor_styles = self
+                        .cursor_styles
+                        .with(value)
+                        .ok_or_else(|| format!("Invalid value for '{option}': '{value}'"))?;
+                }
+            },
+            "indent-width" => match value {
+                None => {
+                    self.message = Some(Ok(format!("indent-width {}", self.indent_width)));
+                }
+                Some(value) => match value.parse() {
+                    Ok(width) if width > 0 => self.indent_width = width,
+                    _ => return Err(format!("Invalid value for '{option}': '{value}'")),
+                },
+            },
+            "comment-token" => match value {
+                None => self.message = Some(Ok(format!("comment-token {}", self.comment_token))),
+                Some("") => return Err(format!("Invalid value for '{option}': ''")),
+                Some(value) => self.comment_token = String::from(value),
+            },
+            "line-numbers" => match value {
+                None => {
+                    let line_numbers = match self.line_numbers {
+                        LineNumbers::Absolute => "absolute",
+                        LineNumbers::Relative => "relative",
+                    };
+                    self.message = Some(Ok(format!("line-numbers {line_numbers}")));
+                }
+                Some("absolute") => self.line_numbers = LineNumbers::Absolute,
+                Some("relative") => self.line_numbers = LineNumbers::Relative,
+                Some(value) => return Err(format!("Invalid value for '{option}': '{value}'")),
+            },
+            "theme" => match value {
+                None => self.message = Some(Ok(String::from("theme light"))),
+                Some("light") => self.theme = Theme::Light,
+                Some(value) => return Err(format!("Invalid value for '{option}': '{value}'")),
+            },
+            "fileformat" => match value {
+                None => {
+                    let fileformat = match self.line_ending {
+                        LineEnding::Lf => "unix",
+                        LineEnding::Crlf => "dos",
+                    };
+                    self.message = Some(Ok(format!("fileformat {fileformat}")));
+                }
+                Some("unix") => self.line_ending = LineEnding::Lf,
+                Some("dos") => self.line_ending = LineEnding::Crlf,
+                Some(value) => return Err(format!("Invalid value for '{option}': '{value}'")),
+            },
+            "tab-width" => match value {
+                None => self.message = Some(Ok(format!("tab-width {}", self.tab_width))),
+                Some(value) => match value.parse() {
+                    Ok(width) if width > 0 => self.tab_width = width,
+                    _ => return Err(format!("Invalid value for '{option}': '{value}'")),
+                },
+            },
+            "shell-timeout" => match value {
+                None => {
+                    let seconds = self.shell_timeout.as_secs_f64();
+                    self.message = Some(Ok(format!("shell-timeout {seconds}")));
+                }
+                Some(value) => match value.parse().map(Duration::try_from_secs_f64) {
+                    Ok(Ok(timeout)) => self.shell_timeout = timeout,
+                    _ => return Err(format!("Invalid value for '{option}': '{value}'")),
+                },
+            },
+            "scrolloff" => match value {
+                None => self.message = Some(Ok(format!("scrolloff {}", self.scrolloff))),
+                Some(value) => match value.parse() {
+                    Ok(scrolloff) => self.scrolloff = scrolloff,
+                    Err(_) => return Err(format!("Invalid value for '{option}': '{value}'")),
+                },
+            },
+            _ => return Err(format!("Unknown option '{option}'")),
+        }
+        Ok(())
+    }
+
+    fn flag_option(&mut self, option: &str) -> Option<&mut bool> {
+        match option {
+            "expandtab" => Some(&mut self.expand_tab),
+            "relative-path" => Some(&mut self.relative_path),
+            "trim-trailing-whitespace" => Some(&mut self.trim_trailing_whitespace),
+            "final-newline" => Some(&mut self.final_newline),
+            "search-in-selection" => Some(&mut self.search_in_selection),
+            "ignore-case" => Some(&mut self.ignore_case),
+            "smart-case" => Some(&mut self.smart_case),
+            "whole-word" => Some(&mut self.whole_word),
+            "list" => Some(&mut self.list),
+            "auto-pairs" => Some(&mut self.auto_pairs),
+            "readonly" => Some(&mut self.read_only),
+            "undofile" => Some(&mut self.undofile),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn execute_command(&mut self) -> anyhow::Result<()> {
+        let command = self.command.to_string();
+        self.command = Rope::new();
+        self.command_cursor = 0;
+        self.mode = Mode::Normal;
+        let current_line = self.text.line_of_byte(self.cursor());
+        let rest = match split_range(&command, current_line, self.text.line_len()) {
+            Ok(Some((_, rest))) => rest,
+            _ => &command,
+        };
+        if let Some(shell_command) = shell_command(rest).filter(|command| !command.is_empty()) {
+            self.message = Some(Ok(format!("Running: {shell_command}")));
+            self.pending_command = Some(command);
+            return Ok(());
+        }
+        self.run_command(&command)
+    }
+
+    /// Enter Search mode. Matches are selected as the query is typed, so the selection and scroll
+    /// are remembered to search from, and to go back to if the search is cancelled.
+    pub(crate) fn start_search(&mut self) {
+        self.command = Rope::new();
+        self.command_cursor = 0;
+        self.mode = Mode::Search;
+        self.search_origin = Some((self.primary(), self.vertical_scroll));
+    }
+
+    /// Select the next match for the query typed so far, searching from where the search started.
+    pub(crate) fn update_search(&mut self) {
+        debug_assert!(self.mode == Mode::Search);
+        self.restore_search_origin();
+        let query = self.command.to_string();
+        if query.is_empty() {
+            return;
+        }
+        self.search_range = (self.search_in_selection && self.anchor != self.head)
+            .then(|| min(self.anchor, self.head)..max(self.anchor, self.head));
+        if let Some(range) = self.find_forward(&query) {
+            self.select_match(range);
+        }
+    }
+
+    /// Leave Search mode without searching, going back to where the search started.
+    pub(crate) fn cancel_search(&mut self) {
+        self.restore_search_origin();
+        self.search_origin = None;
+        self.command = Rope::new();
+        self.command_cursor = 0;
+        self.mode = Mode::Normal;
+    }
+
+    fn restore_search_origin(&mut self) {
+        if let Some((selection, vertical_scroll)) = self.search_origin {
+            self.anchor = selection.anchor;
+            self.head = selection.head;
+            self.desired_column = selection.desired_column;
+            self.vertical_scroll = vertical_scroll;
+        }
+    }
+
+    /// Select the next match for the query in the command line, searching forward from the cursor
+    /// and wrapping around at the end of the text.
+    pub(crate) fn execute_search(&mut self) {
+        self.restore_search_origin();
+        self.search_origin = None;
+        let query = self.command.to_string();
+        self.command = Rope::new();
+        self.command_cursor = 0;
+        self.mode = Mode::Normal;
+        if query.is_empty() {
+            return;
+        }
+        self.search_range = (self.search_in_selection && self.anchor != self.head)
+            .then(|| min(self.anchor, self.head)..max(self.anchor, self.head));
+        self.search_forward(&query);
+        self.last_search = Some(query);
+    }
+
+    /// Select the next match for the last search.
+    pub(crate) fn search_next(&mut self) {
+        match self.last_search.clone() {
+            Some(query) => self.search_forward(&query),
+            None => self.message = Some(Err(String::from("No previous search"))),
+        }
+    }
+
+    /// Select the previous match for the last search.
+    pub(crate) fn search_prev(&mut self) {
+        match self.last_search.clone() {
+            Some(query) => self.search_backward(&query),
+            None => self.message = Some(Err(String::from("No previous search"))),
+        }
+    }
+
+    // The byte range searches wrap around in, which is the selection the search started from with
+    // `search-in-selection` set, or else the whole text.
+    fn search_bounds(&self) -> Range<usize> {
+        let text = self.text.byte_slice(..);
+        match &self.search_range {
+            Some(range) if self.search_in_selection => {
+                let end = floor_grapheme_boundary(&text, min(range.end, text.byte_len()));
+                floor_grapheme_boundary(&text, min(range.start, end))..end
+            }
+            _ => 0..text.byte_len(),
+        }
+    }
+
+    fn search_forward(&mut self, query: &str) {
+        let Some(range) = self.find_forward(query) else {
+            self.message = Some(Err(String::from("Pattern not found")));
+            return;
+        };
+        self.push_jump();
+        self.select_match(range);
+    }
+
+    // The next match for `query` starting after the cursor, wrapping around.
+    fn find_forward(&self, query: &str) -> Option<Range<usize>> {
+        let text = self.text.to_string();
+        let bounds = self.search_bounds();
+        let regex = self.search_regex(query);
+        let from = next_grapheme_boundary(&self.text.byte_slice(..), self.cursor())
+            .unwrap_or(text.len())
+            .clamp(bounds.start, bounds.end);
+        regex
+            .find_at(&text[..bounds.end], from)
+            .or_else(|| regex.find_at(&text[..bounds.end], bounds.start))
+            .map(|found| found.range())
+    }
+
+    // Every match for `query` within the search bounds, in order.
+    fn search_matches(&self, query: &str) -> Vec<Range<usize>> {
+        let text = self.text.to_string();
+        let bounds = self.search_bounds();
+        let regex = self.search_regex(query);
+        let mut matches = Vec::new();
+        let mut from = bounds.start;
+        while let Some(found) = regex.find_at(&text[..bounds.end], from)
+            && !found.is_empty()
+        {
+            matches.push(found.range());
+            from = found.end();
+        }
+        matches
+    }
+
+    /// The regex a search for `query` matches with, following `ignore-case`, `smart-case`, and
+    /// `whole-word`.
+    pub(crate) fn search_regex(&self, query: &str) -> Regex {
+        let ignore_case =
+            self.ignore_case || (self.smart_case && !query.chars().any(char::is_uppercase));
+        let is_word =
+            |char: Option<char>| char.is_some_and(|char| char.is_alphanumeric() || char == '_');
+        let mut pattern = regex::escape(query);
+        if self.whole_word && is_word(query.chars().next()) {
+            pattern.insert_str(0, r"\b");
+        }
+        if self.whole_word && is_word(query.chars().next_back()) {
+            pattern.push_str(r"\b");
+        }
+        RegexBuilder::new(&pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .expect("Escaped query should be a valid regex")
+    }
+
+    fn search_backward(&mut self, query: &str) {
+        let before = min(self.anchor, self.head);
+        let matches = self.search_matches(query);
+        let Some(last) = matches.last() else {
+            self.message = Some(Err(String::from("Pattern not found")));
+            return;
+        };
+        let range = matches
+            .iter()
+            .rfind(|range| range.start < before)
+            .unwrap_or(last)
+            .clone();
+        self.push_jump();
+        self.select_match(range);
+    }
+
+    fn select_match(&mut self, range: Range<usize>) {
+        let text = self.text.byte_slice(..);
+        self.anchor = floor_grapheme_boundary(&text, range.start);
+        self.head = ceil_grapheme_boundary(&text, range.end);
+        self.update_desired_column();
+    }
+
+    /// Run startup commands in order. Failures are reported in the status bar, but don't stop
+    /// later commands from running.
+    pub fn run_commands(&mut self, commands: &[String]) {
+        let mut first_error = None;
+        for command in commands {
+            if let Err(error) = self.run_command(command) {
+                self.message = Some(Err(error.to_string()));
+            }
+            if first_error.is_none()
+                && let Some(Err(error)) = &self.message
+            {
+                first_error = Some(error.clone());
+            }
+        }
+        if let Some(error) = first_error {
+            self.message = Some(Err(error));
+        }
+    }
+
+    /// Run an Ex-style command line, like `%s/a/b` or `write`. Invalid commands are reported in
+    /// the status bar rather than returned.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the command does, like `write` failing to save the file.
+    #[expect(clippy::too_many_lines)]
+    pub fn run_command(&mut self, command: &str) -> anyhow::Result<()> {
+        // A bare line number jumps there, rather than selecting it like other ranges.
+        if !command.is_empty() && command.bytes().all(|byte| byte.is_ascii_digit()) {
+            self.goto_line(command.parse().unwrap_or(usize::MAX));
+            return Ok(());
+        }
+        let current_line = self.text.line_of_byte(self.cursor());
+        let command = match split_range(command, current_line, self.text.line_len()) {
+            Ok(Some((lines, command))) => {
+                self.select_lines(lines);
+                command
+            }
+            Ok(None) => command,
+            Err(error) => {
+                self.message = Some(Err(error));
+                return Ok(());
+            }
+        };
+        if let Some((pattern, command)) = split_global(command) {
+            return self.global(&pattern, command);
+        }
+        if let Some(shell_command) = shell_command(command) {
+            match shell_command {
+                "" => self.message = Some(Err(String::from("Missing shell command"))),
+                shell_command => self.pipe(shell_command),
+            }
+            return Ok(());
+        }
+        let Ok(args) = shellwords::split(command) else {
+            self.message = Some(Err(String::from("Invalid command")));
+            return Ok(());
+        };
+        if args.is_empty() {
+            return Ok(());
+        }
+        let args = iter::once(String::from("blue")).chain(args);
+        let command = match Command::try_parse_from(args) {
+            Ok(command) => command,
+            Err(error) => {
+                let error = error.to_string();
+                let error = error
+                    .strip_prefix("error: ")
+                    .unwrap_or(&error)
+                    .lines()
+                    .next()
+                    .unwrap_or("");
+                self.message = Some(Err(error.to_string()));
+                return Ok(());
+            }
+        };
+        match command {
+            Command::Align { delimiter } => self.align(&delimiter),
+            Command::Delete => self.delete(),
+            Command::Buffers => self.list_buffers(),
+            Command::BufferNext => self.buffer_next(),
+            Command::BufferPrev => self.buffer_prev(),
+            Command::Edit { path } => self.edit(&path, false),
+            Command::EditForce { path: Some(path) } => self.edit(&path, true),
+            Command::EditForce { path: None } | Command::Reload => self.reload(),
+            Command::Echo { error, message } => {
+                if error {
+                    self.message = Some(Err(message.join(" ")));
+                } else {
+                    self.message = Some(Ok(message.join(" ")));
+                }
+            }
+            Command::Fold => self.fold(),
+            Command::Global { pattern, command } => {
+                let command = command.iter().map(String::as_str).collect::<Vec<_>>();
+                self.global(&pattern, &shellwords::join(&command))?;
+            }
+            Command::Highlight { color, pattern } => {
+                let Ok(color) = color.parse() else {
+                    self.message = Some(Err(format!("Invalid color '{color}'")));
+                    return Ok(());
+                };
+                match Regex::new(&pattern) {
+                    Ok(regex) => {
+                        self.highlight_rules.push(Rule { regex, color });
+                        self.highlights.get_mut().clear();
+                    }
+                    Err(error) => self.message = Some(Err(format!("Invalid pattern: {error}"))),
+                }
+            }
+            Command::Sort { reverse, numeric } => self.sort_lines(reverse, numeric),
+            Command::Wc => self.word_count(),
+            Command::Set { option, value } => {
+                if let Err(error) = self.set_option(&option, value.as_deref()) {
+                    self.message = Some(Err(error));
+                }
+            }
+            Command::Mksession { path } => {
+                let path = path.unwrap_or_else(|| Utf8PathBuf::from(session::DEFAULT_PATH));
+                let path = match &self.pwd {
+                    Some(pwd) => pwd.join(path),
+                    None => path,
+                };
+                self.message = Some(match self.session().save(&path) {
+                    Ok(()) => Ok(format!("Saved session to '{path}'")),
+                    Err(error) => Err(format!("Failed to write '{path}': {error}")),
+                });
+            }
+            Command::Substitute {
+                confirm,
+                pattern,
+                replacement,
+            } => self.substitute(&pattern, &replacement, confirm),
+            Command::RepeatSubstitute => self.repeat_substitute(false),
+            Command::RepeatSubstituteWithFlags => self.repeat_substitute(true),
+            Command::Yank => self.yank(),
+            Command::Write { .. } | Command::WriteQuit { .. } if self.read_only => {
+                self.check_writable();
+            }
+            Command::Write { path: Some(path) } => self.save_as(&path),
+            Command::Write { path: None } => {
+                self.write();
+            }
+            Command::Quit { exit_code } => {
+                if self.modified {
+                    self.message = Some(Err(String::from("Unsaved changes")));
+                } else {
+                    self.quit(exit_code);
+                }
+            }
+            Command::QuitForce { exit_code } => self.quit(exit_code),
+            Command::QuitAll { exit_code } => self.quit_all(exit_code, false),
+            Command::QuitAllForce { exit_code } => self.quit_all(exit_code, true),
+            Command::Cquit { exit_code } => {
+                self.exit_code = Some(ExitCode::from(exit_code.unwrap_or(1)));
+            }
+            Command::WriteQuit { exit_code } => {
+                if self.write() {
+                    self.quit(exit_code);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<&str> for Editor {
+    type Error = anyhow::Error;
+    fn try_from(text: &str) -> Result<Self, Self::Error> {
+        Self::try_from(Rope::from(text))
+    }
+}
+
+impl TryFrom<Rope> for Editor {
+    type Error = anyhow::Error;
+    fn try_from(rope: Rope) -> Result<Self, Self::Error> {
+        Ok(Self {
+            pwd: None,
+            path: None,
+            filetype: None,
+            highlight_rules: Vec::new(),
+            modified: false,
+            baseline: rope.clone(),
+            hunks: RefCell::new(None),
+            dirty: Cell::new(None),
+            highlights: RefCell::new(Vec::new()),
+            text: rope,
             anchor: 0,
             head: 0,
             desired_column: 0,
+            selections: Vec::new(),
             vertical_scroll: 0,
+            folds: Vec::new(),
+            marks: HashMap::new(),
+            jumps: Vec::new(),
+            jump_index: 0,
+            scrolloff: 3,
+            history: History::default(),
+            register: String::new(),
+            inserting: String::new(),
+            inserted: String::new(),
+            clipboard: Clipboard::Internal,
+            pending_clipboard: None,
+            pending_command: None,
+            shell_timeout: Duration::from_secs(30),
+            relative_path: true,
+            eol_cursor: EolCursor::Block,
+            cursor_styles: CursorStyles::default(),
+            expand_tab: false,
+            indent_width: 4,
+            trim_trailing_whitespace: false,
+            auto_pairs: false,
+            read_only: false,
+            undofile: false,
+            undo_dir: default_undo_dir(),
+            final_newline: false,
+            line_ending: LineEnding::Lf,
+            tab_width: 8,
+            line_numbers: LineNumbers::Absolute,
+            theme: Theme::Light,
+            list: false,
+            list_chars: ListChars::default(),
+            config: Config::default(),
+            comment_token: String::from("//"),
             mode: Mode::Normal,
+            count: None,
             command: Rope::new(),
             command_cursor: 0,
+            completion: None,
+            last_search: None,
+            last_substitute: None,
+            confirm: None,
+            search_in_selection: false,
+            ignore_case: false,
+            smart_case: true,
+            whole_word: false,
+            search_range: None,
+            search_origin: None,
             message: None,
             exit_code: None,
+            buffers: Vec::new(),
+            buffer_index: 0,
+        })
+    }
+}
+
+/// The first non-empty match for `regex` within `offset..end` of `text`, with `replacement`
+/// expanded for it. Matching against the whole text keeps anchors like `^` and `\b` from matching
+/// at the edges of the range.
+fn next_match(
+    regex: &Regex,
+    replacement: &str,
+    text: &str,
+    mut offset: usize,
+    end: usize,
+) -> Option<(Range<usize>, String)> {
+    while offset <= end {
+        let captures = regex.captures_at(text, offset)?;
+        let found = captures.get(0).unwrap();
+        if found.end() > end {
+            return None;
+        }
+        if found.is_empty() {
+            offset = text[found.end()..]
+                .chars()
+                .next()
+                .map_or(usize::MAX, |char| found.end() + char.len_utf8());
+            continue;
+        }
+        let mut expanded = String::new();
+        captures.expand(replacement, &mut expanded);
+        return Some((found.range(), expanded));
+    }
+    None
+}
+
+/// Commands whose argument `complete` completes as a path.
+const PATH_COMMANDS: [&str; 7] = ["e", "edit", "e!", "edit!", "w", "write", "mksession"];
+
+// Every command's name and aliases, including `pipe`, which `run_command` handles itself.
+fn command_names() -> Vec<String> {
+    Command::command()
+        .get_subcommands()
+        .flat_map(|command| iter::once(command.get_name()).chain(command.get_all_aliases()))
+        .chain(["pipe"])
+        .map(String::from)
+        .collect()
+}
+
+// The longest start `a` and `b` share.
+fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let len = iter::zip(a.char_indices(), b.chars())
+        .take_while(|((_, a), b)| a == b)
+        .last()
+        .map_or(0, |((start, char), _)| start + char.len_utf8());
+    &a[..len]
+}
+
+/// How many positions the jump list keeps before forgetting the oldest.
+const JUMP_LIST_CAPACITY: usize = 100;
+
+// The integer at the start of `line`, after any indentation.
+fn leading_number(line: &str) -> Option<i64> {
+    let line = line.trim_start();
+    let digits = line.strip_prefix('-').unwrap_or(line);
+    let len = line.len() - digits.len() + digits.bytes().take_while(u8::is_ascii_digit).count();
+    line[..len].parse().ok()
+}
+
+// Read the file at `path` with its line endings normalized to `\n`, and the ending it used.
+fn read_text(path: &Utf8Path) -> io::Result<(Rope, LineEnding)> {
+    let string = fs::read_to_string(path)?;
+    let line_ending = LineEnding::detect(&string);
+    Ok((Rope::from(string.replace("\r\n", "\n")), line_ending))
+}
+
+// Typed together when `auto_pairs` is on.
+const AUTO_PAIRS: [(char, char); 5] =
+    [('(', ')'), ('[', ']'), ('{', '}'), ('"', '"'), ('\'', '\'')];
+
+// The opening and closing brackets of the pair `bracket` belongs to, if it's a bracket.
+fn bracket_pair(bracket: u8) -> Option<(u8, u8)> {
+    match bracket {
+        b'(' | b')' => Some((b'(', b')')),
+        b'[' | b']' => Some((b'[', b']')),
+        b'{' | b'}' => Some((b'{', b'}')),
+        _ => None,
+    }
+}
+
+fn replaced_message(count: usize) -> String {
+    let noun = if count == 1 {
+        "occurrence"
+    } else {
+        "occurrences"
+    };
+    format!("Replaced {count} {noun}")
+}
+
+/// Split a Vim-style line range like `10,20`, `.,$`, or `%` off the start of `command`. Lines are
+/// numbered from one, `.` is `current_line`, and `$` is the last line. The range is returned as
+/// line indices, with the rest of the command.
+fn split_range(
+    command: &str,
+    current_line: usize,
+    line_len: usize,
+) -> Result<Option<(RangeInclusive<usize>, &str)>, String> {
+    // An empty text still has one line to address.
+    let last_line = max(line_len, 1);
+    let (start, end, rest) = if let Some(rest) = command.strip_prefix('%') {
+        (1, last_line, rest)
+    } else if let Some((start, rest)) = address(command, current_line, last_line) {
+        match rest.strip_prefix(',') {
+            Some(rest) => {
+                let (end, rest) = address(rest, current_line, last_line).ok_or("Invalid range")?;
+                (start, end, rest)
+            }
+            None => (start, start, rest),
+        }
+    } else {
+        return Ok(None);
+    };
+    if start == 0 || start > end || end > last_line {
+        return Err(String::from("Invalid range"));
+    }
+    Ok(Some((start - 1..=end - 1, rest.trim_start())))
+}
+
+// A line number, `.`, or `$` at the start of `command`, with the rest of the command.
+fn address(command: &str, current_line: usize, last_line: usize) -> Option<(usize, &str)> {
+    if let Some(rest) = command.strip_prefix('.') {
+        return Some((current_line + 1, rest));
+    }
+    if let Some(rest) = command.strip_prefix('$') {
+        return Some((last_line, rest));
+    }
+    let digits = command.bytes().take_while(u8::is_ascii_digit).count();
+    if digits == 0 {
+        return None;
+    }
+    let line = command[..digits].parse().unwrap_or(usize::MAX);
+    Some((line, &command[digits..]))
+}
+
+/// Where undo files are kept: `$XDG_CACHE_HOME/blue/undo`, falling back to `~/.cache/blue/undo`.
+fn default_undo_dir() -> Option<Utf8PathBuf> {
+    let cache_home = match env::var("XDG_CACHE_HOME") {
+        Ok(cache_home) if !cache_home.is_empty() => Utf8PathBuf::from(cache_home),
+        _ => Utf8PathBuf::from(env::var("HOME").ok()?).join(".cache"),
+    };
+    Some(cache_home.join("blue/undo"))
+}
+
+// The undo file for the file at `path`, named after its canonical path with `%` and `/`
+// percent-encoded, so no two paths share one.
+fn undo_file(undo_dir: &Utf8Path, path: &Utf8Path) -> Option<Utf8PathBuf> {
+    let path = path.canonicalize_utf8().ok()?;
+    Some(undo_dir.join(path.as_str().replace('%', "%25").replace('/', "%2F")))
+}
+
+fn write_undo_file(
+    undo_dir: &Utf8Path,
+    path: &Utf8Path,
+    history: &History,
+    text: &Rope,
+) -> io::Result<()> {
+    let Some(file) = undo_file(undo_dir, path) else {
+        return Ok(());
+    };
+    fs::create_dir_all(undo_dir)?;
+    fs::write(file, history.to_undo_file(text))
+}
+
+/// The shell command in a `pipe` command line, which is the rest of the line, with its own quoting
+/// and pipes.
+fn shell_command(command: &str) -> Option<&str> {
+    command
+        .strip_prefix("pipe")
+        .filter(|rest| rest.is_empty() || rest.starts_with(' '))
+        .map(str::trim)
+}
+
+/// Give `child` its input and wait for it to finish, killing it if it outlives a non-zero
+/// `timeout`, in which case there's no output.
+fn wait_with_timeout(
+    mut child: Child,
+    input: String,
+    timeout: Duration,
+) -> io::Result<Option<Output>> {
+    fn read_all(mut pipe: impl Read + Send + 'static) -> JoinHandle<io::Result<Vec<u8>>> {
+        thread::spawn(move || {
+            let mut bytes = Vec::new();
+            pipe.read_to_end(&mut bytes)?;
+            Ok(bytes)
         })
     }
-}
+    let mut stdin = child.stdin.take().unwrap();
+    // Writing and reading from other threads means a command can't block on a full pipe while
+    // the rest of the selection waits to be written.
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let stdout = read_all(child.stdout.take().unwrap());
+    let stderr = read_all(child.stderr.take().unwrap());
+    let status = if timeout.is_zero() {
+        child.wait()?
+    } else {
+        let start = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if start.elapsed() >= timeout {
+                child.kill()?;
+                child.wait()?;
+                // Anything the command started could still hold the pipes open, so the threads
+                // are left to finish on their own.
+                return Ok(None);
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    };
+    // A command can exit without reading all of its input, which isn't an error.
+    let _ = writer.join();
+    Ok(Some(Output {
+        status,
+        stdout: stdout.join().unwrap()?,
+        stderr: stderr.join().unwrap()?,
+    }))
+}
+
+/// Split Vim-style `g/pattern/command` syntax, where `\/` escapes a slash in the pattern.
+fn split_global(command: &str) -> Option<(String, &str)> {
+    let rest = command
+        .strip_prefix("global/")
+        .or_else(|| command.strip_prefix("g/"))?;
+    let mut pattern = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((index, char)) = chars.next() {
+        match char {
+            '\\' => match chars.next() {
+                Some((_, '/')) => pattern.push('/'),
+                Some((_, next)) => {
+                    pattern.push('\\');
+                    pattern.push(next);
+                }
+                None => pattern.push('\\'),
+            },
+            '/' => return Some((pattern, &rest[index + 1..])),
+            _ => pattern.push(char),
+        }
+    }
+    Some((pattern, ""))
+}
+
+/// How the cursor is drawn when it sits at the end of a line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EolCursor {
+    /// A plain block in the column after the line's text.
+    Block,
+    /// A block containing a newline symbol, so it's distinguishable from trailing whitespace.
+    Newline,
+}
+
+/// The line terminator a file uses on disk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    /// Whichever ending most of the lines in `text` use.
+    fn detect(text: &str) -> Self {
+        let crlf = text.matches("\r\n").count();
+        let lf = text.matches('\n').count() - crlf;
+        if crlf > lf { Self::Crlf } else { Self::Lf }
+    }
+}
+
+/// How lines are numbered in the gutter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineNumbers {
+    Absolute,
+    /// Distances from the cursor's line, which shows its own number.
+    Relative,
+}
+
+/// The color scheme. There's only the built-in light one so far.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Theme {
+    Light,
+}
+
+/// What `:set list` draws in place of whitespace.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ListChars {
+    /// Drawn in a tab's first column. The rest of its columns stay blank.
+    pub tab: char,
+    /// Drawn for each space at the end of a line.
+    pub trail: char,
+    /// Drawn after the end of each line which has a newline.
+    pub eol: char,
+}
+
+impl Default for ListChars {
+    fn default() -> Self {
+        Self {
+            tab: '→',
+            trail: '·',
+            eol: '¶',
+        }
+    }
+}
+
+/// Where yanked text goes, besides the register.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Clipboard {
+    /// Only the register.
+    Internal,
+    /// The system clipboard too, via the terminal. Text pasted by the terminal is pasted in Normal
+    /// mode as well as Insert mode.
+    System,
+}
+
+/// Completion matches in Command mode, which can be filled in one at a time.
+pub struct Completion {
+    pub candidates: Vec<String>,
+    /// The candidate filled in, once cycling has started.
+    pub index: Option<usize>,
+    // Where the text being completed starts and ends in the command.
+    start: usize,
+    end: usize,
+    // The command after the last completion, which only it can carry on from.
+    command: Rope,
+}
+
+/// A `:substitute --confirm` in progress.
+pub struct Confirm {
+    regex: Regex,
+    pub replacement: String,
+    // The selected match, and where the range being substituted ends.
+    candidate: Range<usize>,
+    end: usize,
+    found: usize,
+    replaced: usize,
+}
+
+/// A selection's anchor and head, with the column vertical motion aims for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Selection {
+    pub anchor: usize,
+    pub head: usize,
+    desired_column: usize,
+}
+
+impl Selection {
+    #[must_use]
+    pub fn start(&self) -> usize {
+        min(self.anchor, self.head)
+    }
+
+    #[must_use]
+    pub fn end(&self) -> usize {
+        max(self.anchor, self.head)
+    }
+
+    #[must_use]
+    pub fn is_forward(&self) -> bool {
+        self.anchor <= self.head
+    }
+
+    /// The start of the grapheme the cursor is drawn on, as in `Editor::cursor`.
+    #[must_use]
+    pub fn cursor(&self, text: &Rope) -> usize {
+        if self.anchor < self.head {
+            prev_grapheme_boundary(&text.byte_slice(..), self.head).unwrap_or(self.head)
+        } else {
+            self.head
+        }
+    }
+}
+
+/// The terminal cursor's style in each mode, set with `:set guicursor`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CursorStyles {
+    pub normal: CursorStyle,
+    /// Goto, Next, Prev, View, Replace, Surround, the mark modes, and Confirm, which all wait for
+    /// the rest of a key sequence.
+    pub goto: CursorStyle,
+    pub insert: CursorStyle,
+    pub command: CursorStyle,
+}
+
+impl CursorStyles {
+    #[must_use]
+    pub fn get(self, mode: &Mode) -> CursorStyle {
+        match mode {
+            Mode::Normal => self.normal,
+            Mode::Goto
+            | Mode::Next
+            | Mode::Prev
+            | Mode::View
+            | Mode::Replace
+            | Mode::Surround
+            | Mode::Find { .. }
+            | Mode::SetMark
+            | Mode::GotoMark
+            | Mode::Confirm => self.goto,
+            Mode::Insert => self.insert,
+            Mode::Command | Mode::Search => self.command,
+        }
+    }
+
+    /// Apply a comma-separated list of `mode:shape` pairs, where the shape is `block`, `bar`, or
+    /// `underline`, optionally followed by `-blink`. Modes which aren't listed are unchanged.
+    fn with(mut self, value: &str) -> Option<Self> {
+        for pair in value.split(',') {
+            let (mode, style) = pair.split_once(':')?;
+            let (shape, blink) = match style.strip_suffix("-blink") {
+                Some(shape) => (shape, true),
+                None => (style, false),
+            };
+            let shape = match shape {
+                "block" => CursorShape::Block,
+                "bar" => CursorShape::Bar,
+                "underline" => CursorShape::Underline,
+                _ => return None,
+            };
+            let style = match mode {
+                "normal" => &mut self.normal,
+                "goto" => &mut self.goto,
+                "insert" => &mut self.insert,
+                "command" => &mut self.command,
+                _ => return None,
+            };
+            *style = CursorStyle { shape, blink };
+        }
+        Some(self)
+    }
+}
+
+impl Default for CursorStyles {
+    fn default() -> Self {
+        let style = |shape| CursorStyle {
+            shape,
+            blink: false,
+        };
+        Self {
+            normal: style(CursorShape::Block),
+            goto: style(CursorShape::Underline),
+            insert: style(CursorShape::Bar),
+            command: style(CursorShape::Underline),
+        }
+    }
+}
+
+impl fmt::Display for CursorStyles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let styles = [
+            ("normal", self.normal),
+            ("goto", self.goto),
+            ("insert", self.insert),
+            ("command", self.command),
+        ];
+        for (i, (mode, style)) in styles.into_iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            let shape = match style.shape {
+                CursorShape::Block => "block",
+                CursorShape::Bar => "bar",
+                CursorShape::Underline => "underline",
+            };
+            let blink = if style.blink { "-blink" } else { "" };
+            write!(f, "{separator}{mode}:{shape}{blink}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CursorStyle {
+    pub shape: CursorShape,
+    pub blink: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CursorShape {
+    Block,
+    Bar,
+    Underline,
+}
+
+/// The commands `run_command` parses.
+#[derive(clap::Parser)]
+#[clap(
+    disable_help_flag = true,
+    disable_help_subcommand = true,
+    override_usage = ""
+)]
+enum Command {
+    Align {
+        delimiter: String,
+    },
+    #[clap(alias = "d")]
+    Delete,
+    /// Open another file in a new buffer
+    #[clap(alias = "e")]
+    Edit {
+        path: Utf8PathBuf,
+    },
+    /// Open another file in place of this one, or reload this one, discarding its changes
+    #[clap(name = "edit!", alias = "e!")]
+    EditForce {
+        path: Option<Utf8PathBuf>,
+    },
+    /// Read the file again, discarding unsaved changes
+    Reload,
+    /// List the open buffers
+    Buffers,
+    BufferNext,
+    BufferPrev,
+    Echo {
+        #[clap(long)]
+        error: bool,
+        message: Vec<String>,
+    },
+    /// Color text matching a regex
+    #[clap(alias = "hi")]
+    Highlight {
+        color: String,
+        pattern: String,
+    },
+    /// Fold the selected lines
+    Fold,
+    #[clap(alias = "g")]
+    Global {
+        pattern: String,
+        #[clap(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    Set {
+        option: String,
+        value: Option<String>,
+    },
+    /// Sort the selected lines
+    Sort {
+        #[clap(short, long)]
+        reverse: bool,
+        /// Compare the numbers lines start with
+        #[clap(short, long)]
+        numeric: bool,
+    },
+    /// Count the lines, words, characters, and bytes in the selection or the whole text
+    Wc,
+    /// Save the open file and selection, to restore with `--session`
+    Mksession {
+        path: Option<Utf8PathBuf>,
+    },
+    #[clap(alias = "s")]
+    Substitute {
+        /// Ask before replacing each match
+        #[clap(short, long)]
+        confirm: bool,
+        pattern: String,
+        replacement: String,
+    },
+    /// Repeat the last substitution on the selected lines
+    #[clap(name = "&")]
+    RepeatSubstitute,
+    /// Repeat the last substitution with its flags
+    #[clap(name = "&&")]
+    RepeatSubstituteWithFlags,
+    #[clap(alias = "y")]
+    Yank,
+    /// Save the file, or save it to another path from now on
+    #[clap(alias = "w")]
+    Write {
+        path: Option<Utf8PathBuf>,
+    },
+    #[clap(alias = "q")]
+    Quit {
+        exit_code: Option<u8>,
+    },
+    #[clap(name = "quit!", alias = "q!")]
+    QuitForce {
+        exit_code: Option<u8>,
+    },
+    /// Exit, closing every buffer
+    #[clap(name = "quit-all", alias = "qa")]
+    QuitAll {
+        exit_code: Option<u8>,
+    },
+    #[clap(name = "quit-all!", alias = "qa!")]
+    QuitAllForce {
+        exit_code: Option<u8>,
+    },
+    #[clap(name = "cquit", alias = "cq")]
+    Cquit {
+        #[clap(value_parser = clap::value_parser!(u8).range(1..))]
+        exit_code: Option<u8>,
+    },
+    #[clap(name = "write-quit", alias = "wq")]
+    WriteQuit {
+        exit_code: Option<u8>,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Category {
+    Word,
+    Punctuation,
+    Whitespace,
+    LineBreak,
+}
+
+#[derive(PartialEq)]
+pub enum Mode {
+    Normal,
+    Goto,
+    Next,
+    Prev,
+    View,
+    /// Waiting for the character to replace the selection with.
+    Replace,
+    /// Waiting for the character to surround the selection with.
+    Surround,
+    /// Waiting for the character to extend the selection to, searching forward or backward, and
+    /// stopping next to it with `till`.
+    Find {
+        forward: bool,
+        till: bool,
+    },
+    /// Waiting for the name of a mark to set.
+    SetMark,
+    /// Waiting for the name of a mark to jump to.
+    GotoMark,
+    Insert,
+    Command,
+    Search,
+    /// Asking whether to replace each match of a `:substitute --confirm`.
+    Confirm,
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::ops::Deref;
+
+    /// A new directory for a test's files, which is deleted when this is dropped, even if the test
+    /// fails.
+    pub(crate) struct TempDir(Utf8PathBuf);
+
+    impl Deref for TempDir {
+        type Target = Utf8Path;
+
+        fn deref(&self) -> &Utf8Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Make an empty `TempDir` named after `name` and the process, replacing any left behind.
+    pub(crate) fn temp_dir(name: &str) -> TempDir {
+        let dir = Utf8PathBuf::try_from(std::env::temp_dir())
+            .unwrap()
+            .join(format!("blue-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    #[test]
+    fn test_run_commands() {
+        let mut editor = Editor::try_from(Rope::from("a = 1\nbbb = 2\n")).unwrap();
+        editor.head = editor.text.byte_len();
+        editor.run_commands(&[
+            String::from("bogus"),
+            String::from("align ="),
+            String::from("echo done"),
+        ]);
+        assert_eq!(editor.text, "a   = 1\nbbb = 2\n");
+        assert!(matches!(editor.message, Some(Err(_))));
+        assert!(editor.exit_code.is_none());
+
+        editor.run_commands(&[String::from("quit! 3")]);
+        assert_eq!(editor.exit_code, Some(ExitCode::from(3)));
+    }
+
+    #[test]
+    fn test_extend_line_boundaries() {
+        let mut editor = Editor::try_from(Rope::from("first\nhello world\n")).unwrap();
+        let line_start = "first\n".len();
+        let line_end = line_start + "hello world".len();
+        for anchor_column in [0, 1, 5] {
+            editor.anchor = line_start + anchor_column;
+            editor.head = line_start + 8;
+            editor.extend_line_start();
+            assert_eq!(editor.anchor, line_start + anchor_column);
+            assert_eq!(editor.head, line_start);
+
+            editor.head = line_start + 3;
+            editor.extend_line_end();
+            assert_eq!(editor.anchor, line_start + anchor_column);
+            assert_eq!(editor.head, line_end);
+        }
+    }
+
+    #[test]
+    fn test_smart_line_start() {
+        let mut editor = Editor::try_from(Rope::from("first\n\t  hello\n  \n")).unwrap();
+        editor.move_to(12);
+        editor.move_smart_line_start();
+        assert_eq!((editor.anchor, editor.head), (9, 9));
+        // Pressing it again goes to the line start, and then back.
+        editor.move_smart_line_start();
+        assert_eq!((editor.anchor, editor.head), (6, 6));
+        editor.move_smart_line_start();
+        assert_eq!((editor.anchor, editor.head), (9, 9));
+        // Extending keeps the anchor.
+        editor.anchor = 14;
+        editor.extend_smart_line_start();
+        assert_eq!((editor.anchor, editor.head), (14, 6));
+        // A blank line's first non-whitespace is its end.
+        editor.move_to(16);
+        editor.move_smart_line_start();
+        assert_eq!((editor.anchor, editor.head), (17, 17));
+    }
+
+    #[test]
+    fn test_word_motions() {
+        let mut editor = Editor::try_from(Rope::from("foo.bar  baz\n\n  qux")).unwrap();
+        let selection = |editor: &Editor| (editor.anchor, editor.head);
+
+        editor.move_next_word_start(1);
+        assert_eq!(selection(&editor), (0, 3));
+        editor.move_next_word_start(1);
+        assert_eq!(selection(&editor), (3, 4));
+        editor.move_next_word_start(1);
+        assert_eq!(selection(&editor), (4, 9));
+        // Line breaks are skipped.
+        editor.move_next_word_start(2);
+        assert_eq!(selection(&editor), (14, 16));
+        editor.move_next_word_start(1);
+        assert_eq!(selection(&editor), (16, 19));
+        // Nothing left at EOF.
+        editor.move_next_word_start(1);
+        assert_eq!(selection(&editor), (16, 19));
+        editor.move_next_word_end(1);
+        assert_eq!(selection(&editor), (16, 19));
+
+        editor.move_prev_word_start(1);
+        assert_eq!(selection(&editor), (19, 16));
+        // Indentation is selected on its own, before moving to the previous line.
+        editor.move_prev_word_start(1);
+        assert_eq!(selection(&editor), (16, 14));
+        editor.move_prev_word_start(1);
+        assert_eq!(selection(&editor), (12, 9));
+        editor.move_prev_word_start(3);
+        assert_eq!(selection(&editor), (3, 0));
+        editor.move_prev_word_start(1);
+        assert_eq!(selection(&editor), (3, 0));
+
+        editor.move_next_word_end(1);
+        assert_eq!(selection(&editor), (0, 3));
+        editor.move_next_word_end(1);
+        assert_eq!(selection(&editor), (3, 4));
+        editor.move_next_word_end(1);
+        assert_eq!(selection(&editor), (4, 7));
+        editor.move_next_word_end(1);
+        assert_eq!(selection(&editor), (7, 12));
+
+        editor.move_to(0);
+        editor.move_next_long_word_start(1);
+        assert_eq!(selection(&editor), (0, 9));
+        editor.move_next_long_word_end(1);
+        assert_eq!(selection(&editor), (9, 12));
+        editor.move_prev_long_word_start(1);
+        assert_eq!(selection(&editor), (12, 9));
+        editor.move_prev_long_word_start(1);
+        assert_eq!(selection(&editor), (9, 0));
+
+        let mut editor = Editor::new().unwrap();
+        editor.move_next_word_start(1);
+        editor.move_next_word_end(1);
+        editor.move_prev_word_start(1);
+        assert_eq!(selection(&editor), (0, 0));
+    }
+
+    #[test]
+    fn test_scrolloff() {
+        let text = (0..20).map(|n| n.to_string() + "\n").collect::<String>();
+        let mut editor = Editor::try_from(Rope::from(text)).unwrap();
+        let move_to_line = |editor: &mut Editor, line| {
+            editor.move_to(editor.text.byte_of_line(line));
+            editor.scroll_to_head(10);
+        };
+
+        move_to_line(&mut editor, 6);
+        assert_eq!(editor.vertical_scroll, 0);
+        move_to_line(&mut editor, 7);
+        assert_eq!(editor.vertical_scroll, 1);
+        move_to_line(&mut editor, 15);
+        assert_eq!(editor.vertical_scroll, 9);
+        // The margin shrinks at the end of the text instead of scrolling past it.
+        move_to_line(&mut editor, 19);
+        assert_eq!(editor.vertical_scroll, 10);
+        move_to_line(&mut editor, 12);
+        assert_eq!(editor.vertical_scroll, 9);
+        move_to_line(&mut editor, 1);
+        assert_eq!(editor.vertical_scroll, 0);
+
+        editor.run_command("set scrolloff 0").unwrap();
+        move_to_line(&mut editor, 9);
+        assert_eq!(editor.vertical_scroll, 0);
+        move_to_line(&mut editor, 10);
+        assert_eq!(editor.vertical_scroll, 1);
+
+        // Short viewports can't fit the whole margin.
+        editor.run_command("set scrolloff 3").unwrap();
+        editor.move_to(editor.text.byte_of_line(10));
+        editor.scroll_to_head(3);
+        assert_eq!(editor.vertical_scroll, 9);
+    }
+
+    #[test]
+    fn test_fold() {
+        let text = (0..10).map(|n| n.to_string() + "\n").collect::<String>();
+        let mut editor = Editor::try_from(Rope::from(text)).unwrap();
+        let line = |editor: &Editor| editor.text.line_of_byte(editor.head);
+        editor.anchor = editor.text.byte_of_line(2);
+        editor.head = editor.text.byte_of_line(5);
+        editor.run_command("fold").unwrap();
+        assert_eq!(
+            editor.folds,
+            [Fold {
+                start: 2,
+                end: 4,
+                closed: true
+            }]
+        );
+        assert_eq!(line(&editor), 2);
+
+        // Vertical motion skips over the closed fold.
+        editor.move_down(1);
+        assert_eq!(line(&editor), 5);
+        editor.move_up(2);
+        assert_eq!(line(&editor), 1);
+        editor.move_down(2);
+        assert_eq!(line(&editor), 5);
+
+        // A closed fold counts as one line when scrolling.
+        editor.scroll_to_head(2);
+        assert_eq!(editor.vertical_scroll, 2);
+
+        editor.move_up(1);
+        editor.open_fold();
+        editor.move_down(1);
+        assert_eq!(line(&editor), 3);
+        // Closing the fold moves the cursor out from inside it.
+        editor.toggle_fold();
+        assert_eq!(line(&editor), 2);
+
+        // Folds follow their lines through edits.
+        editor.move_to(0);
+        editor.insert("\n");
+        assert_eq!(editor.folds[0].start, 3);
+        editor.undo();
+        assert_eq!(editor.folds[0].start, 2);
+
+        editor.move_to(0);
+        editor.delete_fold();
+        assert_eq!(editor.message, Some(Err(String::from("No fold"))));
+        editor.move_to(editor.text.byte_of_line(3));
+        editor.delete_fold();
+        assert!(editor.folds.is_empty());
+    }
+
+    #[test]
+    fn test_multiple_selections() {
+        let mut editor = Editor::try_from(Rope::from("ab\ncd\nef\n")).unwrap();
+        let selection = |editor: &Editor| (editor.anchor, editor.head);
+        editor.move_to(0);
+        editor.copy_selection_below();
+        editor.copy_selection_below();
+        assert_eq!(selection(&editor), (6, 7));
+        let others = |editor: &Editor| {
+            editor
+                .selections
+                .iter()
+                .map(|selection| (selection.anchor, selection.head))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(others(&editor), [(0, 1), (3, 4)]);
+        editor.copy_selection_below();
+        assert_eq!(editor.message, Some(Err(String::from("No line below"))));
+
+        // Each edit shifts the selections after it.
+        editor.for_each_selection(Editor::reduce);
+        editor.for_each_selection(|editor| editor.insert("xy"));
+        assert_eq!(editor.text, "axyb\ncxyd\nexyf\n");
+        assert_eq!(others(&editor), [(3, 3), (8, 8)]);
+        assert_eq!(selection(&editor), (13, 13));
+        editor.undo();
+        assert_eq!(editor.text, "ab\ncd\nef\n");
+        assert!(editor.selections.is_empty());
+
+        // Typing at several selections is undone a word at a time too.
+        editor.move_to(0);
+        editor.copy_selection_below();
+        editor.for_each_selection(Editor::reduce);
+        for char in "xyz".chars() {
+            editor.for_each_selection(|editor| editor.insert(&char.to_string()));
+        }
+        assert_eq!(editor.text, "axyzb\ncxyzd\nef\n");
+        editor.undo();
+        assert_eq!(editor.text, "ab\ncd\nef\n");
+
+        // Selections which meet are merged, and the primary selection survives.
+        editor.copy_selection_below();
+        editor.for_each_selection(|editor| editor.move_to(0));
+        assert!(editor.selections.is_empty());
+        assert_eq!(selection(&editor), (0, 1));
+
+        editor.copy_selection_below();
+        editor.keep_primary_selection();
+        assert!(editor.selections.is_empty());
+        assert_eq!(selection(&editor), (3, 4));
+    }
+
+    #[test]
+    fn test_hunk_navigation() {
+        let mut editor = Editor::try_from(Rope::from("a\nb\nc\nd\ne\nf\n")).unwrap();
+        editor.move_next_hunk();
+        assert_eq!(editor.message, Some(Err(String::from("No changes"))));
+        assert_eq!(editor.head, 0);
+
+        // Change line 1, delete line 5.
+        editor.apply_edit(2..3, "B");
+        editor.apply_edit(10..12, "");
+        editor.modified = true;
+        assert_eq!(editor.text, "a\nB\nc\nd\ne\n");
+        let line = |editor: &Editor| editor.text.line_of_byte(editor.head);
+
+        editor.move_next_hunk();
+        assert_eq!(line(&editor), 1);
+        editor.move_next_hunk();
+        assert_eq!(line(&editor), 4);
+        // The deletion at EOF is marked on the last line, so wrap around.
+        editor.move_next_hunk();
+        assert_eq!(line(&editor), 1);
+
+        editor.move_prev_hunk();
+        assert_eq!(line(&editor), 4);
+        editor.move_prev_hunk();
+        assert_eq!(line(&editor), 1);
+    }
+
+    #[test]
+    fn test_replace_with() {
+        let mut editor = Editor::try_from(Rope::from("ab\ncé\n")).unwrap();
+        editor.replace_with('x');
+        assert_eq!(editor.text, "xb\ncé\n");
+        assert_eq!((editor.anchor, editor.head), (0, 0));
+
+        // Each grapheme is replaced, whatever its width, and the selection covers the result.
+        editor.anchor = 7;
+        editor.head = 1;
+        editor.replace_with('日');
+        assert_eq!(editor.text, "x日\n日日\n");
+        assert_eq!((editor.anchor, editor.head), (12, 1));
+        assert!(editor.modified);
+
+        editor.undo();
+        assert_eq!(editor.text, "xb\ncé\n");
+        editor.anchor = 8;
+        editor.head = 8;
+        editor.replace_with('y');
+        assert_eq!(editor.text, "xb\ncé\n");
+    }
+
+    #[test]
+    fn test_toggle_case() {
+        let mut editor = Editor::try_from(Rope::from("héllo Wörld")).unwrap();
+        for _ in 0..5 {
+            editor.toggle_case();
+        }
+        assert_eq!(editor.text, "HÉLLO Wörld");
+        assert_eq!(editor.head, "HÉLLO".len());
+        assert_eq!(editor.anchor, editor.head);
+
+        editor.head = editor.text.byte_len();
+        editor.toggle_case();
+        assert_eq!(editor.text, "HÉLLO wÖRLD");
+        assert_eq!(editor.anchor, "HÉLLO".len());
+        assert_eq!(editor.head, editor.text.byte_len());
+
+        let mut editor = Editor::try_from(Rope::from("1-2")).unwrap();
+        for _ in 0..4 {
+            editor.toggle_case();
+        }
+        assert_eq!(editor.text, "1-2");
+        assert_eq!(editor.head, 3);
+        assert!(!editor.modified);
+    }
+
+    #[test]
+    fn test_change_case() {
+        let mut editor = Editor::try_from(Rope::from("straße Öl")).unwrap();
+        editor.anchor = 0;
+        editor.head = "straße".len();
+        editor.uppercase();
+        assert_eq!(editor.text, "STRASSE Öl");
+        assert_eq!((editor.anchor, editor.head), (0, 7));
+        assert!(editor.modified);
+        editor.anchor = editor.text.byte_len();
+        editor.head = 0;
+        editor.lowercase();
+        assert_eq!(editor.text, "strasse öl");
+        assert_eq!((editor.anchor, editor.head), (editor.text.byte_len(), 0));
+        editor.reduce();
+        editor.uppercase();
+        assert_eq!(editor.text, "Strasse öl");
+        assert_eq!((editor.anchor, editor.head), (1, 1));
+    }
+
+    #[test]
+    fn test_split_range() {
+        assert_eq!(split_range("d", 0, 30), Ok(None));
+        assert_eq!(split_range("10,20d", 0, 30), Ok(Some((9..=19, "d"))));
+        assert_eq!(split_range(".,$ y", 4, 30), Ok(Some((4..=29, "y"))));
+        assert_eq!(split_range("%s a b", 4, 30), Ok(Some((0..=29, "s a b"))));
+        assert_eq!(split_range("7", 0, 30), Ok(Some((6..=6, ""))));
+        assert_eq!(split_range("1d", 0, 0), Ok(Some((0..=0, "d"))));
+        let invalid = Err(String::from("Invalid range"));
+        assert_eq!(split_range("0d", 0, 30), invalid);
+        assert_eq!(split_range("20,10d", 0, 30), invalid);
+        assert_eq!(split_range("10,31d", 0, 30), invalid);
+        assert_eq!(split_range("10,d", 0, 30), invalid);
+    }
+
+    #[test]
+    fn test_range_command() {
+        let text = (1..=30).map(|n| n.to_string() + "\n").collect::<String>();
+        let mut editor = Editor::try_from(Rope::from(text)).unwrap();
+        editor.run_command("10,20d").unwrap();
+        assert_eq!(editor.text.line_len(), 19);
+        assert_eq!(editor.text.line(8), "9");
+        assert_eq!(editor.text.line(9), "21");
+        assert_eq!(
+            editor.register,
+            (10..=20).map(|n| n.to_string() + "\n").collect::<String>()
+        );
+
+        editor.run_command("$y").unwrap();
+        assert_eq!(editor.register, "30\n");
+        editor.run_command("40y").unwrap();
+        assert_eq!(editor.message, Some(Err(String::from("Invalid range"))));
+    }
+
+    #[test]
+    fn test_goto_line() {
+        let mut editor = Editor::try_from(Rope::from("a\nbc\nd\n")).unwrap();
+        editor.run_command("2").unwrap();
+        assert_eq!((editor.anchor, editor.head), (2, 3));
+        editor.run_command("99").unwrap();
+        assert_eq!((editor.anchor, editor.head), (5, 6));
+        editor.run_command("0").unwrap();
+        assert_eq!((editor.anchor, editor.head), (0, 1));
+        assert_eq!(editor.message, None);
+
+        let mut editor = Editor::new().unwrap();
+        editor.run_command("3").unwrap();
+        assert_eq!((editor.anchor, editor.head), (0, 0));
+    }
+
+    #[test]
+    fn test_goto_matching_bracket() {
+        let mut editor = Editor::try_from(Rope::from("f(a, [b], (c)\n  {d})\n(")).unwrap();
+        editor.move_to(0);
+        editor.goto_matching_bracket();
+        assert_eq!((editor.anchor, editor.head), (0, 1));
+        editor.move_to(1);
+        editor.goto_matching_bracket();
+        assert_eq!((editor.anchor, editor.head), (19, 20));
+        editor.goto_matching_bracket();
+        assert_eq!((editor.anchor, editor.head), (1, 2));
+        editor.move_to(16);
+        editor.goto_matching_bracket();
+        assert_eq!((editor.anchor, editor.head), (18, 19));
+        assert_eq!(editor.message, None);
+        editor.move_to(21);
+        editor.goto_matching_bracket();
+        assert_eq!((editor.anchor, editor.head), (21, 22));
+        assert_eq!(
+            editor.message,
+            Some(Err(String::from("No matching bracket")))
+        );
+        assert_eq!(editor.matching_bracket(1, 18), Some(19));
+        assert_eq!(editor.matching_bracket(1, 17), None);
+        assert_eq!(editor.matching_bracket(19, 18), Some(1));
+        assert_eq!(editor.matching_bracket(2, usize::MAX), None);
+    }
 
-#[derive(PartialEq)]
-pub enum Mode {
-    Normal,
-    Goto,
-    Insert,
-    Command,
+    #[test]
+    fn test_marks() {
+        let mut editor = Editor::try_from(Rope::from("one two three\n")).unwrap();
+        editor.move_to(4);
+        editor.set_mark('a');
+        editor.move_to(8);
+        editor.set_mark('b');
+        // Inserting before a mark moves it along with the text.
+        (editor.anchor, editor.head) = (0, 0);
+        editor.insert("zero ");
+        editor.goto_mark('a');
+        assert_eq!((editor.anchor, editor.head), (9, 10));
+        // Deleting the text under a mark moves it to where the text was.
+        editor.move_to(13);
+        editor.extend_to(17);
+        editor.delete();
+        assert_eq!(editor.text.to_string(), "zero one two \n");
+        editor.goto_mark('b');
+        assert_eq!((editor.anchor, editor.head), (13, 14));
+        assert_eq!(editor.message, None);
+
+        editor.goto_mark('c');
+        assert_eq!((editor.anchor, editor.head), (13, 14));
+        assert_eq!(
+            editor.message,
+            Some(Err(String::from("Mark 'c' isn't set")))
+        );
+    }
+
+    #[test]
+    fn test_jumps() {
+        let text = (1..=20).map(|n| n.to_string() + "\n").collect::<String>();
+        let mut editor = Editor::try_from(Rope::from(text)).unwrap();
+        let line = |editor: &Editor| editor.text.line_of_byte(editor.cursor()) + 1;
+        editor.move_to(0);
+        editor.goto_line(10);
+        editor.goto_line(5);
+        editor.move_down(1);
+        editor.jump_back();
+        assert_eq!(line(&editor), 10);
+        editor.jump_back();
+        assert_eq!(line(&editor), 1);
+        editor.jump_back();
+        assert_eq!(line(&editor), 1);
+        editor.jump_forward();
+        editor.jump_forward();
+        assert_eq!(line(&editor), 6);
+        editor.jump_forward();
+        assert_eq!(line(&editor), 6);
+
+        // Jumps move with the text, and jumping somewhere new forgets what was jumped back from.
+        editor.apply_edit(0..0, "\n\n");
+        editor.jump_back();
+        assert_eq!(line(&editor), 12);
+        editor.goto_line(20);
+        editor.jump_back();
+        assert_eq!(line(&editor), 12);
+        editor.jump_back();
+        assert_eq!(line(&editor), 1);
+        editor.jump_forward();
+        editor.jump_forward();
+        assert_eq!(line(&editor), 20);
+
+        for number in 1..=JUMP_LIST_CAPACITY + 10 {
+            editor.goto_line(number % 20 + 1);
+        }
+        assert_eq!(editor.jumps.len(), JUMP_LIST_CAPACITY);
+    }
+
+    #[test]
+    fn test_delete_to_line_end() {
+        let mut editor = Editor::try_from(Rope::from("abc\ndéf")).unwrap();
+        editor.move_to(1);
+        editor.delete_to_line_end();
+        assert_eq!(editor.text.to_string(), "a\ndéf");
+        assert_eq!(editor.register, "bc");
+        assert_eq!((editor.anchor, editor.head), (1, 1));
+        // Already at the end of the line.
+        editor.delete_to_line_end();
+        assert_eq!(editor.text.to_string(), "a\ndéf");
+        // The last line doesn't need a terminator.
+        (editor.anchor, editor.head) = (3, 5);
+        editor.delete_to_line_end();
+        assert_eq!(editor.text.to_string(), "a\nd");
+        (editor.anchor, editor.head) = (3, 3);
+        editor.delete_to_line_end();
+        assert_eq!(editor.text.to_string(), "a\nd");
+    }
+
+    #[test]
+    fn test_pipe() {
+        let mut editor = Editor::try_from(Rope::from("b\nc\na\nz\n")).unwrap();
+        (editor.anchor, editor.head) = (0, 6);
+        editor.run_command("pipe sort").unwrap();
+        assert_eq!(editor.text.to_string(), "a\nb\nc\nz\n");
+        assert_eq!((editor.anchor, editor.head), (0, 6));
+        assert!(editor.modified);
+
+        editor.run_command("pipe echo 'oops' >&2; exit 3").unwrap();
+        assert_eq!(editor.message, Some(Err(String::from("oops"))));
+        editor.run_command("pipe false").unwrap();
+        assert!(
+            matches!(&editor.message, Some(Err(message)) if message.starts_with("'false' failed"))
+        );
+        editor.run_command("pipe").unwrap();
+        assert_eq!(
+            editor.message,
+            Some(Err(String::from("Missing shell command")))
+        );
+        assert_eq!(editor.text.to_string(), "a\nb\nc\nz\n");
+
+        // A command typed in Command mode waits for a draw saying it's running.
+        editor.command = Rope::from("pipe sort -r");
+        editor.execute_command().unwrap();
+        assert_eq!(editor.message, Some(Ok(String::from("Running: sort -r"))));
+        assert_eq!(editor.text.to_string(), "a\nb\nc\nz\n");
+        assert!(editor.run_pending_command().unwrap());
+        assert_eq!(editor.message, None);
+        assert_eq!(editor.text.to_string(), "c\nb\na\nz\n");
+        assert!(!editor.run_pending_command().unwrap());
+        editor.undo();
+
+        editor.run_command("pipe tr a-z A-Z | sort -r").unwrap();
+        assert_eq!(editor.text.to_string(), "C\nB\nA\nz\n");
+        editor.undo();
+        editor.undo();
+        assert_eq!(editor.text.to_string(), "b\nc\na\nz\n");
+    }
+
+    #[test]
+    fn test_shell_timeout() {
+        let mut editor = Editor::try_from(Rope::from("a\n")).unwrap();
+        editor.run_command("set shell-timeout 0.1").unwrap();
+        editor.run_command("set shell-timeout").unwrap();
+        assert_eq!(editor.message, Some(Ok(String::from("shell-timeout 0.1"))));
+        let start = Instant::now();
+        editor.run_command("pipe sleep 10").unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            editor.message,
+            Some(Err(String::from("Killed 'sleep 10' after 0.1s")))
+        );
+        assert_eq!(editor.text.to_string(), "a\n");
+        assert!(!editor.modified);
+
+        editor.run_command("set shell-timeout x").unwrap();
+        assert_eq!(
+            editor.message,
+            Some(Err(String::from("Invalid value for 'shell-timeout': 'x'")))
+        );
+        // Zero means there's no limit.
+        editor.run_command("set shell-timeout 0").unwrap();
+        editor.run_command("pipe sleep 0.2; echo b").unwrap();
+        assert_eq!(editor.text.to_string(), "b\na\n");
+    }
+
+    #[test]
+    fn test_sort_lines() {
+        let mut editor = Editor::try_from(Rope::from("x\n10 b\n9 a\n-1 c\nd\ny\n")).unwrap();
+        editor.run_command("2,5sort").unwrap();
+        assert_eq!(editor.text.to_string(), "x\n-1 c\n10 b\n9 a\nd\ny\n");
+        assert_eq!((editor.anchor, editor.head), (2, 18));
+        assert!(editor.modified);
+        editor.run_command("sort --numeric").unwrap();
+        assert_eq!(editor.text.to_string(), "x\nd\n-1 c\n9 a\n10 b\ny\n");
+        editor.run_command("sort -rn").unwrap();
+        assert_eq!(editor.text.to_string(), "x\n10 b\n9 a\n-1 c\nd\ny\n");
+
+        // A single line has nothing to sort.
+        editor.run_command("1sort -r").unwrap();
+        assert_eq!(editor.text.to_string(), "x\n10 b\n9 a\n-1 c\nd\ny\n");
+
+        let mut editor = Editor::try_from(Rope::from("b\na")).unwrap();
+        editor.run_command("%sort").unwrap();
+        assert_eq!(editor.text.to_string(), "a\nb");
+        editor.undo();
+        assert_eq!(editor.text.to_string(), "b\na");
+    }
+
+    #[test]
+    fn test_auto_pairs() {
+        let mut editor = Editor::try_from(Rope::from("\n")).unwrap();
+        let type_chars = |editor: &mut Editor, chars: &str| {
+            for char in chars.chars() {
+                editor.insert_char(char);
+            }
+        };
+        type_chars(&mut editor, "f(");
+        assert_eq!(editor.text.to_string(), "f(\n");
+        editor.auto_pairs = true;
+        type_chars(&mut editor, "[\"a");
+        assert_eq!(editor.text.to_string(), "f([\"a\"]\n");
+        // Closing halves step over the ones already there.
+        type_chars(&mut editor, "\"]");
+        assert_eq!(editor.text.to_string(), "f([\"a\"]\n");
+        assert_eq!(editor.head, 7);
+        // An apostrophe after a letter is typed alone.
+        type_chars(&mut editor, " don't {");
+        assert_eq!(editor.text.to_string(), "f([\"a\"] don't {}\n");
+        // Backspace deletes an empty pair, but only the opening half of a full one.
+        editor.delete_before();
+        assert_eq!(editor.text.to_string(), "f([\"a\"] don't \n");
+        editor.head = 2;
+        editor.reduce();
+        editor.delete_before();
+        assert_eq!(editor.text.to_string(), "f[\"a\"] don't \n");
+    }
+
+    #[test]
+    fn test_surround() {
+        let mut editor = Editor::try_from(Rope::from("a bé c\n")).unwrap();
+        (editor.anchor, editor.head) = (2, 5);
+        editor.surround(')');
+        assert_eq!(editor.text.to_string(), "a (bé) c\n");
+        assert_eq!((editor.anchor, editor.head), (2, 7));
+        editor.flip();
+        editor.surround('*');
+        assert_eq!(editor.text.to_string(), "a *(bé)* c\n");
+        assert_eq!((editor.anchor, editor.head), (9, 2));
+        assert!(editor.modified);
+        editor.undo();
+        assert_eq!(editor.text.to_string(), "a (bé) c\n");
+    }
+
+    #[test]
+    fn test_extend_find() {
+        let mut editor = Editor::try_from(Rope::from("a(bé)c(d\nx)\n")).unwrap();
+        editor.move_to(0);
+        editor.extend_find(')', true, false);
+        assert_eq!((editor.anchor, editor.head), (0, 6));
+        editor.move_to(0);
+        editor.extend_find(')', true, true);
+        assert_eq!((editor.anchor, editor.head), (0, 5));
+        // The search doesn't leave the line.
+        editor.move_to(7);
+        editor.extend_find(')', true, false);
+        assert_eq!((editor.anchor, editor.head), (7, 8));
+        assert!(matches!(editor.message, Some(Err(_))));
+        editor.extend_find('(', false, false);
+        assert_eq!((editor.anchor, editor.head), (7, 1));
+        (editor.anchor, editor.head) = (3, 5);
+        editor.extend_find('(', false, true);
+        assert_eq!((editor.anchor, editor.head), (3, 2));
+    }
+
+    #[test]
+    fn test_word_count() {
+        let mut editor = Editor::try_from(Rope::from("one twö\n  three\n")).unwrap();
+        editor.run_command("wc").unwrap();
+        assert_eq!(
+            editor.message,
+            Some(Ok(String::from(
+                "2 lines, 3 words, 16 characters, 17 bytes"
+            )))
+        );
+        (editor.anchor, editor.head) = (9, 4);
+        editor.run_command("wc").unwrap();
+        assert_eq!(
+            editor.message,
+            Some(Ok(String::from("1 line, 1 word, 4 characters, 5 bytes")))
+        );
+    }
+
+    #[test]
+    fn test_delete_word_before() {
+        let mut editor = Editor::try_from(Rope::from("a\nfoo.bar  baz\n")).unwrap();
+        editor.head = 14;
+        editor.reduce();
+        editor.delete_word_before();
+        assert_eq!(editor.text.to_string(), "a\nfoo.bar  \n");
+        // Whitespace goes along with the word before it.
+        editor.delete_word_before();
+        assert_eq!(editor.text.to_string(), "a\nfoo.\n");
+        editor.delete_word_before();
+        editor.delete_word_before();
+        assert_eq!(editor.text.to_string(), "a\n\n");
+        assert_eq!((editor.anchor, editor.head), (2, 2));
+        // It stops at the start of the line.
+        editor.delete_word_before();
+        assert_eq!(editor.text.to_string(), "a\n\n");
+        assert!(editor.modified);
+    }
+
+    #[test]
+    fn test_global() {
+        let mut editor = Editor::try_from(Rope::from("TODO: a\nb\n// TODO: c\nd/e\nTODO")).unwrap();
+
+        editor.run_command("g/TODO/echo found").unwrap();
+        assert_eq!(editor.message, Some(Ok(String::from("found"))));
+        assert!(!editor.modified);
+
+        editor.run_command("g/nope/delete").unwrap();
+        assert_eq!(editor.message, Some(Err(String::from("Pattern not found"))));
+
+        editor.run_command(r"g/d\/e/d").unwrap();
+        assert_eq!(editor.text, "TODO: a\nb\n// TODO: c\nTODO");
+
+        editor.run_command("global ^TODO delete").unwrap();
+        assert_eq!(editor.text, "b\n// TODO: c\n");
+
+        editor.run_command("g/TODO/delete").unwrap();
+        assert_eq!(editor.text, "b\n");
+        assert_eq!(editor.head, 2);
+        assert!(editor.modified);
+
+        // All of the deletions are undone together.
+        editor.run_command("global ^ delete").unwrap();
+        assert_eq!(editor.text, "");
+        editor.undo();
+        assert_eq!(editor.text, "b\n");
+    }
+
+    #[test]
+    fn test_set_indentation() {
+        let mut editor = Editor::new().unwrap();
+        assert_eq!(editor.indent_unit(), "\t");
+        editor.run_command("set expandtab").unwrap();
+        assert_eq!(editor.indent_unit(), "    ");
+        editor.run_command("set indent-width 2").unwrap();
+        assert_eq!(editor.indent_unit(), "  ");
+        editor.run_command("set expandtab!").unwrap();
+        assert_eq!(editor.indent_unit(), "\t");
+        editor.run_command("set indent-width 0").unwrap();
+        assert!(matches!(editor.message, Some(Err(_))));
+        assert_eq!(editor.indent_width, 2);
+    }
+
+    #[test]
+    fn test_set_config() {
+        let dir = temp_dir("editor-config");
+        fs::write(dir.join("a.txt"), "a\n\tb\n").unwrap();
+        let config = Config {
+            tab_width: Some(4),
+            indent_style: Some(IndentStyle::Space),
+            indent_width: Some(2),
+            scrolloff: Some(0),
+            line_numbers: Some(LineNumbers::Relative),
+            theme: Some(Theme::Light),
+            final_newline: Some(true),
+            auto_pairs: None,
+            list_chars: None,
+        };
+
+        let mut editor = Editor::new().unwrap();
+        editor.pwd = Some(dir.to_path_buf());
+        editor.set_config(config);
+        assert_eq!((editor.tab_width, editor.scrolloff), (4, 0));
+        assert!(editor.line_numbers == LineNumbers::Relative);
+        assert!(editor.expand_tab);
+        assert_eq!(editor.indent_width, 2);
+        // A new buffer starts from the config too, but the file's own tabs win.
+        editor.run_command("e b.txt").unwrap();
+        assert!(editor.expand_tab);
+        assert_eq!(editor.indent_width, 2);
+        assert!(editor.final_newline);
+        editor.run_command("e a.txt").unwrap();
+        assert!(!editor.expand_tab);
+
+        editor.run_command("set tab-width 2").unwrap();
+        assert_eq!(editor.tab_width, 2);
+        editor.run_command("set line-numbers absolute").unwrap();
+        assert!(editor.line_numbers == LineNumbers::Absolute);
+        editor.run_command("set theme").unwrap();
+        assert_eq!(editor.message, Some(Ok(String::from("theme light"))));
+    }
+
+    #[test]
+    fn test_editorconfig() {
+        let dir = temp_dir("editor-editorconfig");
+        fs::write(
+            dir.join(".editorconfig"),
+            "root = true\n[*.txt]\nindent_style = space\nindent_size = 3\ntab_width = 6\n\
+             end_of_line = crlf\ntrim_trailing_whitespace = true\n",
+        )
+        .unwrap();
+        fs::write(dir.join("a.txt"), "\tone  \n\ttwo\t\n").unwrap();
+
+        let mut editor = Editor::open(dir.join("a.txt")).unwrap();
+        assert!(editor.expand_tab);
+        assert_eq!(editor.indent_width, 3);
+        assert_eq!(editor.tab_width, 6);
+        assert_eq!(editor.line_ending, LineEnding::Crlf);
+        assert!(editor.trim_trailing_whitespace);
+
+        editor.head = editor.text.byte_len();
+        editor.save().unwrap();
+        assert_eq!(editor.text, "\tone\n\ttwo\n");
+        assert_eq!(editor.head, editor.text.byte_len());
+        assert_eq!(
+            fs::read_to_string(dir.join("a.txt")).unwrap(),
+            "\tone\r\n\ttwo\r\n"
+        );
+
+        // Each buffer keeps its own tab width.
+        fs::write(dir.join("b.md"), "b\n").unwrap();
+        editor.pwd = Some(dir.to_path_buf());
+        editor.run_command("e b.md").unwrap();
+        assert_eq!(editor.tab_width, 8);
+        editor.run_command("e a.txt").unwrap();
+        assert_eq!(editor.tab_width, 6);
+        editor.run_command("e b.md").unwrap();
+        assert_eq!(editor.tab_width, 8);
+    }
+
+    #[test]
+    fn test_edit_above_viewport() {
+        let text = (0..100).map(|n| n.to_string() + "\n").collect::<String>();
+        let mut editor = Editor::try_from(Rope::from(text)).unwrap();
+        editor.vertical_scroll = 50;
+        editor.head = editor.text.byte_of_line(60);
+        editor.anchor = editor.head;
+
+        // Lines inserted above the viewport push it down along with the cursor.
+        let start = editor.text.byte_of_line(10);
+        editor.apply_edit(start..start, "a\nb\n");
+        assert_eq!(editor.vertical_scroll, 52);
+        assert_eq!(editor.text.line_of_byte(editor.head), 62);
+        assert_eq!(editor.text.line(52), "50");
+
+        // Deleting the lines again moves it back up.
+        editor.apply_edit(start..start + 4, "");
+        assert_eq!(editor.vertical_scroll, 50);
+        assert_eq!(editor.text.line_of_byte(editor.head), 60);
+
+        // Edits on screen don't scroll.
+        let start = editor.text.byte_of_line(55);
+        editor.apply_edit(start..start, "c\n");
+        assert_eq!(editor.vertical_scroll, 50);
+
+        // An edit which reaches into the viewport from above keeps its start on screen.
+        let start = editor.text.byte_of_line(45);
+        let end = editor.text.byte_of_line(52);
+        editor.apply_edit(start..end, "");
+        assert_eq!(editor.vertical_scroll, 45);
+
+        // Undo and redo scroll the same way.
+        let mut editor = Editor::try_from(Rope::from("a\nb\nc\nd\n")).unwrap();
+        editor.insert("x\ny\n");
+        editor.head = editor.text.byte_of_line(5);
+        editor.vertical_scroll = 4;
+        editor.undo();
+        assert_eq!(editor.vertical_scroll, 2);
+        editor.redo();
+        assert_eq!(editor.vertical_scroll, 4);
+    }
+
+    #[test]
+    fn test_move_after_cursor() {
+        let mut editor = Editor::try_from(Rope::from("ab\n")).unwrap();
+        editor.move_after_cursor();
+        assert_eq!((editor.anchor, editor.head), (1, 1));
+        // A forward selection's cursor is the grapheme before its head.
+        editor.anchor = 0;
+        editor.head = 2;
+        editor.move_after_cursor();
+        assert_eq!((editor.anchor, editor.head), (2, 2));
+        editor.head = 3;
+        editor.move_after_cursor();
+        editor.move_after_cursor();
+        assert_eq!((editor.anchor, editor.head), (3, 3));
+    }
+
+    #[test]
+    fn test_open_line() {
+        let mut editor = Editor::try_from(Rope::from("ab\ncd")).unwrap();
+        editor.open_above();
+        assert_eq!(editor.text, "\nab\ncd");
+        assert_eq!((editor.anchor, editor.head), (0, 0));
+        assert!(editor.modified);
+        editor.move_to(5);
+        editor.open_below();
+        assert_eq!(editor.text, "\nab\ncd\n");
+        assert_eq!((editor.anchor, editor.head), (7, 7));
+        editor.open_above();
+        assert_eq!(editor.text, "\nab\ncd\n\n");
+        assert_eq!((editor.anchor, editor.head), (7, 7));
+        editor.move_to(1);
+        editor.open_below();
+        assert_eq!(editor.text, "\nab\n\ncd\n\n");
+        assert_eq!((editor.anchor, editor.head), (4, 4));
+    }
+
+    #[test]
+    fn test_reduce() {
+        let mut editor = Editor::try_from(Rope::from("abcd\nx\nabcd")).unwrap();
+        editor.move_to(3);
+        editor.move_down(1);
+        assert_eq!((editor.head, editor.desired_column), (6, 3));
+        editor.reduce();
+        assert_eq!(
+            (editor.anchor, editor.head, editor.desired_column),
+            (6, 6, 3)
+        );
+
+        editor.anchor = 1;
+        editor.head = 3;
+        editor.reduce();
+        assert_eq!((editor.anchor, editor.head), (3, 3));
+        editor.anchor = 4;
+        editor.head = 2;
+        editor.reduce();
+        assert_eq!((editor.anchor, editor.head), (2, 2));
+
+        editor.anchor = 1;
+        editor.head = 8;
+        editor.reduce_to_anchor();
+        assert_eq!(
+            (editor.anchor, editor.head, editor.desired_column),
+            (1, 1, 1)
+        );
+        editor.anchor = 10;
+        editor.head = 2;
+        editor.reduce_to_anchor();
+        assert_eq!(
+            (editor.anchor, editor.head, editor.desired_column),
+            (10, 10, 3)
+        );
+    }
+
+    #[test]
+    fn test_toggle_comment() {
+        let mut editor = Editor::try_from(Rope::from("a\n\n  b\nc\n")).unwrap();
+        editor.anchor = 0;
+        editor.head = 7;
+        editor.toggle_comment();
+        assert_eq!(editor.text, "// a\n\n  // b\nc\n");
+        assert_eq!((editor.anchor, editor.head), (0, 13));
+        assert!(editor.modified);
+        editor.toggle_comment();
+        assert_eq!(editor.text, "a\n\n  b\nc\n");
+        assert_eq!((editor.anchor, editor.head), (0, 7));
+        // Lines which aren't all commented get commented.
+        editor.text = Rope::from("# a\nb\n");
+        editor.comment_token = String::from("#");
+        editor.anchor = 0;
+        editor.head = 6;
+        editor.toggle_comment();
+        assert_eq!(editor.text, "# # a\n# b\n");
+        editor.run_command("set comment-token").unwrap();
+        assert_eq!(editor.message, Some(Ok(String::from("comment-token #"))));
+        editor.undo();
+        assert_eq!(editor.text, "# a\nb\n");
+    }
+
+    #[test]
+    fn test_join_lines() {
+        let mut editor = Editor::try_from(Rope::from("ab\n  cd\n\tef\ngh")).unwrap();
+        editor.join_lines();
+        assert_eq!(editor.text, "ab cd\n\tef\ngh");
+        assert_eq!((editor.anchor, editor.head), (2, 2));
+        assert!(editor.modified);
+        // Every line in the selection is joined.
+        editor.anchor = 0;
+        editor.head = 11;
+        editor.join_lines();
+        assert_eq!(editor.text, "ab cd ef gh");
+        assert_eq!((editor.anchor, editor.head), (8, 8));
+        // Nothing to join on the last line.
+        editor.join_lines();
+        assert_eq!(editor.text, "ab cd ef gh");
+        editor.undo();
+        assert_eq!(editor.text, "ab cd\n\tef\ngh");
+    }
+
+    #[test]
+    fn test_select_line() {
+        let mut editor = Editor::try_from(Rope::from("ab\ncd\nef")).unwrap();
+        let selection = |editor: &Editor| (editor.anchor, editor.head);
+        editor.move_to(4);
+        editor.select_line();
+        assert_eq!(selection(&editor), (3, 6));
+        editor.select_line();
+        assert_eq!(selection(&editor), (3, 8));
+        // The last line has no terminator, so there's nothing more to select.
+        editor.select_line();
+        assert_eq!(selection(&editor), (3, 8));
+
+        // A partial selection is replaced by its cursor's line.
+        editor.anchor = 1;
+        editor.head = 4;
+        editor.select_line();
+        assert_eq!(selection(&editor), (3, 6));
+
+        let mut editor = Editor::try_from(Rope::from("ab\n")).unwrap();
+        editor.select_line();
+        assert_eq!(selection(&editor), (0, 3));
+        editor.select_line();
+        assert_eq!(selection(&editor), (0, 3));
+    }
+
+    #[test]
+    fn test_select_all() {
+        let mut editor = Editor::try_from(Rope::from("ab\ncd\n")).unwrap();
+        editor.copy_selection_below();
+        editor.select_all();
+        assert_eq!((editor.anchor, editor.head), (0, 6));
+        assert!(editor.selections.is_empty());
+        editor.delete();
+        assert_eq!(editor.text, "");
+        assert_eq!(editor.register, "ab\ncd\n");
+    }
+
+    #[test]
+    fn test_yank_paste() {
+        let mut editor = Editor::try_from(Rope::from("foo bar\n")).unwrap();
+        editor.paste_after();
+        assert_eq!(editor.message, Some(Err(String::from("Register is empty"))));
+
+        editor.head = 4;
+        editor.yank();
+        assert_eq!(editor.register, "foo ");
+
+        editor.paste_before();
+        assert_eq!(editor.text, "foo foo bar\n");
+        assert_eq!((editor.anchor, editor.head), (0, 4));
+        editor.paste_after();
+        assert_eq!(editor.text, "foo foo foo bar\n");
+        assert_eq!((editor.anchor, editor.head), (4, 8));
+
+        // Deleting cuts into the register.
+        editor.anchor = 12;
+        editor.head = 15;
+        editor.delete();
+        assert_eq!(editor.text, "foo foo foo \n");
+        assert_eq!(editor.register, "bar");
+        editor.paste_before();
+        assert_eq!(editor.text, "foo foo foo bar\n");
+
+        editor.undo();
+        assert_eq!(editor.text, "foo foo foo \n");
+    }
+
+    #[test]
+    fn test_system_clipboard() {
+        let mut editor = Editor::try_from(Rope::from("héllo\nwörld\n")).unwrap();
+        editor.head = editor.text.byte_len();
+        editor.yank();
+        assert_eq!(editor.pending_clipboard, None);
+
+        editor.run_command("set clipboard system").unwrap();
+        assert_eq!(editor.clipboard, Clipboard::System);
+        editor.yank();
+        assert_eq!(editor.pending_clipboard.as_deref(), Some("héllo\nwörld\n"));
+
+        editor.run_command("set clipboard primary").unwrap();
+        assert_eq!(
+            editor.message,
+            Some(Err(String::from(
+                "Invalid value for 'clipboard': 'primary'"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_filetype() {
+        let dir = temp_dir("editor-filetype");
+        let mut editor = Editor::open(dir.join("main.rs")).unwrap();
+        assert_eq!(editor.filetype.as_deref(), Some("rust"));
+        assert_eq!(editor.comment_token, "//");
+
+        editor.run_command("set filetype python").unwrap();
+        assert_eq!(editor.comment_token, "#");
+        editor.run_command("set filetype markdown").unwrap();
+        assert_eq!(editor.filetype.as_deref(), Some("markdown"));
+        editor.run_command("set filetype").unwrap();
+        assert_eq!(editor.message, Some(Ok(String::from("filetype markdown"))));
+
+        let editor = Editor::open(dir.join("notes")).unwrap();
+        assert_eq!(editor.filetype, None);
+    }
+
+    #[test]
+    fn test_dirty_range() {
+        let mut editor = Editor::try_from(Rope::from("aaaa\nbbbb\ncccc\n")).unwrap();
+        assert_eq!(editor.take_dirty(), None);
+
+        editor.apply_edit(5..5, "xx");
+        editor.apply_edit(12..13, "y");
+        assert_eq!(editor.text, "aaaa\nxxbbbb\nyccc\n");
+        assert_eq!(editor.take_dirty(), Some(5..13));
+        assert_eq!(editor.take_dirty(), None);
+
+        // Inserting before the dirty range shifts it along with the text.
+        editor.apply_edit(7..9, "");
+        editor.apply_edit(0..0, "zzz");
+        assert_eq!(editor.text, "zzzaaaa\nxxbb\nyccc\n");
+        assert_eq!(editor.take_dirty(), Some(0..10));
+
+        // Deleting across the dirty range clamps it to the deletion.
+        editor.apply_edit(9..10, "w");
+        editor.apply_edit(2..12, "");
+        assert_eq!(editor.text, "zz\nyccc\n");
+        assert_eq!(editor.take_dirty(), Some(2..2));
+
+        // Undo marks the bytes which differ between the two texts.
+        editor.anchor = 3;
+        editor.head = 4;
+        editor.delete();
+        editor.take_dirty();
+        editor.undo();
+        assert_eq!(editor.text, "zz\nyccc\n");
+        assert_eq!(editor.take_dirty(), Some(3..4));
+    }
+
+    #[test]
+    fn test_highlights() {
+        let mut editor = Editor::try_from(Rope::from("let a\nb\nc\n")).unwrap();
+        editor.set_filetype(Some(String::from("rust")));
+        let spans = |editor: &Editor| {
+            (0..editor.text.line_len())
+                .map(|line_index| editor.highlights(line_index).len())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(spans(&editor), [1, 0, 0]);
+        editor.apply_edit(8..8, "fn ");
+        assert_eq!(spans(&editor), [1, 0, 1]);
+        // Lines after an edit keep their spans, moved along with them.
+        editor.apply_edit(0..0, "x\n");
+        assert_eq!(spans(&editor), [0, 1, 0, 1]);
+        editor.apply_edit(0..6, "");
+        assert_eq!(spans(&editor), [0, 0, 1]);
+        // New rules apply to every line.
+        editor.run_command("highlight red [a-z]").unwrap();
+        assert_eq!(spans(&editor), [1, 1, 2]);
+    }
+
+    #[test]
+    fn test_search() {
+        let mut editor = Editor::try_from(Rope::from("foo bar\nbar foo\n")).unwrap();
+        let search = |editor: &mut Editor, query| {
+            editor.mode = Mode::Search;
+            editor.command = Rope::from(query);
+            editor.execute_search();
+        };
+
+        search(&mut editor, "bar");
+        assert_eq!((editor.anchor, editor.head), (4, 7));
+        assert!(editor.mode == Mode::Normal);
+        assert_eq!(editor.last_search.as_deref(), Some("bar"));
+        search(&mut editor, "bar");
+        assert_eq!((editor.anchor, editor.head), (8, 11));
+        // Wraps around to the start.
+        search(&mut editor, "foo");
+        assert_eq!((editor.anchor, editor.head), (12, 15));
+        search(&mut editor, "foo");
+        assert_eq!((editor.anchor, editor.head), (0, 3));
+        // A lone match is found again from inside it.
+        search(&mut editor, "\nbar");
+        assert_eq!((editor.anchor, editor.head), (7, 11));
+        search(&mut editor, "\nbar");
+        assert_eq!((editor.anchor, editor.head), (7, 11));
+
+        search(&mut editor, "baz");
+        assert_eq!(editor.message, Some(Err(String::from("Pattern not found"))));
+        assert_eq!((editor.anchor, editor.head), (7, 11));
+        assert_eq!(editor.last_search.as_deref(), Some("baz"));
+    }
+
+    #[test]
+    fn test_highlight_rules() {
+        let mut editor = Editor::new().unwrap();
+        assert!(editor.highlight_rules.is_empty());
+        editor.run_command("set filetype rust").unwrap();
+        let rust_rules = editor.highlight_rules.len();
+        assert!(rust_rules > 0);
+
+        editor.run_command("highlight red 'TODO|FIXME'").unwrap();
+        assert_eq!(editor.highlight_rules.len(), rust_rules + 1);
+        editor.run_command("hi '#ff0000' '('").unwrap();
+        assert!(matches!(editor.message, Some(Err(_))));
+        editor.run_command("hi reddish x").unwrap();
+        assert_eq!(
+            editor.message,
+            Some(Err(String::from("Invalid color 'reddish'")))
+        );
+        assert_eq!(editor.highlight_rules.len(), rust_rules + 1);
+
+        // Changing the file type starts over with its built-in rules.
+        editor.run_command("set filetype rust").unwrap();
+        assert_eq!(editor.highlight_rules.len(), rust_rules);
+    }
+
+    #[test]
+    fn test_search_next_prev() {
+        let mut editor = Editor::try_from(Rope::from("ab ab\nab\n")).unwrap();
+        editor.search_next();
+        assert_eq!(
+            editor.message,
+            Some(Err(String::from("No previous search")))
+        );
+        editor.search_prev();
+        assert_eq!(
+            editor.message,
+            Some(Err(String::from("No previous search")))
+        );
+
+        editor.last_search = Some(String::from("ab"));
+        editor.search_next();
+        assert_eq!((editor.anchor, editor.head), (3, 5));
+        editor.search_next();
+        assert_eq!((editor.anchor, editor.head), (6, 8));
+        editor.search_next();
+        assert_eq!((editor.anchor, editor.head), (0, 2));
+        // Backward from the first match wraps to the last.
+        editor.search_prev();
+        assert_eq!((editor.anchor, editor.head), (6, 8));
+        editor.search_prev();
+        assert_eq!((editor.anchor, editor.head), (3, 5));
+
+        editor.last_search = Some(String::from("x"));
+        editor.search_prev();
+        assert_eq!(editor.message, Some(Err(String::from("Pattern not found"))));
+    }
+
+    #[test]
+    fn test_search_flags() {
+        let mut editor = Editor::try_from(Rope::from("Foo foo food\n")).unwrap();
+        let search = |editor: &mut Editor, query| {
+            editor.mode = Mode::Search;
+            editor.command = Rope::from(query);
+            editor.execute_search();
+            editor.anchor
+        };
+        // Smart case ignores case until the query has an uppercase letter.
+        editor.move_to(0);
+        assert_eq!([(); 3].map(|()| search(&mut editor, "foo")), [4, 8, 0]);
+        assert_eq!(search(&mut editor, "Foo"), 0);
+        assert_eq!(search(&mut editor, "Foo"), 0);
+        editor.run_command("set nosmart-case").unwrap();
+        assert_eq!([(); 3].map(|()| search(&mut editor, "foo")), [4, 8, 4]);
+        editor.run_command("set whole-word").unwrap();
+        assert_eq!([(); 2].map(|()| search(&mut editor, "foo")), [4, 4]);
+        editor.run_command("set ignore-case").unwrap();
+        assert_eq!([(); 2].map(|()| search(&mut editor, "FOO")), [0, 4]);
+    }
+
+    #[test]
+    fn test_rotate_selections() {
+        let mut editor = Editor::try_from(Rope::from("one two three\n")).unwrap();
+        let selections = |editor: &Editor| {
+            let mut selections = editor
+                .selections
+                .iter()
+                .map(|selection| (selection.anchor, selection.head, false))
+                .collect::<Vec<_>>();
+            selections.push((editor.anchor, editor.head, true));
+            selections.sort_unstable();
+            selections
+        };
+        editor.selections = vec![
+            Selection {
+                anchor: 0,
+                head: 3,
+                desired_column: 3,
+            },
+            Selection {
+                anchor: 4,
+                head: 7,
+                desired_column: 7,
+            },
+        ];
+        (editor.anchor, editor.head) = (8, 13);
+        editor.rotate_selections(true);
+        assert_eq!((editor.anchor, editor.head), (0, 3));
+        editor.rotate_selections(true);
+        assert_eq!((editor.anchor, editor.head), (4, 7));
+        editor.rotate_selections(false);
+        editor.rotate_selections(false);
+        assert_eq!((editor.anchor, editor.head), (8, 13));
+
+        // The selections follow their new text as it changes length.
+        editor.rotate_contents(true);
+        assert_eq!(editor.text, "three one two\n");
+        assert_eq!(
+            selections(&editor),
+            [(0, 5, false), (6, 9, false), (10, 13, true)]
+        );
+        editor.rotate_contents(false);
+        assert_eq!(editor.text, "one two three\n");
+        assert_eq!(
+            selections(&editor),
+            [(0, 3, false), (4, 7, false), (8, 13, true)]
+        );
+        // Each rotation is a single undo step.
+        editor.undo();
+        assert_eq!(editor.text, "three one two\n");
+    }
+
+    #[test]
+    fn test_search_in_selection() {
+        let mut editor = Editor::try_from(Rope::from("ab ab ab ab\n")).unwrap();
+        let search = |editor: &mut Editor, query: &str| {
+            editor.command = Rope::from(query);
+            editor.execute_search();
+            (editor.anchor, editor.head)
+        };
+        editor.run_command("set search-in-selection").unwrap();
+        editor.anchor = 2;
+        editor.head = 9;
+        assert_eq!(search(&mut editor, "ab"), (3, 5));
+        editor.search_next();
+        assert_eq!((editor.anchor, editor.head), (6, 8));
+        // Wraps around within the original selection.
+        editor.search_next();
+        assert_eq!((editor.anchor, editor.head), (3, 5));
+        editor.search_prev();
+        assert_eq!((editor.anchor, editor.head), (6, 8));
+
+        // Without the option, the whole text is searched again.
+        editor.run_command("set nosearch-in-selection").unwrap();
+        editor.search_next();
+        assert_eq!((editor.anchor, editor.head), (9, 11));
+    }
+
+    #[test]
+    fn test_session() {
+        let dir = temp_dir("editor-session");
+        fs::write(dir.join("a.txt"), "one\ntwo\nthree\n").unwrap();
+        let session_path = dir.join("Session.blue");
+
+        let mut editor = Editor::open(dir.join("a.txt")).unwrap();
+        editor.pwd = Some(dir.to_path_buf());
+        editor.anchor = 4;
+        editor.head = 7;
+        editor.vertical_scroll = 1;
+        // The path is relative to the working directory.
+        editor.run_command("mksession Session.blue").unwrap();
+        assert_eq!(
+            editor.message,
+            Some(Ok(format!("Saved session to '{session_path}'")))
+        );
+        // Failing to write it is only an error message.
+        editor
+            .run_command("mksession missing/Session.blue")
+            .unwrap();
+        assert!(matches!(editor.message, Some(Err(_))));
+
+        let restored = Editor::from_session(&Session::load(&session_path).unwrap()).unwrap();
+        assert_eq!(restored.path, editor.path);
+        assert_eq!(restored.text, "one\ntwo\nthree\n");
+        assert_eq!((restored.anchor, restored.head), (4, 7));
+        assert_eq!(restored.vertical_scroll, 1);
+
+        // The selection is clamped if the file shrank since.
+        fs::write(dir.join("a.txt"), "one\n").unwrap();
+        let restored = Editor::from_session(&Session::load(&session_path).unwrap()).unwrap();
+        assert_eq!((restored.anchor, restored.head), (4, 4));
+        assert_eq!(restored.vertical_scroll, 0);
+    }
+
+    #[test]
+    fn test_open_buffer() {
+        let dir = temp_dir("editor-open-buffer");
+        fs::write(dir.join("a.txt"), "one\n").unwrap();
+        fs::write(dir.join("b.txt"), "two\n").unwrap();
+
+        let mut editor = Editor::open(dir.join("a.txt")).unwrap();
+        editor.open_buffer(dir.join("b.txt")).unwrap();
+        editor.open_buffer(dir.join("c.txt")).unwrap();
+        // The first file stays current.
+        assert_eq!(editor.text, "one\n");
+        assert_eq!((editor.buffer_index(), editor.buffer_count()), (0, 3));
+        editor.buffer_next();
+        assert_eq!(editor.text, "two\n");
+        // A missing file opens empty, to be saved at its path.
+        editor.buffer_next();
+        assert_eq!(editor.path, Some(dir.join("c.txt")));
+        assert_eq!(editor.text, "");
+
+        // Files which are already open, however they're named, don't open twice.
+        editor.open_buffer(dir.join("a.txt")).unwrap();
+        editor.open_buffer(dir.join("./b.txt")).unwrap();
+        editor.open_buffer(dir.join("c.txt")).unwrap();
+        assert_eq!(editor.buffer_count(), 3);
+    }
+
+    #[test]
+    fn test_edit() {
+        let dir = temp_dir("editor-edit");
+        fs::write(dir.join("a.txt"), "one\ntwo\n").unwrap();
+        fs::write(dir.join("b.txt"), "three\n").unwrap();
+
+        let mut editor = Editor::open(dir.join("a.txt")).unwrap();
+        editor.pwd = Some(dir.to_path_buf());
+        editor.move_to(4);
+        editor.vertical_scroll = 1;
+        editor.insert("x");
+        assert_eq!(editor.text, "one\ntxwo\n");
+        let selection = (editor.anchor, editor.head);
+
+        // Another file opens in a new buffer, and the first keeps its changes.
+        editor.run_command("e b.txt").unwrap();
+        assert_eq!(
+            editor.path,
+            Some(dir.join("b.txt").canonicalize_utf8().unwrap())
+        );
+        assert_eq!(editor.text, "three\n");
+        assert_eq!(
+            (editor.anchor, editor.head, editor.vertical_scroll),
+            (0, 0, 0)
+        );
+        assert!(!editor.modified);
+        assert_eq!((editor.buffer_index(), editor.buffer_count()), (1, 2));
+        // The other file's history doesn't come along.
+        editor.undo();
+        assert_eq!(editor.text, "three\n");
+        editor.run_command("buffers").unwrap();
+        assert_eq!(editor.message, Some(Ok(String::from("a.txt* [b.txt]"))));
+
+        // Editing a file which is already open switches to its buffer.
+        editor.run_command("e a.txt").unwrap();
+        assert_eq!(editor.buffer_count(), 2);
+        assert_eq!(editor.text, "one\ntxwo\n");
+        assert_eq!((editor.anchor, editor.head), selection);
+        assert_eq!(editor.vertical_scroll, 1);
+        assert_eq!(editor.buffer_index(), 0);
+        editor.run_command("q").unwrap();
+        assert_eq!(editor.message, Some(Err(String::from("Unsaved changes"))));
+
+        // Quitting everything waits until every buffer is saved, unless it's forced.
+        editor.run_command("qa").unwrap();
+        assert_eq!(
+            editor.message,
+            Some(Err(String::from("Unsaved changes in a.txt")))
+        );
+        editor.buffer_next();
+        editor.insert("x");
+        editor.run_command("qa").unwrap();
+        assert_eq!(
+            editor.message,
+            Some(Err(String::from("Unsaved changes in a.txt, b.txt")))
+        );
+        assert_eq!(editor.exit_code, None);
+        editor.run_command("qa! 2").unwrap();
+        assert_eq!(editor.exit_code, Some(ExitCode::from(2)));
+        editor.exit_code = None;
+        editor.undo();
+        editor.buffer_prev();
+
+        // A new file opens empty in place of the current one.
+        editor.run_command("edit! c.txt").unwrap();
+        assert_eq!(editor.path, Some(dir.join("c.txt")));
+        assert_eq!(editor.text, "");
+        assert_eq!((editor.buffer_index(), editor.buffer_count()), (0, 2));
+        editor.run_command("buffer-prev").unwrap();
+        assert_eq!(editor.text, "three\n");
+        // Forcing it reloads the file instead.
+        editor.insert("x");
+        editor.run_command("e! b.txt").unwrap();
+        assert_eq!(editor.text, "three\n");
+        assert!(!editor.modified);
+        assert_eq!((editor.buffer_index(), editor.buffer_count()), (1, 2));
+
+        // Quitting closes buffers until the last one.
+        editor.run_command("q").unwrap();
+        assert_eq!(editor.path, Some(dir.join("c.txt")));
+        assert_eq!(editor.buffer_count(), 1);
+        assert_eq!(editor.exit_code, None);
+        editor.run_command("q").unwrap();
+        assert_eq!(editor.exit_code, Some(ExitCode::SUCCESS));
+    }
+
+    #[test]
+    fn test_save_as() {
+        let dir = temp_dir("editor-save-as");
+
+        let mut editor = Editor::new().unwrap();
+        editor.pwd = Some(dir.to_path_buf());
+        editor.insert("hi");
+        editor.run_command("w").unwrap();
+        assert_eq!(editor.message, Some(Err(String::from("No file name"))));
+        assert!(editor.modified);
+        // Quitting only happens once it's saved.
+        editor.message = None;
+        editor.run_command("wq").unwrap();
+        assert_eq!(editor.message, Some(Err(String::from("No file name"))));
+        assert_eq!(editor.exit_code, None);
+
+        editor.run_command("w a/b.txt").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("a/b.txt")).unwrap(), "hi");
+        assert_eq!(editor.path, Some(dir.join("a/b.txt")));
+        assert!(!editor.modified);
+        // Later saves go to the new path.
+        editor.insert("!");
+        editor.run_command("w").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("a/b.txt")).unwrap(), "hi!");
+
+        // `a/b.txt` is a file, so it can't have a file inside it.
+        editor.message = None;
+        editor.run_command("w a/b.txt/c.txt").unwrap();
+        assert!(matches!(editor.message, Some(Err(_))));
+        assert_eq!(editor.path, Some(dir.join("a/b.txt")));
+    }
+
+    #[test]
+    fn test_final_newline() {
+        let dir = temp_dir("editor-final-newline");
+        let path = dir.join("a.txt");
+
+        // A file without a final newline is saved without one.
+        fs::write(&path, "one").unwrap();
+        let mut editor = Editor::open(&path).unwrap();
+        assert!(!editor.final_newline);
+        (editor.anchor, editor.head) = (2, 2);
+        editor.insert("\n");
+        editor.run_command("w").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "on\ne");
+
+        // Once asked for, it's added, and extras are removed.
+        editor.run_command("set final-newline").unwrap();
+        editor.run_command("w").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "on\ne\n");
+        assert_eq!(editor.text.to_string(), "on\ne\n");
+        assert_eq!((editor.anchor, editor.head), (3, 3));
+        (editor.anchor, editor.head) = (5, 5);
+        editor.insert("\n\n");
+        editor.run_command("w").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "on\ne\n");
+        assert_eq!((editor.anchor, editor.head), (5, 5));
+        editor.undo();
+        assert_eq!(editor.text.to_string(), "on\ne\n\n\n");
+
+        // A file which ends with a newline keeps it.
+        fs::write(&path, "one\n").unwrap();
+        let mut editor = Editor::open(&path).unwrap();
+        assert!(editor.final_newline);
+        editor.move_to(3);
+        editor.delete();
+        editor.run_command("w").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\n");
+    }
+
+    #[test]
+    fn test_line_ending() {
+        let dir = temp_dir("editor-line-ending");
+        let path = dir.join("a.txt");
+        fs::write(&path, "one\r\ntwo\r\nthree\n").unwrap();
+
+        let mut editor = Editor::open(&path).unwrap();
+        assert_eq!(editor.line_ending, LineEnding::Crlf);
+        assert_eq!(editor.text.to_string(), "one\ntwo\nthree\n");
+        assert_eq!(editor.text.line(0).byte_len(), 3);
+        (editor.anchor, editor.head) = (3, 3);
+        editor.insert("!");
+        editor.run_command("w").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "one!\r\ntwo\r\nthree\r\n"
+        );
+
+        editor.run_command("set fileformat").unwrap();
+        assert_eq!(editor.message, Some(Ok(String::from("fileformat dos"))));
+        editor.run_command("set fileformat unix").unwrap();
+        editor.run_command("w").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one!\ntwo\nthree\n");
+
+        fs::write(&path, "uno\r\n").unwrap();
+        editor.run_command("reload").unwrap();
+        assert_eq!(editor.line_ending, LineEnding::Crlf);
+        assert_eq!(editor.text.to_string(), "uno\n");
+    }
+
+    #[test]
+    fn test_reload() {
+        let dir = temp_dir("editor-reload");
+        let path = dir.join("a.txt");
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+
+        let mut editor = Editor::open(&path).unwrap();
+        editor.move_to(10);
+        editor.insert("x");
+        fs::write(&path, "uno\n").unwrap();
+        editor.run_command("reload").unwrap();
+        assert_eq!(editor.text, "uno\n");
+        assert!(!editor.modified);
+        // The file shrank, so the selection is clamped to its end.
+        assert_eq!((editor.anchor, editor.head), (4, 4));
+        assert!(editor.hunks().is_empty());
+
+        editor.undo();
+        assert_eq!(editor.text, "one\ntwo\nthrxee\n");
+        assert!(editor.modified);
+        editor.run_command("e!").unwrap();
+        assert_eq!(editor.text, "uno\n");
+        assert!(!editor.modified);
+
+        let mut editor = Editor::new().unwrap();
+        editor.run_command("reload").unwrap();
+        assert_eq!(editor.message, Some(Err(String::from("No file name"))));
+    }
+
+    #[test]
+    fn test_substitute() {
+        let mut editor = Editor::try_from(Rope::from("a-b a-b\na-b\n")).unwrap();
+        editor.run_command("s x y").unwrap();
+        assert_eq!(editor.message, Some(Err(String::from("Pattern not found"))));
+        assert!(!editor.modified);
+
+        // With only the cursor selected, the whole text is searched.
+        editor.move_to(0);
+        editor.run_command("s b B").unwrap();
+        assert_eq!(editor.text, "a-B a-B\na-B\n");
+        editor.undo();
+
+        // Only within the selection, which grows with the longer replacement.
+        editor.anchor = 2;
+        editor.head = 11;
+        editor.run_command("s '(\\w)-(\\w)' '$2 <- $1'").unwrap();
+        assert_eq!(editor.text, "a-b b <- a\nb <- a\n");
+        assert_eq!((editor.anchor, editor.head), (2, 17));
+        assert_eq!(
+            editor.message,
+            Some(Ok(String::from("Replaced 2 occurrences")))
+        );
+        assert!(editor.modified);
+
+        // Matches must lie entirely inside the selection, and anchors see the text around it.
+        editor.anchor = 1;
+        editor.head = 7;
+        editor.run_command("s 'a-b' x").unwrap();
+        assert_eq!(editor.message, Some(Err(String::from("Pattern not found"))));
+        editor.run_command("s '^\\w' x").unwrap();
+        assert_eq!(editor.message, Some(Err(String::from("Pattern not found"))));
+        editor.run_command("s '\\bb' B").unwrap();
+        assert_eq!(editor.text, "a-B B <- a\nb <- a\n");
+        editor.undo();
+
+        // Everywhere when the selection is empty.
+        editor.anchor = 18;
+        editor.head = 18;
+        editor.run_command("substitute ' <- ' ''").unwrap();
+        assert_eq!(editor.text, "a-b ba\nba\n");
+        assert_eq!((editor.anchor, editor.head), (10, 10));
+
+        editor.undo();
+        assert_eq!(editor.text, "a-b b <- a\nb <- a\n");
+    }
+
+    #[test]
+    fn test_substitute_confirm() {
+        let mut editor = Editor::try_from(Rope::from("a a a a\n")).unwrap();
+        let selection = |editor: &Editor| (editor.anchor, editor.head);
+        editor.run_command("s --confirm a bb").unwrap();
+        assert!(editor.mode == Mode::Confirm);
+        assert_eq!(selection(&editor), (0, 1));
+        editor.confirm_replace();
+        assert_eq!(editor.text, "bb a a a\n");
+        assert_eq!(selection(&editor), (3, 4));
+        editor.confirm_skip();
+        assert_eq!(selection(&editor), (5, 6));
+        editor.confirm_replace();
+        assert_eq!(editor.text, "bb a bb a\n");
+        editor.stop_confirm();
+        assert!(editor.mode == Mode::Normal);
+        assert!(editor.confirm.is_none());
+        assert_eq!(
+            editor.message,
+            Some(Ok(String::from("Replaced 2 occurrences")))
+        );
+        // All the replacements are undone together.
+        editor.undo();
+        assert_eq!(editor.text, "a a a a\n");
+
+        editor.anchor = 0;
+        editor.head = 0;
+        editor.run_command("s -c a b").unwrap();
+        editor.confirm_skip();
+        editor.confirm_replace_all();
+        assert_eq!(editor.text, "a b b b\n");
+        assert!(editor.mode == Mode::Normal);
+
+        editor.run_command("s -c x y").unwrap();
+        assert!(editor.mode == Mode::Normal);
+        assert_eq!(editor.message, Some(Err(String::from("Pattern not found"))));
+    }
+
+    #[test]
+    fn test_repeat_substitute() {
+        let mut editor = Editor::try_from(Rope::from("a a\na a\na a\n")).unwrap();
+        editor.run_command("&").unwrap();
+        assert_eq!(
+            editor.message,
+            Some(Err(String::from("No previous substitution")))
+        );
+
+        editor.anchor = 0;
+        editor.head = 3;
+        editor.run_command("s a b").unwrap();
+        assert_eq!(editor.text, "b b\na a\na a\n");
+        // Only on the cursor's line.
+        editor.move_to(editor.text.byte_of_line(1));
+        editor.run_command("&").unwrap();
+        assert_eq!(editor.text, "b b\nb b\na a\n");
+        editor.run_command("3&&").unwrap();
+        assert_eq!(editor.text, "b b\nb b\nb b\n");
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let dir = temp_dir("editor-undo");
+        fs::write(dir.join("a.txt"), "x\n").unwrap();
+        let mut editor = Editor::open(dir.join("a.txt")).unwrap();
+
+        editor.undo();
+        assert_eq!(editor.message, Some(Err(String::from("Nothing to undo"))));
+
+        for char in "hello".chars() {
+            editor.insert(&char.to_string());
+        }
+        editor.insert("\t\t");
+        assert_eq!(editor.text, "hello\t\tx\n");
+
+        editor.undo();
+        assert_eq!(editor.text, "hellox\n");
+        assert_eq!(editor.head, 5);
+        editor.undo();
+        assert_eq!(editor.text, "x\n");
+        assert_eq!(editor.head, 0);
+        assert!(!editor.modified);
+
+        editor.redo();
+        assert_eq!(editor.text, "hellox\n");
+        assert!(editor.modified);
+        editor.save().unwrap();
+        assert!(!editor.modified);
+
+        // Undoing restores the selection from before the edit.
+        editor.anchor = 1;
+        editor.head = 4;
+        editor.delete();
+        assert_eq!(editor.text, "hox\n");
+        editor.undo();
+        assert_eq!(editor.text, "hellox\n");
+        assert_eq!((editor.anchor, editor.head), (1, 4));
+        assert!(!editor.modified);
+
+        editor.undo();
+        assert_eq!(editor.text, "x\n");
+        assert!(editor.modified);
+        editor.redo();
+        assert!(!editor.modified);
+
+        // A new edit discards the redo stack.
+        editor.insert("!");
+        editor.redo();
+        assert_eq!(editor.message, Some(Err(String::from("Nothing to redo"))));
+    }
+
+    #[test]
+    fn test_undofile() {
+        let dir = temp_dir("editor-undofile");
+        fs::write(dir.join("a.txt"), "x\n").unwrap();
+        let open = || {
+            let mut editor = Editor::open(dir.join("a.txt")).unwrap();
+            editor.undo_dir = Some(dir.join("undo"));
+            editor.load_history();
+            editor
+        };
+        let mut editor = open();
+        editor.run_command("set undofile").unwrap();
+        editor.insert("hé");
+        (editor.anchor, editor.head) = (0, 0);
+        editor.insert("\n");
+        editor.save().unwrap();
+        editor.undo();
+        editor.save().unwrap();
+        assert_eq!(editor.text, "héx\n");
+
+        // Both stacks come back, starting saved.
+        let mut editor = open();
+        assert_eq!(editor.text, "héx\n");
+        assert!(!editor.modified);
+        editor.redo();
+        assert_eq!(editor.text, "\nhéx\n");
+        editor.undo();
+        editor.undo();
+        assert_eq!(editor.text, "x\n");
+        assert_eq!((editor.anchor, editor.head), (0, 0));
+        assert!(editor.modified);
+        editor.undo();
+        assert_eq!(editor.message, Some(Err(String::from("Nothing to undo"))));
+
+        // Exiting keeps it too, without saving.
+        editor.redo();
+        fs::remove_dir_all(dir.join("undo")).unwrap();
+        editor.run_command("set undofile").unwrap();
+        editor.run_command("q").unwrap();
+        let mut editor = open();
+        editor.redo();
+        assert_eq!(editor.text, "\nhéx\n");
+
+        // Paths which only differ in where their slashes and percent signs are get their own.
+        for dir_name in ["b%", "b"] {
+            fs::create_dir_all(dir.join(dir_name)).unwrap();
+        }
+        fs::write(dir.join("b%/c"), "").unwrap();
+        fs::write(dir.join("b/%c"), "").unwrap();
+        let undo_dir = dir.join("undo");
+        assert_ne!(
+            undo_file(&undo_dir, &dir.join("b%/c")),
+            undo_file(&undo_dir, &dir.join("b/%c"))
+        );
+
+        // The history is left behind once the file changes without it.
+        fs::write(dir.join("a.txt"), "héy\n").unwrap();
+        let mut editor = open();
+        editor.undo();
+        assert_eq!(editor.text, "héy\n");
+        assert_eq!(editor.message, Some(Err(String::from("Nothing to undo"))));
+    }
+
+    #[test]
+    fn test_cquit() {
+        let mut editor = Editor::new().unwrap();
+        editor.insert("unsaved");
+
+        editor.run_command("cq 0").unwrap();
+        assert!(matches!(editor.message, Some(Err(_))));
+        assert_eq!(editor.exit_code, None);
+
+        editor.run_command("cq").unwrap();
+        assert_eq!(editor.exit_code, Some(ExitCode::FAILURE));
+
+        editor.run_command("cquit 42").unwrap();
+        assert_eq!(editor.exit_code, Some(ExitCode::from(42)));
+    }
+
+    #[test]
+    fn test_paste_reindent() {
+        let mut editor = Editor::try_from(Rope::from("fn main() {\n  foo();\n}\n")).unwrap();
+        editor.register = String::from("        if x {\n\n            bar();\n        }\n");
+        editor.expand_tab = true;
+        editor.anchor = 12;
+        editor.head = 21;
+        editor.paste_reindent();
+        assert_eq!(
+            editor.text,
+            "fn main() {\n  foo();\n  if x {\n\n      bar();\n  }\n}\n"
+        );
+        assert_eq!((editor.anchor, editor.head), (21, 48));
+        // The register itself is left as it was.
+        assert_eq!(
+            editor.register,
+            "        if x {\n\n            bar();\n        }\n"
+        );
+        editor.undo();
+        assert_eq!(editor.text, "fn main() {\n  foo();\n}\n");
+
+        // With tabs, whole tab stops of indentation become tabs.
+        editor.expand_tab = false;
+        editor.tab_width = 2;
+        editor.paste_reindent();
+        assert_eq!(
+            editor.text,
+            "fn main() {\n  foo();\n\tif x {\n\n\t\t\tbar();\n\t}\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_indent_dedent() {
+        let mut editor = Editor::try_from(Rope::from("a\n\n\tb\n  c\n")).unwrap();
+        editor.head = editor.text.byte_len();
+        editor.indent(1);
+        assert_eq!(editor.text, "\ta\n\n\t\tb\n\t  c\n");
+        editor.dedent(1);
+        assert_eq!(editor.text, "a\n\n\tb\n  c\n");
+        editor.dedent(2);
+        assert_eq!(editor.text, "a\n\nb\nc\n");
+        assert_eq!(editor.head, editor.text.byte_len());
+
+        editor.undo();
+        assert_eq!(editor.text, "a\n\n\tb\n  c\n");
+
+        editor.expand_tab = true;
+        editor.indent_width = 2;
+        editor.anchor = 0;
+        editor.head = 0;
+        editor.indent(2);
+        assert_eq!(editor.text, "    a\n\n\tb\n  c\n");
+    }
+
+    #[test]
+    fn test_cursor_styles() {
+        let mut editor = Editor::new().unwrap();
+        let shape = |editor: &Editor, mode| editor.cursor_styles.get(&mode).shape;
+        assert_eq!(shape(&editor, Mode::Normal), CursorShape::Block);
+        assert_eq!(shape(&editor, Mode::Insert), CursorShape::Bar);
+        assert_eq!(shape(&editor, Mode::Goto), CursorShape::Underline);
+        assert_eq!(shape(&editor, Mode::Next), CursorShape::Underline);
+        assert_eq!(shape(&editor, Mode::Command), CursorShape::Underline);
+
+        editor
+            .run_command("set guicursor insert:block-blink,goto:bar")
+            .unwrap();
+        assert_eq!(
+            editor.cursor_styles.get(&Mode::Insert),
+            CursorStyle {
+                shape: CursorShape::Block,
+                blink: true
+            }
+        );
+        assert_eq!(shape(&editor, Mode::Prev), CursorShape::Bar);
+        assert_eq!(shape(&editor, Mode::Normal), CursorShape::Block);
+
+        editor.run_command("set guicursor").unwrap();
+        assert_eq!(
+            editor.message,
+            Some(Ok(String::from(
+                "guicursor normal:block,goto:bar,insert:block-blink,command:underline"
+            )))
+        );
+
+        // Invalid values leave every mode unchanged.
+        editor
+            .run_command("set guicursor normal:bar,insert:beam")
+            .unwrap();
+        assert!(matches!(editor.message, Some(Err(_))));
+        assert_eq!(shape(&editor, Mode::Normal), CursorShape::Block);
+    }
+
+    #[test]
+    fn test_align() {
+        let mut editor =
+            Editor::try_from(Rope::from("a = 1\nbbb = 2\nnone\ncc = = 3\né = 4\n")).unwrap();
+        editor.head = editor.text.byte_len();
+        editor.align("=");
+        assert_eq!(editor.text, "a   = 1\nbbb = 2\nnone\ncc  = = 3\né   = 4\n");
+        assert_eq!(editor.anchor, 0);
+        assert_eq!(editor.head, editor.text.byte_len());
+        assert!(editor.modified);
+    }
 }
@@ -0,0 +1,227 @@
+use crate::editor::LineEnding;
+use camino::Utf8Path;
+use regex::Regex;
+use std::fs;
+
+/// The subset of `.editorconfig` properties understood by the editor.
+#[derive(Debug, Default, PartialEq)]
+pub struct Properties {
+    pub indent_style: Option<IndentStyle>,
+    pub indent_size: Option<IndentSize>,
+    pub tab_width: Option<usize>,
+    pub end_of_line: Option<LineEnding>,
+    pub charset: Option<String>,
+    pub trim_trailing_whitespace: Option<bool>,
+    pub insert_final_newline: Option<bool>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IndentStyle {
+    Tab,
+    Space,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IndentSize {
+    Tab,
+    Columns(usize),
+}
+
+impl Properties {
+    /// The indentation width implied by `indent_size` and `tab_width`.
+    #[must_use]
+    pub fn indent_width(&self) -> Option<usize> {
+        match self.indent_size {
+            Some(IndentSize::Columns(width)) => Some(width),
+            Some(IndentSize::Tab) | None => self.tab_width,
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        let value = value.to_lowercase();
+        match key {
+            "indent_style" => {
+                self.indent_style = match value.as_str() {
+                    "tab" => Some(IndentStyle::Tab),
+                    "space" => Some(IndentStyle::Space),
+                    _ => None,
+                };
+            }
+            "indent_size" => {
+                self.indent_size = match value.as_str() {
+                    "tab" => Some(IndentSize::Tab),
+                    _ => value.parse().ok().map(IndentSize::Columns),
+                };
+            }
+            "tab_width" => self.tab_width = value.parse().ok(),
+            "end_of_line" => {
+                self.end_of_line = match value.as_str() {
+                    "lf" => Some(LineEnding::Lf),
+                    "crlf" => Some(LineEnding::Crlf),
+                    _ => None,
+                };
+            }
+            "charset" => self.charset = Some(value),
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace = value.parse().ok(),
+            "insert_final_newline" => self.insert_final_newline = value.parse().ok(),
+            _ => {}
+        }
+    }
+}
+
+/// Collect the properties which apply to the file at the absolute `path`, from `.editorconfig`
+/// files in its directory and each parent directory up to the first `root = true`.
+pub fn properties(path: &Utf8Path) -> anyhow::Result<Properties> {
+    let mut configs = Vec::new();
+    for dir in path.ancestors().skip(1) {
+        let config_path = dir.join(".editorconfig");
+        if !config_path.try_exists()? {
+            continue;
+        }
+        let config = fs::read_to_string(&config_path)?;
+        let is_root = parse_root(&config);
+        configs.push((dir, config));
+        if is_root {
+            break;
+        }
+    }
+    let mut properties = Properties::default();
+    // Closer files take precedence, so apply them last.
+    for (dir, config) in configs.iter().rev() {
+        let Ok(relative_path) = path.strip_prefix(dir) else {
+            continue;
+        };
+        apply(&mut properties, config, relative_path.as_str());
+    }
+    Ok(properties)
+}
+
+fn parse_root(config: &str) -> bool {
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('[') {
+            break;
+        }
+        if let Some((key, value)) = line.split_once('=')
+            && key.trim().eq_ignore_ascii_case("root")
+        {
+            return value.trim().eq_ignore_ascii_case("true");
+        }
+    }
+    false
+}
+
+fn apply(properties: &mut Properties, config: &str, relative_path: &str) {
+    let mut matches = false;
+    for line in config.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(section) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            matches = glob_matches(section, relative_path);
+        } else if matches && let Some((key, value)) = line.split_once('=') {
+            properties.set(&key.trim().to_lowercase(), value.trim());
+        }
+    }
+}
+
+fn glob_matches(glob: &str, relative_path: &str) -> bool {
+    // Globs without a slash match the file name in any directory.
+    let (glob, prefix) = if glob.contains('/') {
+        (glob.strip_prefix('/').unwrap_or(glob), "^")
+    } else {
+        (glob, "^(?:.*/)?")
+    };
+    let mut pattern = String::from(prefix);
+    let mut chars = glob.chars().peekable();
+    let mut braces = 0;
+    while let Some(char) = chars.next() {
+        match char {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                pattern.push_str(".*");
+            }
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            '{' => {
+                braces += 1;
+                pattern.push_str("(?:");
+            }
+            '}' if braces > 0 => {
+                braces -= 1;
+                pattern.push(')');
+            }
+            ',' if braces > 0 => pattern.push('|'),
+            '[' => {
+                pattern.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    pattern.push('^');
+                }
+            }
+            ']' => pattern.push(']'),
+            '\\' => {
+                if let Some(char) = chars.next() {
+                    pattern.push_str(&regex::escape(&char.to_string()));
+                }
+            }
+            _ => pattern.push_str(&regex::escape(&char.to_string())),
+        }
+    }
+    pattern.push('$');
+    Regex::new(&pattern).is_ok_and(|regex| regex.is_match(relative_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camino::Utf8PathBuf;
+    use std::{env, process};
+
+    #[test]
+    fn test() {
+        let root = Utf8PathBuf::try_from(env::temp_dir())
+            .unwrap()
+            .join(format!("blue-editorconfig-{}", process::id()));
+        let project = root.join("project");
+        fs::create_dir_all(project.join("src")).unwrap();
+        fs::write(
+            root.join(".editorconfig"),
+            "[*]\nindent_style = tab\ntrim_trailing_whitespace = true\ninsert_final_newline = false\n",
+        )
+        .unwrap();
+        fs::write(
+            project.join(".editorconfig"),
+            "root = true\n\n[*]\nindent_style = tab\ntab_width = 8\nend_of_line = LF\n\n\
+             # Rust\n[*.{rs,toml}]\nindent_style = space\nindent_size = 4\n\n\
+             [src/*.md]\nindent_size = tab\ncharset = latin1\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            properties(&project.join("src/main.rs")).unwrap(),
+            Properties {
+                indent_style: Some(IndentStyle::Space),
+                indent_size: Some(IndentSize::Columns(4)),
+                tab_width: Some(8),
+                end_of_line: Some(LineEnding::Lf),
+                ..Properties::default()
+            }
+        );
+
+        let readme = properties(&project.join("src/README.md")).unwrap();
+        assert_eq!(readme.indent_style, Some(IndentStyle::Tab));
+        assert_eq!(readme.indent_width(), Some(8));
+        assert_eq!(readme.charset.as_deref(), Some("latin1"));
+
+        // Above the project root, so only the outer file applies.
+        let outer = properties(&root.join("notes.txt")).unwrap();
+        assert_eq!(outer.indent_style, Some(IndentStyle::Tab));
+        assert_eq!(outer.trim_trailing_whitespace, Some(true));
+        assert_eq!(outer.insert_final_newline, Some(false));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
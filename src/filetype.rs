@@ -0,0 +1,65 @@
+use camino::Utf8Path;
+
+/// Guess a file's type from its name or extension.
+#[must_use]
+pub fn detect(path: &Utf8Path) -> Option<&'static str> {
+    let filetype = match path.file_name()? {
+        "Makefile" | "makefile" | "GNUmakefile" => "make",
+        "Dockerfile" => "dockerfile",
+        "justfile" | "Justfile" => "just",
+        _ => match path.extension()? {
+            "c" | "h" => "c",
+            "cc" | "cpp" | "cxx" | "hh" | "hpp" => "cpp",
+            "css" => "css",
+            "go" => "go",
+            "hs" => "haskell",
+            "html" | "htm" => "html",
+            "js" | "mjs" | "cjs" => "javascript",
+            "json" => "json",
+            "lua" => "lua",
+            "md" | "markdown" => "markdown",
+            "nix" => "nix",
+            "py" => "python",
+            "rb" => "ruby",
+            "rs" => "rust",
+            "sh" | "bash" => "sh",
+            "toml" => "toml",
+            "ts" => "typescript",
+            "yaml" | "yml" => "yaml",
+            _ => return None,
+        },
+    };
+    Some(filetype)
+}
+
+/// The prefix for line comments in files of type `filetype`, falling back to `//`.
+#[must_use]
+pub fn comment_token(filetype: Option<&str>) -> &'static str {
+    match filetype {
+        Some(
+            "dockerfile" | "just" | "make" | "nix" | "python" | "ruby" | "sh" | "toml" | "yaml",
+        ) => "#",
+        Some("haskell" | "lua") => "--",
+        _ => "//",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        assert_eq!(detect(Utf8Path::new("src/main.rs")), Some("rust"));
+        assert_eq!(detect(Utf8Path::new("/a/b/config.yml")), Some("yaml"));
+        assert_eq!(detect(Utf8Path::new("Makefile")), Some("make"));
+        assert_eq!(detect(Utf8Path::new("notes.txt")), None);
+        assert_eq!(detect(Utf8Path::new("LICENSE")), None);
+        assert_eq!(detect(Utf8Path::new(".bashrc")), None);
+
+        assert_eq!(comment_token(Some("rust")), "//");
+        assert_eq!(comment_token(Some("python")), "#");
+        assert_eq!(comment_token(Some("lua")), "--");
+        assert_eq!(comment_token(None), "//");
+    }
+}
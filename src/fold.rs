@@ -0,0 +1,117 @@
+use std::{cmp::min, iter};
+
+/// A manual fold over the lines `start..=end`. When closed, only its first line is shown, as a
+/// summary of the rest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fold {
+    pub start: usize,
+    pub end: usize,
+    pub closed: bool,
+}
+
+impl Fold {
+    #[must_use]
+    pub fn contains(&self, line: usize) -> bool {
+        (self.start..=self.end).contains(&line)
+    }
+}
+
+/// The closed fold containing `line`, if any.
+#[must_use]
+pub fn closed_fold_at(folds: &[Fold], line: usize) -> Option<&Fold> {
+    folds.iter().find(|fold| fold.closed && fold.contains(line))
+}
+
+/// `line` if it's visible, or the first line of the closed fold hiding it.
+#[must_use]
+pub fn visible_line(folds: &[Fold], line: usize) -> usize {
+    closed_fold_at(folds, line).map_or(line, |fold| fold.start)
+}
+
+/// The visible line after `line`, skipping the rest of a closed fold.
+#[must_use]
+pub fn next_visible_line(folds: &[Fold], line: usize) -> usize {
+    closed_fold_at(folds, line).map_or(line + 1, |fold| fold.end + 1)
+}
+
+/// Visible lines from `first` onward, one per row. This doesn't stop at the end of the text.
+pub fn visible_lines(folds: &[Fold], first: usize) -> impl Iterator<Item = usize> {
+    iter::successors(Some(visible_line(folds, first)), |line| {
+        Some(next_visible_line(folds, *line))
+    })
+}
+
+/// Move folds to follow their lines after an edit replaced the lines `start_line..=old_end_line`
+/// with `start_line..=new_end_line`. Folds whose lines were all deleted are removed.
+pub fn shift(
+    folds: &mut Vec<Fold>,
+    start_line: usize,
+    old_end_line: usize,
+    new_end_line: usize,
+    line_len: usize,
+) {
+    let shift = |line: usize| line - old_end_line + new_end_line;
+    folds.retain_mut(|fold| {
+        if fold.start > old_end_line {
+            fold.start = shift(fold.start);
+            fold.end = shift(fold.end);
+        } else if fold.end >= start_line {
+            if fold.start > new_end_line && fold.end <= old_end_line {
+                return false;
+            }
+            if fold.start > start_line {
+                fold.start = min(fold.start, new_end_line);
+            }
+            fold.end = if fold.end > old_end_line {
+                shift(fold.end)
+            } else {
+                min(fold.end, new_end_line)
+            };
+        }
+        fold.end = min(fold.end, line_len.saturating_sub(1));
+        fold.start <= fold.end
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fold(start: usize, end: usize) -> Fold {
+        Fold {
+            start,
+            end,
+            closed: true,
+        }
+    }
+
+    #[test]
+    fn test_visible_lines() {
+        let mut folds = vec![fold(2, 4), fold(6, 6)];
+        let lines = |folds: &[Fold], first| visible_lines(folds, first).take(5).collect::<Vec<_>>();
+        assert_eq!(lines(&folds, 0), [0, 1, 2, 5, 6]);
+        // Starting inside a fold starts from its summary line.
+        assert_eq!(lines(&folds, 3), [2, 5, 6, 7, 8]);
+        folds[0].closed = false;
+        assert_eq!(lines(&folds, 3), [3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn test_shift() {
+        let shifted = |start_line, old_end_line, new_end_line| {
+            let mut folds = vec![fold(2, 4), fold(8, 10)];
+            shift(&mut folds, start_line, old_end_line, new_end_line, 20);
+            folds
+        };
+        // A line inserted above moves both folds down.
+        assert_eq!(shifted(0, 0, 1), [fold(3, 5), fold(9, 11)]);
+        // A line inserted inside grows the fold.
+        assert_eq!(shifted(3, 3, 4), [fold(2, 5), fold(9, 11)]);
+        // Deleting all of a fold's lines removes it.
+        assert_eq!(shifted(1, 6, 1), [fold(3, 5)]);
+        // Deleting across a fold's start keeps the rest.
+        assert_eq!(shifted(6, 9, 6), [fold(2, 4), fold(6, 7)]);
+        // Deleting across a fold's end keeps the start.
+        assert_eq!(shifted(3, 6, 3), [fold(2, 3), fold(5, 7)]);
+    }
+}
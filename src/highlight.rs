@@ -0,0 +1,127 @@
+use crate::graphemes::{ceil_grapheme_boundary, floor_grapheme_boundary};
+use crop::RopeSlice;
+use ratatui::style::Color;
+use regex::Regex;
+use std::ops::Range;
+
+const GRAY: Color = Color::Rgb(0x6e, 0x77, 0x81);
+
+const NAVY: Color = Color::Rgb(0x0a, 0x30, 0x69);
+
+const RED: Color = Color::Rgb(0xcf, 0x22, 0x2e);
+
+const PURPLE: Color = Color::Rgb(0x82, 0x50, 0xdf);
+
+/// A byte range of a line, and the color it's drawn in.
+pub type Highlight = (Range<usize>, Color);
+
+/// Text matching `regex` is drawn in `color`.
+pub struct Rule {
+    pub regex: Regex,
+    pub color: Color,
+}
+
+/// The built-in rules for `filetype`, covering comments, strings, and keywords.
+#[must_use]
+pub fn rules(filetype: Option<&str>) -> Vec<Rule> {
+    let rules: &[(&str, Color)] = match filetype {
+        Some("rust") => &[
+            (r"//.*", GRAY),
+            (r#""(?:[^"\\]|\\.)*""#, NAVY),
+            (
+                r"\b(?:as|break|const|continue|crate|dyn|else|enum|fn|for|if|impl|in|let|loop|match|mod|move|mut|pub|ref|return|self|Self|static|struct|super|trait|type|unsafe|use|where|while)\b",
+                RED,
+            ),
+            (r"\b(?:true|false|[0-9][0-9_]*)\b", PURPLE),
+        ],
+        Some("python") => &[
+            (r"#.*", GRAY),
+            (r#""(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)*'"#, NAVY),
+            (
+                r"\b(?:and|as|class|def|elif|else|for|from|if|import|in|is|lambda|not|or|pass|return|while|with|yield)\b",
+                RED,
+            ),
+            (r"\b(?:True|False|None|[0-9]+)\b", PURPLE),
+        ],
+        Some("sh") => &[
+            (r"(?:^|\s)#.*", GRAY),
+            (r#""(?:[^"\\]|\\.)*"|'[^']*'"#, NAVY),
+            (
+                r"\b(?:case|do|done|elif|else|esac|fi|for|function|if|in|then|while)\b",
+                RED,
+            ),
+        ],
+        _ => &[],
+    };
+    rules
+        .iter()
+        .map(|(pattern, color)| Rule {
+            regex: Regex::new(pattern).expect("Built-in highlight rules should be valid"),
+            color: *color,
+        })
+        .collect()
+}
+
+/// Colored byte ranges of `line`, in order and without overlaps. At each position the earliest
+/// match wins, with ties going to the earlier rule, so keywords inside strings and comments
+/// aren't colored separately. Ranges are widened to grapheme boundaries.
+#[must_use]
+pub fn highlight(rules: &[Rule], line: RopeSlice) -> Vec<Highlight> {
+    let string = line.to_string();
+    let mut spans = Vec::new();
+    let mut offset = 0;
+    while offset < string.len() {
+        let next = rules
+            .iter()
+            .filter_map(|rule| {
+                let found = rule.regex.find_at(&string, offset)?;
+                Some((found.range(), rule.color))
+            })
+            .filter(|(range, _)| !range.is_empty())
+            .min_by_key(|(range, _)| range.start);
+        let Some((range, color)) = next else {
+            break;
+        };
+        let start = floor_grapheme_boundary(&line, range.start);
+        let end = ceil_grapheme_boundary(&line, range.end);
+        spans.push((start..end, color));
+        offset = end;
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crop::Rope;
+
+    #[test]
+    fn test() {
+        let rust_rules = rules(Some("rust"));
+        let rope = Rope::from("let s = \"if\"; // fn\n");
+        assert_eq!(
+            highlight(&rust_rules, rope.line(0)),
+            [(0..3, RED), (8..12, NAVY), (14..19, GRAY)]
+        );
+
+        let rules = [
+            Rule {
+                regex: Regex::new("e").unwrap(),
+                color: Color::Red,
+            },
+            Rule {
+                regex: Regex::new("x+").unwrap(),
+                color: Color::Blue,
+            },
+        ];
+        // The combining accent belongs to the same grapheme as the `e`.
+        let rope = Rope::from("xxe\u{301}x");
+        assert_eq!(
+            highlight(&rules, rope.line(0)),
+            [(0..2, Color::Blue), (2..5, Color::Red), (5..6, Color::Blue)]
+        );
+
+        assert_eq!(highlight(&[], rope.line(0)), []);
+        assert_eq!(highlight(&rust_rules, Rope::new().byte_slice(..)), []);
+    }
+}
@@ -0,0 +1,247 @@
+use crop::Rope;
+use std::{cmp::min, fmt::Write as _, mem, ops::Range, str::FromStr};
+
+const UNDO_FILE_HEADER: &str = "blue-undo 1\n";
+
+/// Editor state which is restored by undo and redo.
+pub struct Snapshot {
+    pub text: Rope,
+    pub anchor: usize,
+    pub head: usize,
+    pub desired_column: usize,
+}
+
+/// Undo and redo stacks of snapshots, each taken before an edit.
+///
+/// Every state gets a revision number, so the editor can tell when undo or redo returns to the
+/// last saved state.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<(Snapshot, usize)>,
+    redo: Vec<(Snapshot, usize)>,
+    revision: usize,
+    last_revision: usize,
+    saved_revision: usize,
+    // Where the single-grapheme inserts since the last undo step ended, so the next ones can join
+    // it. There's one for each selection typed at together.
+    insert_ends: Vec<usize>,
+    group: Option<Group>,
+}
+
+#[derive(PartialEq)]
+enum Group {
+    Empty,
+    Recorded,
+}
+
+impl History {
+    /// Record the state from before an edit as a new undo step.
+    pub fn record(&mut self, snapshot: Snapshot) {
+        self.insert_ends.clear();
+        self.push(snapshot);
+    }
+
+    /// Record the state from before inserting a single grapheme at `start`. Consecutive inserts
+    /// share an undo step, so a word typed in Insert mode is undone all at once, even when it's
+    /// typed at several selections.
+    pub fn record_insert(&mut self, snapshot: Snapshot, start: usize, end: usize) {
+        if let Some(index) = self
+            .insert_ends
+            .iter()
+            .position(|insert_end| *insert_end == start)
+        {
+            self.insert_ends.swap_remove(index);
+        } else {
+            // Inserts at other selections in the same group share its step.
+            if self.group != Some(Group::Recorded) {
+                self.insert_ends.clear();
+            }
+            self.push(snapshot);
+        }
+        for insert_end in &mut self.insert_ends {
+            if *insert_end >= start {
+                *insert_end += end - start;
+            }
+        }
+        self.insert_ends.push(end);
+    }
+
+    fn push(&mut self, snapshot: Snapshot) {
+        match self.group {
+            Some(Group::Recorded) => return,
+            Some(Group::Empty) => self.group = Some(Group::Recorded),
+            None => {}
+        }
+        self.undo.push((snapshot, self.revision));
+        self.redo.clear();
+        self.last_revision += 1;
+        self.revision = self.last_revision;
+    }
+
+    /// Start a new undo step with the next insert, even if it's where the last one ended.
+    pub fn break_insert(&mut self) {
+        self.insert_ends.clear();
+    }
+
+    /// Combine all edits until `end_group` into a single undo step.
+    pub fn begin_group(&mut self) {
+        self.group = Some(Group::Empty);
+    }
+
+    pub fn end_group(&mut self) {
+        self.group = None;
+    }
+
+    /// Swap `current` for the snapshot from before the last edit.
+    pub fn undo(&mut self, current: Snapshot) -> Option<Snapshot> {
+        let (snapshot, revision) = self.undo.pop()?;
+        self.redo
+            .push((current, mem::replace(&mut self.revision, revision)));
+        self.insert_ends.clear();
+        Some(snapshot)
+    }
+
+    /// Swap `current` for the snapshot from before the last undo.
+    pub fn redo(&mut self, current: Snapshot) -> Option<Snapshot> {
+        let (snapshot, revision) = self.redo.pop()?;
+        self.undo
+            .push((current, mem::replace(&mut self.revision, revision)));
+        self.insert_ends.clear();
+        Some(snapshot)
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.insert_ends.clear();
+        self.saved_revision = self.revision;
+    }
+
+    pub fn is_saved(&self) -> bool {
+        self.revision == self.saved_revision
+    }
+
+    /// The undo and redo stacks as an undo file for `text`, where each step is stored as the edit
+    /// from the state after it.
+    pub fn to_undo_file(&self, text: &Rope) -> String {
+        let mut contents = String::from(UNDO_FILE_HEADER);
+        let _ = writeln!(contents, "{}", hash(text));
+        let _ = writeln!(
+            contents,
+            "{} {} {}",
+            text.byte_len(),
+            self.revision,
+            self.last_revision
+        );
+        for stack in [&self.undo, &self.redo] {
+            let _ = writeln!(contents, "{}", stack.len());
+            let mut newer = text;
+            for (snapshot, revision) in stack.iter().rev() {
+                let (range, replacement) = edit_between(newer, &snapshot.text);
+                let _ = writeln!(
+                    contents,
+                    "{revision} {} {} {} {} {} {}\n{replacement}",
+                    snapshot.anchor,
+                    snapshot.head,
+                    snapshot.desired_column,
+                    range.start,
+                    range.end,
+                    replacement.len(),
+                );
+                newer = &snapshot.text;
+            }
+        }
+        contents
+    }
+
+    /// Read an undo file written by `to_undo_file`, as long as it was written for `text`. The
+    /// history starts out saved.
+    pub fn from_undo_file(contents: &str, text: &Rope) -> Option<Self> {
+        let mut rest = contents.strip_prefix(UNDO_FILE_HEADER)?;
+        let [text_hash] = take_fields::<u64, 1>(&mut rest)?;
+        let [len, revision, last_revision] = take_fields(&mut rest)?;
+        if text_hash != hash(text) || len != text.byte_len() {
+            return None;
+        }
+        let mut history = Self {
+            revision,
+            last_revision,
+            saved_revision: revision,
+            ..Self::default()
+        };
+        for stack in [&mut history.undo, &mut history.redo] {
+            let [count] = take_fields(&mut rest)?;
+            let mut newer = text.clone();
+            for _ in 0..count {
+                let [revision, anchor, head, desired_column, start, end, len] =
+                    take_fields(&mut rest)?;
+                let replacement = rest.get(..len)?;
+                rest = rest[len..].strip_prefix('\n')?;
+                if start > end
+                    || end > newer.byte_len()
+                    || !newer.is_char_boundary(start)
+                    || !newer.is_char_boundary(end)
+                {
+                    return None;
+                }
+                let mut older = newer.clone();
+                older.replace(start..end, replacement);
+                if [anchor, head].iter().any(|offset| {
+                    *offset > older.byte_len() || !older.is_grapheme_boundary(*offset)
+                }) {
+                    return None;
+                }
+                let snapshot = Snapshot {
+                    text: older.clone(),
+                    anchor,
+                    head,
+                    desired_column,
+                };
+                stack.push((snapshot, revision));
+                newer = older;
+            }
+            stack.reverse();
+        }
+        rest.is_empty().then_some(history)
+    }
+}
+
+// The range of `old` to replace with the returned text to get `new`, leaving out what they start
+// and end with in common.
+fn edit_between(old: &Rope, new: &Rope) -> (Range<usize>, String) {
+    let mut prefix = iter_eq_len(old.bytes(), new.bytes(), usize::MAX);
+    while !old.is_char_boundary(prefix) {
+        prefix -= 1;
+    }
+    let max_suffix = min(old.byte_len(), new.byte_len()) - prefix;
+    let mut suffix = iter_eq_len(old.bytes().rev(), new.bytes().rev(), max_suffix);
+    while !old.is_char_boundary(old.byte_len() - suffix) {
+        suffix -= 1;
+    }
+    let replacement = new.byte_slice(prefix..new.byte_len() - suffix).to_string();
+    (prefix..old.byte_len() - suffix, replacement)
+}
+
+// How many bytes the iterators start with in common, up to `max`.
+fn iter_eq_len(old: impl Iterator<Item = u8>, new: impl Iterator<Item = u8>, max: usize) -> usize {
+    old.zip(new)
+        .take(max)
+        .take_while(|(old, new)| old == new)
+        .count()
+}
+
+// Read a line of space-separated fields from the start of `rest`.
+fn take_fields<T: FromStr, const N: usize>(rest: &mut &str) -> Option<[T; N]> {
+    let (line, after) = rest.split_once('\n')?;
+    *rest = after;
+    let fields = line
+        .split(' ')
+        .map(|field| field.parse().ok())
+        .collect::<Option<Vec<_>>>()?;
+    fields.try_into().ok()
+}
+
+// FNV-1a, which unlike `DefaultHasher` is the same from one build to the next.
+fn hash(text: &Rope) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
@@ -0,0 +1,77 @@
+use crop::Rope;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Indent {
+    Tabs,
+    Spaces(usize),
+}
+
+/// Guess a file's indentation style from the leading whitespace of its lines.
+///
+/// Tab-indented lines are counted against space-indented lines. For spaces, the width is the most
+/// common change in indentation between consecutive non-blank lines.
+#[must_use]
+pub fn detect(rope: &Rope) -> Option<Indent> {
+    let mut tab_lines = 0;
+    let mut space_lines = 0;
+    let mut deltas = HashMap::<usize, usize>::new();
+    let mut prev_spaces = 0;
+    for line in rope.lines() {
+        if line.chars().all(char::is_whitespace) {
+            continue;
+        }
+        let mut chars = line.chars().peekable();
+        if chars.peek() == Some(&'\t') {
+            tab_lines += 1;
+            prev_spaces = 0;
+            continue;
+        }
+        let spaces = chars.take_while(|char| *char == ' ').count();
+        if spaces > 0 {
+            space_lines += 1;
+        }
+        let delta = spaces.abs_diff(prev_spaces);
+        if delta > 0 {
+            *deltas.entry(delta).or_default() += 1;
+        }
+        prev_spaces = spaces;
+    }
+    if tab_lines == 0 && space_lines == 0 {
+        None
+    } else if tab_lines >= space_lines {
+        Some(Indent::Tabs)
+    } else {
+        deltas
+            .into_iter()
+            .max_by(|(a_width, a_count), (b_width, b_count)| {
+                a_count.cmp(b_count).then(b_width.cmp(a_width))
+            })
+            .map(|(width, _)| Indent::Spaces(width))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        assert_eq!(detect(&Rope::from("")), None);
+        assert_eq!(detect(&Rope::from("a\nb\n")), None);
+        assert_eq!(
+            detect(&Rope::from("fn f() {\n\tif x {\n\t\ty\n\t}\n}\n")),
+            Some(Indent::Tabs)
+        );
+        assert_eq!(
+            detect(&Rope::from("a:\n  b:\n    c\n  d\n\n  e:\n    f\n")),
+            Some(Indent::Spaces(2))
+        );
+        assert_eq!(
+            detect(&Rope::from(
+                "fn f() {\n    if x {\n        y\n      // odd\n    }\n}\n"
+            )),
+            Some(Indent::Spaces(4))
+        );
+    }
+}
@@ -0,0 +1,25 @@
+//! The editing core of blue, for embedding in other programs. An [`Editor`] holds a text and its
+//! selections, takes terminal events with [`Editor::handle_event`], and draws itself into a
+//! ratatui buffer with [`Editor::render`].
+
+mod config;
+mod diff;
+mod display_width;
+mod editor;
+mod editorconfig;
+mod filetype;
+mod fold;
+mod graphemes;
+mod highlight;
+mod history;
+mod indent;
+mod session;
+mod ui;
+
+pub use crate::{
+    config::Config,
+    editor::{CursorShape, CursorStyle, Editor, LineNumbers, ListChars, Mode, Selection},
+    editorconfig::IndentStyle,
+    session::Session,
+};
+pub use crop::Rope;
@@ -1,562 +1,123 @@
-mod display_width;
-mod editor;
-mod graphemes;
+mod clipboard;
 mod terminal;
 
-use crate::{
-    display_width::DisplayWidth as _,
-    editor::{Editor, Mode},
-    graphemes::{floor_grapheme_boundary, prev_grapheme_boundary},
-};
+use blue::{Config, Editor, Session};
 use camino::Utf8PathBuf;
 use clap::Parser as _;
-use crop::Rope;
-use crossterm::event::{Event, KeyCode, KeyModifiers, MouseButton, MouseEventKind};
-use pathdiff::diff_utf8_paths;
-use ratatui::prelude::*;
+use crossterm::event::Event;
+use ratatui::layout::Rect;
 use std::{
-    cmp::{max, min},
     env,
-    iter::zip,
+    io::{self, IsTerminal as _, Read as _},
     process::ExitCode,
 };
 
 #[derive(clap::Parser)]
 struct Args {
-    file: Option<Utf8PathBuf>,
+    /// Files to open, each in its own buffer
+    files: Vec<Utf8PathBuf>,
+    /// Command to run after opening the file (can be repeated)
+    #[clap(short = 'c', long = "command")]
+    commands: Vec<String>,
+    /// Restore a session saved with `:mksession`, instead of opening a file
+    #[clap(long, conflicts_with = "files")]
+    session: Option<Utf8PathBuf>,
+    /// Config file to use instead of `~/.config/blue/config.toml`
+    #[clap(long)]
+    config: Option<Utf8PathBuf>,
+    /// File type to use instead of guessing from the file name
+    #[clap(short = 't', long)]
+    filetype: Option<String>,
+    /// Only look at the text, refusing to change it
+    #[clap(long)]
+    readonly: bool,
 }
 
 fn main() -> anyhow::Result<ExitCode> {
     let args = Args::parse();
 
-    let mut terminal = terminal::init();
-
-    let mut editor = if let Some(path) = args.file {
-        Editor::open(path)?
-    } else {
-        Editor::new()?
-    };
-
-    editor.pwd = Some(Utf8PathBuf::try_from(env::current_dir()?)?);
-
-    let mut area = Rect::default();
-
-    let exit_code = loop {
-        terminal.draw(|frame| {
-            area = frame.area();
-            render(&editor, area, frame.buffer_mut());
-        })?;
-        let event = crossterm::event::read()?;
-        if matches!(event, Event::Resize(_, _)) {
-            continue;
-        }
-        update(&mut editor, area, &event)?;
-        if let Some(exit_code) = editor.exit_code {
-            break exit_code;
-        }
+    // A missing config file is only worth a warning if it was asked for.
+    let config = match args.config.clone().or_else(Config::default_path) {
+        Some(path) if args.config.is_some() || path.exists() => Config::load(&path),
+        _ => Ok(Config::default()),
     };
 
-    Ok(exit_code)
-}
-
-const LIGHT_RED: Color = Color::Rgb(0xff, 0xdc, 0xe0);
-
-const DARK_ORANGE: Color = Color::Rgb(0xd1, 0x57, 0x04);
-
-const LIGHT_YELLOW: Color = Color::Rgb(0xff, 0xf5, 0xb1);
-
-const DARK_YELLOW: Color = Color::Rgb(0xff, 0xd3, 0x3d);
-
-struct Areas {
-    status_bar: Rect,
-    line_numbers: Rect,
-    text: Rect,
-}
-
-impl Areas {
-    fn new(text: &Rope, area: Rect) -> Self {
-        let line_numbers_width = {
-            let n = text.line_len();
-            let digits = 1 + max(1, n).ilog10();
-            u16::try_from(max(2, digits) + 1)
-                .expect("Line numbers width should always be very small")
-        };
-        let [status_bar, main] = Layout::vertical([
-            // status bar
-            Constraint::Length(1),
-            // line_numbers + text
-            Constraint::Fill(1),
-        ])
-        .areas(area);
-        let [line_numbers, text] = Layout::horizontal([
-            // line_numbers
-            Constraint::Length(line_numbers_width),
-            // fill
-            Constraint::Fill(1),
-        ])
-        .areas(main);
-        Self {
-            status_bar,
-            line_numbers,
-            text,
-        }
-    }
-}
-
-fn render(editor: &Editor, area: Rect, buffer: &mut Buffer) {
-    let areas = Areas::new(&editor.text, area);
-    render_status_bar(editor, areas.status_bar, buffer);
-    render_line_numbers(editor, areas.line_numbers, buffer);
-    render_text(editor, areas.text, buffer);
-    render_selection(editor, areas.text, buffer);
-}
-
-fn render_status_bar(editor: &Editor, area: Rect, buffer: &mut Buffer) {
-    if let Some(message) = &editor.message {
-        match message {
-            Ok(message) => Line::raw(message).underlined().render(area, buffer),
-            Err(message) => Line::raw(message)
-                .underlined()
-                .bg(LIGHT_RED)
-                .render(area, buffer),
-        }
-    } else if let Mode::Command = editor.mode {
-        let status_bar = format!(":{}", editor.command);
-        Line::raw(status_bar).underlined().render(area, buffer);
-        let cursor_x = area.x
-            + 1
-            + u16::try_from(
-                editor
-                    .command
-                    .byte_slice(..editor.command_cursor)
-                    .display_width(),
-            )
-            .expect("Command length should not exceed `u16::MAX`");
-        if let Some(cell) = buffer.cell_mut((cursor_x, area.y)) {
-            cell.set_bg(DARK_YELLOW);
-        }
-    } else {
-        let mode = match editor.mode {
-            Mode::Normal => "normal",
-            Mode::Goto => "goto",
-            Mode::Insert => "insert",
-            Mode::Command => unreachable!(),
-        };
-        let path = match (&editor.pwd, &editor.path) {
-            (_, None) => String::from("*scratch*"),
-            (None, Some(path)) => path.to_string(),
-            (Some(pwd), Some(path)) => match diff_utf8_paths(path, pwd) {
-                None => path.to_string(),
-                Some(path) => path.to_string(),
-            },
-        };
-        let modified = if editor.modified { "*" } else { "" };
-        let anchor = editor.anchor;
-        let head = editor.head;
-        let status_bar = format!("{mode} · {path}{modified} {anchor}-{head}");
-        Line::raw(status_bar).underlined().render(area, buffer);
-    }
-}
-
-fn render_line_numbers(editor: &Editor, area: Rect, buffer: &mut Buffer) {
-    for (line_number, row) in zip(
-        editor.vertical_scroll + 1..=editor.text.line_len(),
-        area.rows(),
-    ) {
-        Line::raw(format!("{line_number}│"))
-            .right_aligned()
-            .render(row, buffer);
-    }
-}
-
-fn render_text(editor: &Editor, area: Rect, buffer: &mut Buffer) {
-    for (line, row) in zip(
-        editor.text.lines().skip(editor.vertical_scroll),
-        area.rows(),
-    ) {
-        Line::raw(line.to_string().replace('\t', "        ")).render(row, buffer);
-    }
-}
-
-fn render_selection(editor: &Editor, area: Rect, buffer: &mut Buffer) {
-    if editor.anchor != editor.head {
-        let start = min(editor.anchor, editor.head);
-        let end = max(editor.anchor, editor.head);
-        let start_line = editor.text.line_of_byte(start);
-        let end_line = editor.text.line_of_byte(end.saturating_sub(1));
-        for line_index in start_line..=end_line {
-            let Some(mut line_area) =
-                line_index_to_area(&editor.text, editor.vertical_scroll, area, line_index)
-            else {
-                continue;
-            };
-            if line_index == start_line {
-                if let Some(start_area) =
-                    byte_offset_to_area(&editor.text, editor.vertical_scroll, area, start)
-                {
-                    let delta = start_area.x - line_area.x;
-                    line_area.x += delta;
-                    line_area.width -= delta;
-                } else {
-                    // TODO: We continue here because we know the range start is off the screen to
-                    // the right. Once horizontal scrolling is added, we'll need to handle when the
-                    // range is off the screen to the left. `byte_offset_to_area` doesn't say which
-                    // direction the index is off screen.
-                    continue;
-                }
-            }
-            #[expect(clippy::collapsible_if)]
-            if line_index == end_line {
-                if let Some(end_area) = byte_offset_to_area(
-                    &editor.text,
-                    editor.vertical_scroll,
-                    area,
-                    end.saturating_sub(1),
-                ) {
-                    let delta = line_area.right() - end_area.right();
-                    line_area.width -= delta;
-                }
-            }
-            buffer.set_style(line_area, Style::new().bg(LIGHT_YELLOW));
-        }
-    }
-    let head = if editor.anchor < editor.head {
-        prev_grapheme_boundary(&editor.text.byte_slice(..), editor.head).unwrap_or(editor.head)
+    // Text piped in is edited as a scratch buffer. Stdin is used up by reading it, but the terminal
+    // falls back to `/dev/tty` for input.
+    let stdin = if args.files.is_empty() && args.session.is_none() && !io::stdin().is_terminal() {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        Some(text)
     } else {
-        editor.head
+        None
     };
-    if let Some(area) = byte_offset_to_area(&editor.text, editor.vertical_scroll, area, head) {
-        buffer.set_style(
-            area,
-            Style::new().bg(if editor.anchor == editor.head {
-                DARK_ORANGE
-            } else {
-                DARK_YELLOW
-            }),
-        );
-    }
-}
-
-// TODO: Add tests for position conversions. Then try and simplify.
-
-fn byte_offset_to_area(
-    rope: &Rope,
-    vertical_scroll: usize,
-    area: Rect,
-    byte_offset: usize,
-) -> Option<Rect> {
-    if byte_offset > rope.byte_len() {
-        return None;
-    }
-
-    let line_offset = rope.line_of_byte(byte_offset);
-
-    if vertical_scroll > line_offset {
-        return None;
-    }
-
-    let y = area.y + u16::try_from(line_offset - vertical_scroll).unwrap();
-
-    if !(area.top()..area.bottom()).contains(&y) {
-        return None;
-    }
-
-    let line_byte_offset = rope.byte_of_line(line_offset);
-
-    let byte_offset = floor_grapheme_boundary(&rope.byte_slice(..), byte_offset);
 
-    let prefix_width = rope
-        .byte_slice(line_byte_offset..byte_offset)
-        .display_width();
-
-    // TODO: When horizontal scroll is introduced, still return portion of rect that is visible.
-    // Even if it starts to the left of the area, it might be wide enough to peek into the viewport.
-    let x = area.x + u16::try_from(prefix_width).unwrap();
+    let mut terminal = terminal::init();
 
-    if !(area.left()..area.right()).contains(&x) {
-        return None;
-    }
+    let mut files = args.files.iter();
 
-    let width = if rope.byte_len() == byte_offset {
-        // Cursor at EOF
-        1
-    } else if let Some(grapheme) = rope.byte_slice(byte_offset..).graphemes().next() {
-        u16::try_from(grapheme.as_ref().display_width()).unwrap()
+    let mut editor = if let Some(path) = files.next() {
+        Editor::open(path)?
+    } else if let Some(path) = args.session {
+        Editor::from_session(&Session::load(&path)?)?
+    } else if let Some(text) = stdin {
+        Editor::try_from(text.as_str())?
     } else {
-        // We're at EOF, but we already checked for that
-        unreachable!()
+        Editor::new()?
     };
 
-    Some(Rect {
-        x,
-        y,
-        width,
-        height: 1,
-    })
-}
-
-fn line_index_to_area(
-    rope: &Rope,
-    vertical_scroll: usize,
-    area: Rect,
-    line_index: usize,
-) -> Option<Rect> {
-    if vertical_scroll > line_index {
-        return None;
+    match config {
+        Ok(config) => editor.set_config(config),
+        Err(error) => editor.set_message(Err(format!("Failed to load config: {error:#}"))),
     }
 
-    if line_index >= rope.line_len() {
-        return None;
+    // The rest are opened after the config is loaded, so they start from its settings too.
+    for path in files {
+        editor.open_buffer(path)?;
     }
 
-    let x = area.x;
-
-    let y = area.y + u16::try_from(line_index - vertical_scroll).unwrap();
-
-    if !(area.top()..area.bottom()).contains(&y) {
-        return None;
+    if let Some(filetype) = args.filetype {
+        editor.set_filetype(Some(filetype));
     }
 
-    let line = rope.line_slice(line_index..=line_index);
-
-    let width = u16::try_from(line.display_width()).unwrap();
-
-    Some(Rect {
-        x,
-        y,
-        width,
-        height: 1,
-    })
-}
+    editor.set_read_only(args.readonly);
 
-fn position_to_byte_offset(
-    rope: &Rope,
-    vertical_scroll: usize,
-    area: Rect,
-    position: Position,
-) -> Option<usize> {
-    if !area.contains(position) {
-        return None;
-    }
+    editor.set_pwd(Utf8PathBuf::try_from(env::current_dir()?)?);
 
-    let target_column = usize::from(position.x - area.x);
-    let row = usize::from(position.y - area.y) + vertical_scroll;
+    editor.run_commands(&args.commands);
 
-    if row >= rope.line_len() {
-        return Some(rope.byte_len());
-    }
+    let mut area = Rect::default();
 
-    let mut current_column = 0;
-    let mut byte_offset = rope.byte_of_line(row);
+    let mut cursor_style = None;
 
-    for grapheme in rope.line(row).graphemes() {
-        let grapheme_width = grapheme.as_ref().display_width();
-        if current_column + grapheme_width > target_column {
-            break;
+    let exit_code = loop {
+        if let Some(exit_code) = editor.exit_code() {
+            break exit_code;
         }
-        current_column += grapheme_width;
-        byte_offset += grapheme.len();
-    }
-
-    Some(byte_offset)
-}
-
-#[expect(clippy::too_many_lines)]
-fn update(editor: &mut Editor, area: Rect, event: &Event) -> anyhow::Result<()> {
-    let dismiss_message = match event {
-        Event::Mouse(mouse) => !matches!(
-            mouse.kind,
-            MouseEventKind::Moved
-                | MouseEventKind::ScrollUp
-                | MouseEventKind::ScrollDown
-                | MouseEventKind::ScrollLeft
-                | MouseEventKind::ScrollRight
-        ),
-        _ => true,
-    };
-    if dismiss_message {
-        editor.message = None;
-    }
-    let areas = Areas::new(&editor.text, area);
-    #[allow(clippy::match_same_arms)]
-    match event {
-        Event::Key(key) => match editor.mode {
-            Mode::Normal => match (key.modifiers, key.code) {
-                (m, KeyCode::Char('p')) if m == KeyModifiers::CONTROL => panic!(),
-                (m, KeyCode::Char('h')) if m == KeyModifiers::NONE => editor.move_left(1),
-                (m, KeyCode::Char('l')) if m == KeyModifiers::NONE => editor.move_right(1),
-                (m, KeyCode::Char('k')) if m == KeyModifiers::NONE => editor.move_up(1),
-                (m, KeyCode::Char('j')) if m == KeyModifiers::NONE => editor.move_down(1),
-                (m, KeyCode::Char('h' | 'H')) if m == KeyModifiers::SHIFT => editor.extend_left(1),
-                (m, KeyCode::Char('l' | 'L')) if m == KeyModifiers::SHIFT => editor.extend_right(1),
-                (m, KeyCode::Char('k' | 'K')) if m == KeyModifiers::SHIFT => editor.extend_up(1),
-                (m, KeyCode::Char('j' | 'J')) if m == KeyModifiers::SHIFT => editor.extend_down(1),
-                (m, KeyCode::Char(';')) if m == KeyModifiers::NONE => editor.reduce(),
-                (m, KeyCode::Char(';')) if m == KeyModifiers::ALT => editor.flip(),
-                (m, KeyCode::Char(';')) if m == KeyModifiers::SHIFT | KeyModifiers::ALT => {
-                    editor.flip_forward();
-                }
-                (m, KeyCode::Char('d')) if m == KeyModifiers::NONE => editor.delete(),
-                (m, KeyCode::Char('c')) if m == KeyModifiers::NONE => {
-                    editor.delete();
-                    editor.mode = Mode::Insert;
-                }
-                (m, KeyCode::Char('i')) if m == KeyModifiers::NONE => {
-                    editor.reduce();
-                    editor.mode = Mode::Insert;
-                }
-                (m, KeyCode::Char(':')) if m == KeyModifiers::NONE => {
-                    editor.command = Rope::new();
-                    editor.command_cursor = 0;
-                    editor.mode = Mode::Command;
-                }
-                (m, KeyCode::Char('u')) if m == KeyModifiers::CONTROL => {
-                    let half_height = usize::from(areas.text.height.saturating_sub(1) / 2);
-                    editor.scroll_up(half_height);
-                }
-                (m, KeyCode::Char('d')) if m == KeyModifiers::CONTROL => {
-                    let half_height = usize::from(areas.text.height.saturating_sub(1) / 2);
-                    editor.scroll_down(half_height);
-                }
-                (m, KeyCode::Char('b')) if m == KeyModifiers::CONTROL => {
-                    let full_height = usize::from(areas.text.height.saturating_sub(2));
-                    editor.scroll_up(full_height);
-                }
-                (m, KeyCode::Char('f')) if m == KeyModifiers::CONTROL => {
-                    let full_height = usize::from(areas.text.height.saturating_sub(2));
-                    editor.scroll_down(full_height);
-                }
-                (m, KeyCode::Char('g')) if m == KeyModifiers::NONE => editor.mode = Mode::Goto,
-                _ => {}
-            },
-            Mode::Goto => match (key.modifiers, key.code) {
-                (m, KeyCode::Char('k')) if m == KeyModifiers::NONE => {
-                    editor.move_to(0);
-                    editor.mode = Mode::Normal;
-                }
-                (m, KeyCode::Char('h')) if m == KeyModifiers::NONE => {
-                    editor.move_line_start();
-                    editor.mode = Mode::Normal;
-                }
-                (m, KeyCode::Char('l')) if m == KeyModifiers::NONE => {
-                    editor.move_line_end();
-                    editor.mode = Mode::Normal;
-                }
-                (m, KeyCode::Char('h' | 'H')) if m == KeyModifiers::SHIFT => {
-                    editor.extend_line_start();
-                    editor.mode = Mode::Normal;
-                }
-                (m, KeyCode::Char('l' | 'L')) if m == KeyModifiers::SHIFT => {
-                    editor.extend_line_end();
-                    editor.mode = Mode::Normal;
-                }
-                (m, KeyCode::Esc) if m == KeyModifiers::NONE => editor.mode = Mode::Normal,
-                _ => {
-                    editor.message = Some(Err(String::from("Unknown key")));
-                    editor.mode = Mode::Normal;
-                }
-            },
-            Mode::Insert => match (key.modifiers, key.code) {
-                (m, KeyCode::Char('a')) if m == KeyModifiers::CONTROL => editor.move_line_start(),
-                (m, KeyCode::Char('e')) if m == KeyModifiers::CONTROL => editor.move_line_end(),
-                (m, KeyCode::Char('b')) if m == KeyModifiers::CONTROL => editor.move_left(1),
-                (m, KeyCode::Char('f')) if m == KeyModifiers::CONTROL => editor.move_right(1),
-                (m, KeyCode::Char(char)) if m == KeyModifiers::NONE || m == KeyModifiers::SHIFT => {
-                    editor.insert(&char.to_string());
-                }
-                (m, KeyCode::Tab) if m == KeyModifiers::NONE => {
-                    editor.insert("\t");
-                }
-                (m, KeyCode::Enter) if m == KeyModifiers::NONE => {
-                    editor.insert("\n");
-                }
-                (m, KeyCode::Backspace) if m == KeyModifiers::NONE => editor.delete_before(),
-                (m, KeyCode::Esc) if m == KeyModifiers::NONE => editor.mode = Mode::Normal,
-                _ => {}
-            },
-            Mode::Command => match (key.modifiers, key.code) {
-                (m, KeyCode::Char('a')) if m == KeyModifiers::CONTROL => editor.command_cursor = 0,
-                (m, KeyCode::Char('e')) if m == KeyModifiers::CONTROL => {
-                    editor.command_cursor = editor.command.byte_len();
-                }
-                (m, KeyCode::Left) if m == KeyModifiers::NONE => editor.command_mode_move_left(1),
-                (m, KeyCode::Right) if m == KeyModifiers::NONE => {
-                    editor.command_mode_move_right(1);
-                }
-                (m, KeyCode::Char('b')) if m == KeyModifiers::CONTROL => {
-                    editor.command_mode_move_left(1);
-                }
-                (m, KeyCode::Char('f')) if m == KeyModifiers::CONTROL => {
-                    editor.command_mode_move_right(1);
-                }
-                (m, KeyCode::Char('u')) if m == KeyModifiers::CONTROL => {
-                    editor.command_mode_delete_before();
-                }
-                (m, KeyCode::Char('k')) if m == KeyModifiers::CONTROL => {
-                    editor.command_mode_delete_after();
-                }
-                (m, KeyCode::Char(char)) if m == KeyModifiers::NONE || m == KeyModifiers::SHIFT => {
-                    let string = char.to_string();
-                    editor.command.insert(editor.command_cursor, &string);
-                    editor.command_cursor += string.len();
-                }
-                (m, KeyCode::Backspace) if m == KeyModifiers::NONE => {
-                    if editor.command_cursor > 0 {
-                        debug_assert!(!editor.command.is_empty());
-                        if let Some(prev) = prev_grapheme_boundary(
-                            &editor.command.byte_slice(..),
-                            editor.command_cursor,
-                        ) {
-                            editor.command.delete(prev..editor.command_cursor);
-                            editor.command_cursor = prev;
-                        }
-                    } else if editor.command.is_empty() {
-                        debug_assert!(editor.command_cursor == 0);
-                        editor.command = Rope::new();
-                        editor.command_cursor = 0;
-                        editor.mode = Mode::Normal;
-                    }
-                }
-                (m, KeyCode::Enter) if m == KeyModifiers::NONE => {
-                    editor.execute_command()?;
-                }
-                (m, KeyCode::Esc) if m == KeyModifiers::NONE => {
-                    editor.command = Rope::new();
-                    editor.command_cursor = 0;
-                    editor.mode = Mode::Normal;
-                }
-                _ => {}
-            },
-        },
-        Event::Mouse(mouse) => match mouse.kind {
-            MouseEventKind::ScrollUp => editor.scroll_up(3),
-            MouseEventKind::ScrollDown => editor.scroll_down(3),
-            // Move
-            MouseEventKind::Down(MouseButton::Left) => {
-                if let Some(byte_offset) = position_to_byte_offset(
-                    &editor.text,
-                    editor.vertical_scroll,
-                    areas.text,
-                    Position::new(mouse.column, mouse.row),
-                ) {
-                    editor.move_to(byte_offset);
-                }
-            }
-            // Extend
-            MouseEventKind::Down(MouseButton::Right)
-            | MouseEventKind::Drag(MouseButton::Left | MouseButton::Right) => {
-                if let Some(byte_offset) = position_to_byte_offset(
-                    &editor.text,
-                    editor.vertical_scroll,
-                    areas.text,
-                    Position::new(mouse.column, mouse.row),
-                ) {
-                    editor.extend_to(byte_offset);
-                }
+        if let Some(text) = editor.take_pending_clipboard() {
+            terminal::set_clipboard(&text)?;
+        }
+        let style = editor.cursor_style();
+        if cursor_style != Some(style) {
+            terminal::set_cursor_style(style)?;
+            cursor_style = Some(style);
+        }
+        terminal.draw(|frame| {
+            area = frame.area();
+            editor.render(area, frame.buffer_mut());
+            if let Some(position) = editor.cursor_position(area) {
+                frame.set_cursor_position(position);
             }
-            _ => {}
-        },
-        _ => {}
-    }
-    Ok(())
+        })?;
+        if editor.run_pending_command()? {
+            continue;
+        }
+        let event = crossterm::event::read()?;
+        if let Event::Resize(width, height) = event {
+            area = Rect::new(0, 0, width, height);
+        }
+        editor.handle_event(area, &event)?;
+    };
+
+    Ok(exit_code)
 }
@@ -0,0 +1,91 @@
+use anyhow::{Context as _, bail};
+use camino::{Utf8Path, Utf8PathBuf};
+use std::{fmt, fs};
+
+/// The default session file, in the working directory.
+pub const DEFAULT_PATH: &str = "Session.blue";
+
+/// Editor state saved by `:mksession` and restored with `--session`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Session {
+    pub path: Option<Utf8PathBuf>,
+    pub anchor: usize,
+    pub head: usize,
+    pub vertical_scroll: usize,
+}
+
+impl Session {
+    /// # Errors
+    ///
+    /// Fails if the file can't be read or isn't a valid session.
+    pub fn load(path: &Utf8Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        Self::parse(&contents).with_context(|| format!("Invalid session file '{path}'"))
+    }
+
+    /// # Errors
+    ///
+    /// Fails if the file can't be written.
+    pub fn save(&self, path: &Utf8Path) -> anyhow::Result<()> {
+        fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    fn parse(contents: &str) -> anyhow::Result<Self> {
+        let mut session = Self::default();
+        for line in contents.lines() {
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = line.split_once(' ') else {
+                bail!("Expected a key and value: '{line}'");
+            };
+            match key {
+                "path" => session.path = Some(Utf8PathBuf::from(value)),
+                "anchor" => session.anchor = value.parse()?,
+                "head" => session.head = value.parse()?,
+                "vertical-scroll" => session.vertical_scroll = value.parse()?,
+                _ => bail!("Unknown key '{key}'"),
+            }
+        }
+        Ok(session)
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.path {
+            writeln!(f, "path {path}")?;
+        }
+        writeln!(f, "anchor {}", self.anchor)?;
+        writeln!(f, "head {}", self.head)?;
+        writeln!(f, "vertical-scroll {}", self.vertical_scroll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let session = Session {
+            path: Some(Utf8PathBuf::from("/home/user/my file.txt")),
+            anchor: 3,
+            head: 10,
+            vertical_scroll: 2,
+        };
+        assert_eq!(Session::parse(&session.to_string()).unwrap(), session);
+        assert_eq!(
+            Session::parse("anchor 1\nhead 2\n").unwrap(),
+            Session {
+                path: None,
+                anchor: 1,
+                head: 2,
+                vertical_scroll: 0,
+            }
+        );
+        assert!(Session::parse("anchor x\n").is_err());
+        assert!(Session::parse("font mono\n").is_err());
+    }
+}
@@ -1,9 +1,13 @@
+use crate::clipboard;
+use blue::{CursorShape, CursorStyle};
 use crossterm::{
+    cursor::SetCursorStyle,
     event::{
-        DisableMouseCapture, EnableMouseCapture, KeyboardEnhancementFlags,
-        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+        DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture,
+        KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
     },
     execute,
+    style::Print,
     terminal::{Clear, ClearType},
 };
 use std::{
@@ -21,11 +25,28 @@ pub fn init() -> TerminalGuard {
         Clear(ClearType::All),
         PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES),
         EnableMouseCapture,
+        EnableBracketedPaste,
     )
     .unwrap();
     TerminalGuard(terminal)
 }
 
+pub fn set_cursor_style(style: CursorStyle) -> io::Result<()> {
+    let style = match (style.shape, style.blink) {
+        (CursorShape::Block, false) => SetCursorStyle::SteadyBlock,
+        (CursorShape::Block, true) => SetCursorStyle::BlinkingBlock,
+        (CursorShape::Bar, false) => SetCursorStyle::SteadyBar,
+        (CursorShape::Bar, true) => SetCursorStyle::BlinkingBar,
+        (CursorShape::Underline, false) => SetCursorStyle::SteadyUnderScore,
+        (CursorShape::Underline, true) => SetCursorStyle::BlinkingUnderScore,
+    };
+    execute!(io::stdout(), style)
+}
+
+pub fn set_clipboard(text: &str) -> io::Result<()> {
+    execute!(io::stdout(), Print(clipboard::osc52(text)))
+}
+
 impl Deref for TerminalGuard {
     type Target = ratatui::DefaultTerminal;
     fn deref(&self) -> &Self::Target {
@@ -41,6 +62,8 @@ impl DerefMut for TerminalGuard {
 
 impl Drop for TerminalGuard {
     fn drop(&mut self) {
+        // When unwinding from a panic, the hook installed by `ratatui::init` has already left raw
+        // mode and the alternate screen, so only the rest needs undoing.
         if !thread::panicking() {
             ratatui::restore();
         }
@@ -48,6 +71,8 @@ impl Drop for TerminalGuard {
             io::stdout(),
             PopKeyboardEnhancementFlags,
             DisableMouseCapture,
+            DisableBracketedPaste,
+            SetCursorStyle::DefaultUserShape,
         );
     }
 }
@@ -0,0 +1,2417 @@
+use crate::{
+    diff::{self, Sign},
+    display_width::{DisplayWidth as _, grapheme_width},
+    editor::{Clipboard, Completion, Editor, EolCursor, LineEnding, LineNumbers, Mode, Selection},
+    fold::{self, Fold},
+    graphemes::{floor_grapheme_boundary, prev_grapheme_boundary},
+};
+use crop::{Rope, RopeSlice};
+use crossterm::event::{Event, KeyCode, KeyModifiers, MouseButton, MouseEventKind};
+use ratatui::prelude::*;
+use std::{
+    cmp::{max, min},
+    iter::zip,
+};
+use unicode_segmentation::UnicodeSegmentation as _;
+
+impl Editor {
+    /// Draw the text, its gutters, and the status bar into `area` of `buffer`.
+    pub fn render(&self, area: Rect, buffer: &mut Buffer) {
+        render(self, area, buffer);
+    }
+
+    /// Where the terminal's cursor goes after rendering into `area`, if it should be shown.
+    #[must_use]
+    pub fn cursor_position(&self, area: Rect) -> Option<Position> {
+        cursor_position(self, area)
+    }
+
+    /// Handle a key, mouse, or paste event for the editor rendered into `area`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a command run by the event does, like `:write` failing to save the file.
+    pub fn handle_event(&mut self, area: Rect, event: &Event) -> anyhow::Result<()> {
+        update(self, area, event)
+    }
+}
+
+const LIGHT_RED: Color = Color::Rgb(0xff, 0xdc, 0xe0);
+
+const ORANGE: Color = Color::Rgb(0xfb, 0x8f, 0x44);
+
+const DARK_ORANGE: Color = Color::Rgb(0xd1, 0x57, 0x04);
+
+const DARK_GREEN: Color = Color::Rgb(0x1a, 0x7f, 0x37);
+
+const DARK_RED: Color = Color::Rgb(0xcf, 0x22, 0x2e);
+
+const LIGHT_YELLOW: Color = Color::Rgb(0xff, 0xf5, 0xb1);
+
+const DARK_YELLOW: Color = Color::Rgb(0xff, 0xd3, 0x3d);
+
+const LIGHT_BLUE: Color = Color::Rgb(0xdd, 0xf4, 0xff);
+
+const LIGHT_ORANGE: Color = Color::Rgb(0xff, 0xd8, 0xb5);
+
+const GRAY: Color = Color::Rgb(0x8c, 0x95, 0x9f);
+
+// How far to look for the bracket matching the one under the cursor while drawing, so huge files
+// don't slow down every frame.
+const MATCHING_BRACKET_LIMIT: usize = 16 * 1024;
+
+struct Areas {
+    status_bar: Rect,
+    signs: Rect,
+    line_numbers: Rect,
+    text: Rect,
+}
+
+impl Areas {
+    fn new(text: &Rope, area: Rect) -> Self {
+        let line_numbers_width = {
+            let n = text.line_len();
+            let digits = 1 + max(1, n).ilog10();
+            u16::try_from(max(2, digits) + 1)
+                .expect("Line numbers width should always be very small")
+        };
+        let [status_bar, main] = Layout::vertical([
+            // status bar
+            Constraint::Length(1),
+            // signs + line_numbers + text
+            Constraint::Fill(1),
+        ])
+        .areas(area);
+        let [signs, line_numbers, text] = Layout::horizontal([
+            // signs
+            Constraint::Length(1),
+            // line_numbers
+            Constraint::Length(line_numbers_width),
+            // fill
+            Constraint::Fill(1),
+        ])
+        .areas(main);
+        Self {
+            status_bar,
+            signs,
+            line_numbers,
+            text,
+        }
+    }
+}
+
+fn render(editor: &Editor, area: Rect, buffer: &mut Buffer) {
+    let areas = Areas::new(&editor.text, area);
+    render_status_bar(editor, areas.status_bar, buffer);
+    render_signs(editor, areas.signs, buffer);
+    render_selection_extent(editor, areas.signs, buffer);
+    render_line_numbers(editor, areas.line_numbers, buffer);
+    render_text(editor, areas.text, buffer);
+    render_search_matches(editor, areas.text, buffer);
+    render_selections(editor, areas.text, buffer);
+    render_matching_bracket(editor, areas.text, buffer);
+}
+
+fn render_status_bar(editor: &Editor, area: Rect, buffer: &mut Buffer) {
+    if let Some(message) = &editor.message {
+        match message {
+            Ok(message) => Line::raw(message).underlined().render(area, buffer),
+            Err(message) => Line::raw(message)
+                .underlined()
+                .bg(LIGHT_RED)
+                .render(area, buffer),
+        }
+    } else if let Some(confirm) = &editor.confirm {
+        let status_bar = format!("Replace with '{}'? (y/n/a/q)", confirm.replacement);
+        Line::raw(status_bar).underlined().render(area, buffer);
+    } else if let Some(completion) = editor.completion() {
+        render_completion(completion, area, buffer);
+    } else if let Mode::Command | Mode::Search = editor.mode {
+        let prompt = if editor.mode == Mode::Search {
+            '/'
+        } else {
+            ':'
+        };
+        let status_bar = format!("{prompt}{}", editor.command);
+        let status_bar = skip_columns(&status_bar, command_scroll(editor, area), editor.tab_width);
+        Line::raw(status_bar).underlined().render(area, buffer);
+        // The search options in effect go on the right, if there's room beside the query.
+        let flags = search_flags(editor);
+        if editor.mode == Mode::Search
+            && !flags.is_empty()
+            && command_cursor_column(editor) + flags.as_str().display_width(editor.tab_width) + 2
+                < usize::from(area.width)
+        {
+            Line::raw(flags)
+                .underlined()
+                .right_aligned()
+                .render(area, buffer);
+        }
+        let cursor_x = command_cursor_x(editor, area);
+        if let Some(cell) = buffer.cell_mut((cursor_x, area.y)) {
+            cell.set_bg(DARK_YELLOW);
+        }
+    } else {
+        let mode = match editor.mode {
+            Mode::Normal => "normal",
+            Mode::Goto => "goto",
+            Mode::Next => "next",
+            Mode::Prev => "prev",
+            Mode::View => "view",
+            Mode::Replace => "replace",
+            Mode::Surround => "surround",
+            Mode::Find { till: false, .. } => "find",
+            Mode::Find { till: true, .. } => "till",
+            Mode::SetMark => "mark",
+            Mode::GotoMark => "jump",
+            Mode::Insert => "insert",
+            Mode::Command | Mode::Search | Mode::Confirm => unreachable!(),
+        };
+        let count = editor
+            .count
+            .map(|count| format!(" {count}"))
+            .unwrap_or_default();
+        let path = display_path(editor);
+        let modified = if editor.modified { "*" } else { "" };
+        let buffers = if editor.buffer_count() > 1 {
+            format!(" [{}/{}]", editor.buffer_index() + 1, editor.buffer_count())
+        } else {
+            String::new()
+        };
+        let (line, column) = cursor_line_column(editor);
+        let selected = selected_graphemes(editor);
+        let selected = if selected > 1 {
+            format!(" ({selected} sel)")
+        } else {
+            String::new()
+        };
+        let indent = if editor.expand_tab {
+            format!("spaces:{}", editor.indent_width)
+        } else {
+            String::from("tabs")
+        };
+        let line_ending = match editor.line_ending {
+            LineEnding::Lf => "",
+            LineEnding::Crlf => " · crlf",
+        };
+        let filetype = editor
+            .filetype
+            .as_ref()
+            .map(|filetype| format!(" · {filetype}"))
+            .unwrap_or_default();
+        let status_bar = format!(
+            "{mode}{count} · {path}{modified}{buffers} {line}:{column}{selected} · {indent}{line_ending}{filetype}"
+        );
+        Line::raw(status_bar).underlined().render(area, buffer);
+    }
+}
+
+/// The cursor's line and display column, both counted from one.
+fn cursor_line_column(editor: &Editor) -> (usize, usize) {
+    let cursor = editor.cursor();
+    let line_index = editor.text.line_of_byte(cursor);
+    let column = editor
+        .text
+        .byte_slice(editor.text.byte_of_line(line_index)..cursor)
+        .display_width(editor.tab_width);
+    (line_index + 1, column + 1)
+}
+
+// How many graphemes the primary selection covers. The cursor alone covers one.
+fn selected_graphemes(editor: &Editor) -> usize {
+    let selection = editor.primary();
+    editor
+        .text
+        .byte_slice(selection.start()..selection.end())
+        .graphemes()
+        .count()
+}
+
+// How searches match, when it's different from matching the query exactly.
+fn search_flags(editor: &Editor) -> String {
+    let case = if editor.ignore_case {
+        Some("ignore-case")
+    } else if editor.smart_case {
+        Some("smart-case")
+    } else {
+        None
+    };
+    let whole_word = editor.whole_word.then_some("whole-word");
+    case.into_iter()
+        .chain(whole_word)
+        .collect::<Vec<_>>()
+        .join(" · ")
+}
+
+// The cursor's display column in the command line, counting the prompt.
+fn command_cursor_column(editor: &Editor) -> usize {
+    1 + editor
+        .command
+        .byte_slice(..editor.command_cursor)
+        .display_width(editor.tab_width)
+}
+
+/// How many columns the command line is scrolled by, so the cursor stays inside `area` when the
+/// command is too long to fit.
+fn command_scroll(editor: &Editor, area: Rect) -> usize {
+    (command_cursor_column(editor) + 1).saturating_sub(usize::from(area.width))
+}
+
+fn command_cursor_x(editor: &Editor, area: Rect) -> u16 {
+    let column = command_cursor_column(editor) - command_scroll(editor, area);
+    area.x + u16::try_from(column).expect("Cursor should be inside the status bar")
+}
+
+/// `text` without its first `columns` display columns. A wide grapheme which is cut in half is
+/// replaced by spaces.
+fn skip_columns(text: &str, columns: usize, tab_width: usize) -> String {
+    let mut skipped = 0;
+    let mut result = String::new();
+    for grapheme in text.graphemes(true) {
+        if skipped < columns {
+            skipped += grapheme_width(grapheme, skipped, tab_width);
+            if skipped > columns {
+                result.push_str(&" ".repeat(skipped - columns));
+            }
+        } else {
+            result.push_str(grapheme);
+        }
+    }
+    result
+}
+
+/// Where to show the terminal's cursor, whose shape depends on the mode.
+fn cursor_position(editor: &Editor, area: Rect) -> Option<Position> {
+    let areas = Areas::new(&editor.text, area);
+    if let Mode::Command | Mode::Search = editor.mode {
+        // Messages and completion matches replace the command line.
+        return (editor.message.is_none() && editor.completion().is_none()).then(|| {
+            Position::new(
+                command_cursor_x(editor, areas.status_bar),
+                areas.status_bar.y,
+            )
+        });
+    }
+    byte_offset_to_area(
+        &editor.text,
+        editor.vertical_scroll,
+        &editor.folds,
+        editor.tab_width,
+        areas.text,
+        editor.cursor(),
+    )
+    .map(Rect::as_position)
+}
+
+/// The buffer's path relative to `pwd`, unless absolute paths are preferred or shorter.
+fn display_path(editor: &Editor) -> String {
+    editor.display_path(editor.path.as_deref())
+}
+
+// List the completion matches in place of the command line, with the one filled in highlighted.
+fn render_completion(completion: &Completion, area: Rect, buffer: &mut Buffer) {
+    let mut spans = Vec::new();
+    for (index, candidate) in completion.candidates.iter().enumerate() {
+        if index > 0 {
+            spans.push(Span::raw("  "));
+        }
+        let span = Span::raw(candidate.as_str());
+        spans.push(if completion.index == Some(index) {
+            span.bg(DARK_YELLOW)
+        } else {
+            span
+        });
+    }
+    Line::from(spans).underlined().render(area, buffer);
+}
+
+fn render_signs(editor: &Editor, area: Rect, buffer: &mut Buffer) {
+    let signs = diff::signs(&editor.hunks(), editor.text.line_len());
+    for (line_index, row) in zip(
+        fold::visible_lines(&editor.folds, editor.vertical_scroll),
+        area.rows(),
+    ) {
+        let Some(sign) = signs.get(line_index) else {
+            break;
+        };
+        match sign {
+            None => {}
+            Some(Sign::Added) => Line::raw("+").fg(DARK_GREEN).render(row, buffer),
+            Some(Sign::Changed) => Line::raw("~").fg(DARK_ORANGE).render(row, buffer),
+            Some(Sign::Removed) => Line::raw("-").fg(DARK_RED).render(row, buffer),
+        }
+    }
+}
+
+/// Arrows at the top and bottom of the gutter when the selection continues off screen.
+fn render_selection_extent(editor: &Editor, area: Rect, buffer: &mut Buffer) {
+    if editor.anchor == editor.head || area.is_empty() {
+        return;
+    }
+    let start_line = editor.text.line_of_byte(min(editor.anchor, editor.head));
+    let end = max(editor.anchor, editor.head);
+    let end_line = editor
+        .text
+        .line_of_byte(prev_grapheme_boundary(&editor.text.byte_slice(..), end).unwrap_or(end));
+    let mut visible_lines = fold::visible_lines(&editor.folds, editor.vertical_scroll);
+    let first_line = visible_lines.next().unwrap();
+    let last_line = usize::from(area.height)
+        .checked_sub(2)
+        .and_then(|rest| visible_lines.nth(rest))
+        .unwrap_or(first_line);
+    if start_line < first_line {
+        Line::raw("↑")
+            .fg(DARK_YELLOW)
+            .render(area.rows().next().unwrap(), buffer);
+    }
+    if fold::visible_line(&editor.folds, end_line) > last_line {
+        Line::raw("↓")
+            .fg(DARK_YELLOW)
+            .render(area.rows().next_back().unwrap(), buffer);
+    }
+}
+
+fn render_line_numbers(editor: &Editor, area: Rect, buffer: &mut Buffer) {
+    let cursor_line = editor.text.line_of_byte(editor.cursor());
+    for (line_index, row) in zip(
+        fold::visible_lines(&editor.folds, editor.vertical_scroll)
+            .take_while(|line_index| *line_index < editor.text.line_len()),
+        area.rows(),
+    ) {
+        let number = match editor.line_numbers {
+            LineNumbers::Relative if line_index != cursor_line => line_index.abs_diff(cursor_line),
+            LineNumbers::Absolute | LineNumbers::Relative => line_index + 1,
+        };
+        Line::raw(format!("{number}│"))
+            .right_aligned()
+            .render(row, buffer);
+    }
+}
+
+fn render_text(editor: &Editor, area: Rect, buffer: &mut Buffer) {
+    for (line_index, row) in zip(
+        fold::visible_lines(&editor.folds, editor.vertical_scroll)
+            .take_while(|line_index| *line_index < editor.text.line_len()),
+        area.rows(),
+    ) {
+        let line = editor.text.line(line_index);
+        let mut spans = Vec::new();
+        let mut offset = 0;
+        // Tabs are expanded to spaces, so they need to know which column each span starts at.
+        let mut column = 0;
+        let mut untabify = |text: RopeSlice| {
+            let mut untabified = String::new();
+            for grapheme in text.graphemes() {
+                let width = grapheme_width(&grapheme, column, editor.tab_width);
+                if grapheme == "\t" {
+                    untabified.push_str(&" ".repeat(width));
+                } else {
+                    untabified.push_str(&grapheme);
+                }
+                column += width;
+            }
+            untabified
+        };
+        for (range, color) in editor.highlights(line_index) {
+            spans.push(Span::raw(untabify(line.byte_slice(offset..range.start))));
+            spans.push(Span::raw(untabify(line.byte_slice(range.clone()))).fg(color));
+            offset = range.end;
+        }
+        spans.push(Span::raw(untabify(line.byte_slice(offset..))));
+        // A closed fold shows its first line, followed by how many lines it hides.
+        if let Some(fold) = fold::closed_fold_at(&editor.folds, line_index) {
+            let hidden = fold.end - fold.start;
+            let s = if hidden == 1 { "" } else { "s" };
+            spans.push(Span::raw(format!(" ··· {hidden} more line{s}")).fg(DARK_YELLOW));
+        }
+        Line::from(spans).render(row, buffer);
+        if editor.list {
+            render_whitespace(editor, line_index, row, buffer);
+        }
+    }
+}
+
+/// Draw `:set list`'s glyphs over the whitespace in a line. Only the glyphs change, so a tab still
+/// takes up all of its columns.
+fn render_whitespace(editor: &Editor, line_index: usize, row: Rect, buffer: &mut Buffer) {
+    let line = editor.text.line(line_index);
+    let trailing = line.byte_len() - line.chars().rev().take_while(|c| *c == ' ').count();
+    let mut draw = |column: usize, glyph: char| {
+        if let Ok(column) = u16::try_from(column)
+            && column < row.width
+        {
+            buffer[(row.x + column, row.y)]
+                .set_char(glyph)
+                .set_style(Style::new().fg(GRAY));
+        }
+    };
+    let (mut offset, mut column) = (0, 0);
+    for grapheme in line.graphemes() {
+        if grapheme == "\t" {
+            draw(column, editor.list_chars.tab);
+        } else if grapheme == " " && offset >= trailing {
+            draw(column, editor.list_chars.trail);
+        }
+        offset += grapheme.len();
+        column += grapheme_width(&grapheme, column, editor.tab_width);
+    }
+    let has_newline =
+        editor.text.byte_of_line(line_index) + line.byte_len() < editor.text.byte_len();
+    if has_newline && fold::closed_fold_at(&editor.folds, line_index).is_none() {
+        draw(column, editor.list_chars.eol);
+    }
+}
+
+/// Highlight matches for the search being typed. Only the lines in view are searched, so typing
+/// stays quick in huge files.
+fn render_search_matches(editor: &Editor, area: Rect, buffer: &mut Buffer) {
+    if editor.mode != Mode::Search || editor.command.is_empty() {
+        return;
+    }
+    let regex = editor.search_regex(&editor.command.to_string());
+    for line_index in fold::visible_lines(&editor.folds, editor.vertical_scroll)
+        .take(usize::from(area.height))
+        .take_while(|line_index| *line_index < editor.text.line_len())
+    {
+        let line_start = editor.text.byte_of_line(line_index);
+        let line = editor.text.line(line_index).to_string();
+        for found in regex.find_iter(&line) {
+            for (offset, _) in found.as_str().grapheme_indices(true) {
+                if let Some(area) = byte_offset_to_area(
+                    &editor.text,
+                    editor.vertical_scroll,
+                    &editor.folds,
+                    editor.tab_width,
+                    area,
+                    line_start + found.start() + offset,
+                ) {
+                    buffer.set_style(area, Style::new().bg(LIGHT_ORANGE));
+                }
+            }
+        }
+    }
+}
+
+/// Draw every selection, with the primary selection's cursor on top.
+fn render_selections(editor: &Editor, area: Rect, buffer: &mut Buffer) {
+    for selection in &editor.selections {
+        render_selection(editor, selection, ORANGE, area, buffer);
+    }
+    let cursor_color = if editor.anchor == editor.head {
+        DARK_ORANGE
+    } else {
+        DARK_YELLOW
+    };
+    render_selection(editor, &editor.primary(), cursor_color, area, buffer);
+}
+
+fn render_selection(
+    editor: &Editor,
+    selection: &Selection,
+    cursor_color: Color,
+    area: Rect,
+    buffer: &mut Buffer,
+) {
+    if selection.anchor != selection.head {
+        let start = selection.start();
+        let end = selection.end();
+        let start_line = editor.text.line_of_byte(start);
+        // The start of the last selected grapheme.
+        let last = prev_grapheme_boundary(&editor.text.byte_slice(..), end).unwrap_or(end);
+        let end_line = editor.text.line_of_byte(last);
+        for line_index in start_line..=end_line {
+            let Some(mut line_area) = line_index_to_area(
+                &editor.text,
+                editor.vertical_scroll,
+                &editor.folds,
+                editor.tab_width,
+                area,
+                line_index,
+            ) else {
+                continue;
+            };
+            if line_index == start_line {
+                if let Some(start_area) = byte_offset_to_area(
+                    &editor.text,
+                    editor.vertical_scroll,
+                    &editor.folds,
+                    editor.tab_width,
+                    area,
+                    start,
+                ) {
+                    let delta = start_area.x - line_area.x;
+                    line_area.x += delta;
+                    line_area.width -= delta;
+                } else {
+                    // TODO: We continue here because we know the range start is off the screen to
+                    // the right. Once horizontal scrolling is added, we'll need to handle when the
+                    // range is off the screen to the left. `byte_offset_to_area` doesn't say which
+                    // direction the index is off screen.
+                    continue;
+                }
+            }
+            #[expect(clippy::collapsible_if)]
+            if line_index == end_line {
+                if let Some(end_area) = byte_offset_to_area(
+                    &editor.text,
+                    editor.vertical_scroll,
+                    &editor.folds,
+                    editor.tab_width,
+                    area,
+                    last,
+                ) {
+                    let delta = line_area.right() - end_area.right();
+                    line_area.width -= delta;
+                }
+            }
+            buffer.set_style(line_area, Style::new().bg(LIGHT_YELLOW));
+        }
+    }
+    let head = selection.cursor(&editor.text);
+    if let Some(area) = byte_offset_to_area(
+        &editor.text,
+        editor.vertical_scroll,
+        &editor.folds,
+        editor.tab_width,
+        area,
+        head,
+    ) {
+        buffer.set_style(area, Style::new().bg(cursor_color));
+        if editor.eol_cursor == EolCursor::Newline
+            && is_line_end(&editor.text, head)
+            && let Some(cell) = buffer.cell_mut(area.as_position())
+        {
+            cell.set_symbol("↵");
+        }
+    }
+}
+
+/// Highlight the bracket matching the one under the cursor, if it's on screen. The cursor's own
+/// bracket already stands out.
+fn render_matching_bracket(editor: &Editor, area: Rect, buffer: &mut Buffer) {
+    let Some(byte_offset) = editor.matching_bracket(editor.cursor(), MATCHING_BRACKET_LIMIT) else {
+        return;
+    };
+    if let Some(area) = byte_offset_to_area(
+        &editor.text,
+        editor.vertical_scroll,
+        &editor.folds,
+        editor.tab_width,
+        area,
+        byte_offset,
+    ) {
+        buffer.set_style(area, Style::new().bg(LIGHT_BLUE));
+    }
+}
+
+// TODO: Add tests for position conversions. Then try and simplify.
+
+/// The row where line `line_index` is drawn, or `None` if it's off screen or hidden in a fold.
+fn line_index_to_row(
+    folds: &[Fold],
+    vertical_scroll: usize,
+    area: Rect,
+    line_index: usize,
+) -> Option<u16> {
+    let row = fold::visible_lines(folds, vertical_scroll)
+        .take(usize::from(area.height))
+        .take_while(|visible_line| *visible_line <= line_index)
+        .position(|visible_line| visible_line == line_index)?;
+    Some(area.y + u16::try_from(row).unwrap())
+}
+
+fn byte_offset_to_area(
+    rope: &Rope,
+    vertical_scroll: usize,
+    folds: &[Fold],
+    tab_width: usize,
+    area: Rect,
+    byte_offset: usize,
+) -> Option<Rect> {
+    if byte_offset > rope.byte_len() {
+        return None;
+    }
+
+    let line_offset = rope.line_of_byte(byte_offset);
+
+    let y = line_index_to_row(folds, vertical_scroll, area, line_offset)?;
+
+    let line_byte_offset = rope.byte_of_line(line_offset);
+
+    let byte_offset = floor_grapheme_boundary(&rope.byte_slice(..), byte_offset);
+
+    let prefix_width = rope
+        .byte_slice(line_byte_offset..byte_offset)
+        .display_width(tab_width);
+
+    // TODO: When horizontal scroll is introduced, still return portion of rect that is visible.
+    // Even if it starts to the left of the area, it might be wide enough to peek into the viewport.
+    let x = area.x + u16::try_from(prefix_width).unwrap();
+
+    if !(area.left()..area.right()).contains(&x) {
+        return None;
+    }
+
+    let width = if is_line_end(rope, byte_offset) {
+        // End-of-line positions (a line terminator or EOF) always occupy the single column right
+        // after the line's text, whatever the terminator is.
+        1
+    } else if let Some(grapheme) = rope.byte_slice(byte_offset..).graphemes().next() {
+        u16::try_from(grapheme_width(grapheme.as_ref(), prefix_width, tab_width)).unwrap()
+    } else {
+        // We're at EOF, but we already checked for that
+        unreachable!()
+    };
+
+    // Wide graphemes may not fit in the last column.
+    let width = min(width, area.right() - x);
+
+    Some(Rect {
+        x,
+        y,
+        width,
+        height: 1,
+    })
+}
+
+fn is_line_end(rope: &Rope, byte_offset: usize) -> bool {
+    let line_offset = rope.line_of_byte(byte_offset);
+    if line_offset >= rope.line_len() {
+        return true;
+    }
+    byte_offset >= rope.byte_of_line(line_offset) + rope.line(line_offset).byte_len()
+}
+
+fn line_index_to_area(
+    rope: &Rope,
+    vertical_scroll: usize,
+    folds: &[Fold],
+    tab_width: usize,
+    area: Rect,
+    line_index: usize,
+) -> Option<Rect> {
+    if line_index >= rope.line_len() {
+        return None;
+    }
+
+    let x = area.x;
+
+    let y = line_index_to_row(folds, vertical_scroll, area, line_index)?;
+
+    let line = rope.line_slice(line_index..=line_index);
+
+    let width = u16::try_from(line.display_width(tab_width)).unwrap();
+
+    Some(Rect {
+        x,
+        y,
+        width,
+        height: 1,
+    })
+}
+
+fn position_to_byte_offset(
+    rope: &Rope,
+    vertical_scroll: usize,
+    folds: &[Fold],
+    tab_width: usize,
+    area: Rect,
+    position: Position,
+) -> Option<usize> {
+    if !area.contains(position) {
+        return None;
+    }
+
+    let target_column = usize::from(position.x - area.x);
+    let row = fold::visible_lines(folds, vertical_scroll)
+        .nth(usize::from(position.y - area.y))
+        .unwrap();
+
+    if row >= rope.line_len() {
+        return Some(rope.byte_len());
+    }
+
+    let mut current_column = 0;
+    let mut byte_offset = rope.byte_of_line(row);
+
+    for grapheme in rope.line(row).graphemes() {
+        let width = grapheme_width(grapheme.as_ref(), current_column, tab_width);
+        if current_column + width > target_column {
+            break;
+        }
+        current_column += width;
+        byte_offset += grapheme.len();
+    }
+
+    Some(byte_offset)
+}
+
+#[expect(clippy::too_many_lines)]
+fn update(editor: &mut Editor, area: Rect, event: &Event) -> anyhow::Result<()> {
+    let dismiss_message = match event {
+        Event::Mouse(mouse) => !matches!(
+            mouse.kind,
+            MouseEventKind::Moved
+                | MouseEventKind::ScrollUp
+                | MouseEventKind::ScrollDown
+                | MouseEventKind::ScrollLeft
+                | MouseEventKind::ScrollRight
+        ),
+        Event::Resize(_, _) => false,
+        _ => true,
+    };
+    if dismiss_message {
+        editor.message = None;
+    }
+    let areas = Areas::new(&editor.text, area);
+    let head = editor.head;
+    #[allow(clippy::match_same_arms)]
+    match event {
+        Event::Key(key) => match editor.mode {
+            Mode::Normal => {
+                let pending_count = editor.count.take();
+                let count = pending_count.unwrap_or(1);
+                match (key.modifiers, key.code) {
+                    // `0` only continues a count, otherwise it goes to the line start.
+                    (m, KeyCode::Char(digit @ '0'..='9'))
+                        if m == KeyModifiers::NONE && (digit != '0' || pending_count.is_some()) =>
+                    {
+                        editor.count = pending_count;
+                        editor.push_count(digit);
+                    }
+                    (m, KeyCode::Char('h')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(|editor| editor.move_left(count));
+                    }
+                    (m, KeyCode::Char('l')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(|editor| editor.move_right(count));
+                    }
+                    (m, KeyCode::Char('k')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(|editor| editor.move_up(count));
+                    }
+                    (m, KeyCode::Char('j')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(|editor| editor.move_down(count));
+                    }
+                    (m, KeyCode::Char('h' | 'H')) if m == KeyModifiers::SHIFT => {
+                        editor.for_each_selection(|editor| editor.extend_left(count));
+                    }
+                    (m, KeyCode::Char('l' | 'L')) if m == KeyModifiers::SHIFT => {
+                        editor.for_each_selection(|editor| editor.extend_right(count));
+                    }
+                    (m, KeyCode::Char('k' | 'K')) if m == KeyModifiers::SHIFT => {
+                        editor.for_each_selection(|editor| editor.extend_up(count));
+                    }
+                    (m, KeyCode::Char('j' | 'J')) if m == KeyModifiers::SHIFT => {
+                        editor.for_each_selection(|editor| editor.extend_down(count));
+                    }
+                    (m, KeyCode::Char('j')) if m == KeyModifiers::ALT => {
+                        editor.for_each_selection(Editor::join_lines);
+                    }
+                    (m, KeyCode::Char('0')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(Editor::move_line_start);
+                    }
+                    (m, KeyCode::Char('w')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(|editor| editor.move_next_word_start(count));
+                    }
+                    (m, KeyCode::Char('b')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(|editor| editor.move_prev_word_start(count));
+                    }
+                    (m, KeyCode::Char('e')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(|editor| editor.move_next_word_end(count));
+                    }
+                    (m, KeyCode::Char('w' | 'W')) if m == KeyModifiers::SHIFT => {
+                        editor.for_each_selection(|editor| editor.move_next_long_word_start(count));
+                    }
+                    (m, KeyCode::Char('b' | 'B')) if m == KeyModifiers::SHIFT => {
+                        editor.for_each_selection(|editor| editor.move_prev_long_word_start(count));
+                    }
+                    (m, KeyCode::Char('e' | 'E')) if m == KeyModifiers::SHIFT => {
+                        editor.for_each_selection(|editor| editor.move_next_long_word_end(count));
+                    }
+                    (m, KeyCode::Char(';')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(|editor| {
+                            editor.reduce();
+                            editor.update_desired_column();
+                        });
+                    }
+                    (m, KeyCode::Char(';')) if m == KeyModifiers::CONTROL => {
+                        editor.for_each_selection(Editor::reduce_to_anchor);
+                    }
+                    (m, KeyCode::Char(';')) if m == KeyModifiers::ALT => {
+                        editor.for_each_selection(Editor::flip);
+                    }
+                    (m, KeyCode::Char(';')) if m == KeyModifiers::SHIFT | KeyModifiers::ALT => {
+                        editor.for_each_selection(Editor::flip_forward);
+                    }
+                    (m, KeyCode::Char('d')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(Editor::delete);
+                    }
+                    (m, KeyCode::Char('d' | 'D')) if m == KeyModifiers::SHIFT => {
+                        editor.for_each_selection(Editor::delete_to_line_end);
+                    }
+                    (m, KeyCode::Char('y')) if m == KeyModifiers::NONE => editor.yank(),
+                    (m, KeyCode::Char('n')) if m == KeyModifiers::NONE => editor.search_next(),
+                    (m, KeyCode::Char('n' | 'N')) if m == KeyModifiers::SHIFT => {
+                        editor.search_prev();
+                    }
+                    (m, KeyCode::Char('p')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(Editor::paste_after);
+                    }
+                    (m, KeyCode::Char('p' | 'P')) if m == KeyModifiers::SHIFT => {
+                        editor.for_each_selection(Editor::paste_before);
+                    }
+                    (m, KeyCode::Char('x')) if m == KeyModifiers::NONE => {
+                        for _ in 0..count {
+                            editor.for_each_selection(Editor::select_line);
+                        }
+                    }
+                    (m, KeyCode::Char('#')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(Editor::toggle_comment);
+                    }
+                    (m, KeyCode::Char('m')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(Editor::goto_matching_bracket);
+                    }
+                    (m, KeyCode::Char('(')) if m == KeyModifiers::NONE => {
+                        editor.rotate_selections(false);
+                    }
+                    (m, KeyCode::Char(')')) if m == KeyModifiers::NONE => {
+                        editor.rotate_selections(true);
+                    }
+                    (m, KeyCode::Char('p')) if m == KeyModifiers::ALT => {
+                        editor.for_each_selection(Editor::paste_inserted);
+                    }
+                    (m, KeyCode::Char('(')) if m == KeyModifiers::ALT => {
+                        editor.rotate_contents(false);
+                    }
+                    (m, KeyCode::Char(')')) if m == KeyModifiers::ALT => {
+                        editor.rotate_contents(true);
+                    }
+                    (m, KeyCode::Char('%')) if m == KeyModifiers::NONE => {
+                        editor.select_all();
+                        // Selecting everything shouldn't scroll to the end of the text.
+                        return Ok(());
+                    }
+                    (m, KeyCode::Char('r')) if m == KeyModifiers::NONE => {
+                        editor.mode = Mode::Replace;
+                    }
+                    (m, KeyCode::Char('s')) if m == KeyModifiers::NONE => {
+                        editor.mode = Mode::Surround;
+                    }
+                    (m, KeyCode::Char(char @ ('f' | 't'))) if m == KeyModifiers::NONE => {
+                        editor.mode = Mode::Find {
+                            forward: true,
+                            till: char == 't',
+                        };
+                    }
+                    (m, KeyCode::Char(char @ ('f' | 'F' | 't' | 'T')))
+                        if m == KeyModifiers::SHIFT =>
+                    {
+                        editor.mode = Mode::Find {
+                            forward: false,
+                            till: char.eq_ignore_ascii_case(&'t'),
+                        };
+                    }
+                    (m, KeyCode::Char('~')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(Editor::toggle_case);
+                    }
+                    (m, KeyCode::Char('`')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(Editor::lowercase);
+                    }
+                    (m, KeyCode::Char('`')) if m == KeyModifiers::ALT => {
+                        editor.for_each_selection(Editor::uppercase);
+                    }
+                    (m, KeyCode::Tab) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(|editor| editor.indent(count));
+                    }
+                    (m, KeyCode::BackTab)
+                        if m == KeyModifiers::NONE || m == KeyModifiers::SHIFT =>
+                    {
+                        editor.for_each_selection(|editor| editor.dedent(count));
+                    }
+                    (m, KeyCode::Char('u')) if m == KeyModifiers::NONE => editor.undo(),
+                    (m, KeyCode::Char('u' | 'U')) if m == KeyModifiers::SHIFT => editor.redo(),
+                    (m, KeyCode::Char('c')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(Editor::delete);
+                        editor.mode = Mode::Insert;
+                    }
+                    (m, KeyCode::Char('a')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(Editor::move_after_cursor);
+                        editor.mode = Mode::Insert;
+                    }
+                    (m, KeyCode::Char('a' | 'A')) if m == KeyModifiers::SHIFT => {
+                        editor.for_each_selection(Editor::move_line_end);
+                        editor.mode = Mode::Insert;
+                    }
+                    (m, KeyCode::Char('o')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(Editor::open_below);
+                        editor.mode = Mode::Insert;
+                    }
+                    (m, KeyCode::Char('o' | 'O')) if m == KeyModifiers::SHIFT => {
+                        editor.for_each_selection(Editor::open_above);
+                        editor.mode = Mode::Insert;
+                    }
+                    (m, KeyCode::Char('i')) if m == KeyModifiers::NONE => {
+                        editor.for_each_selection(Editor::reduce);
+                        editor.mode = Mode::Insert;
+                    }
+                    (m, KeyCode::Char(':')) if m == KeyModifiers::NONE => {
+                        editor.command = Rope::new();
+                        editor.command_cursor = 0;
+                        editor.completion = None;
+                        editor.mode = Mode::Command;
+                    }
+                    (m, KeyCode::Char('/')) if m == KeyModifiers::NONE => editor.start_search(),
+                    (m, KeyCode::Char('o')) if m == KeyModifiers::CONTROL => editor.jump_back(),
+                    // Only terminals which report it separately from Tab send Ctrl-i.
+                    (m, KeyCode::Char('i')) if m == KeyModifiers::CONTROL => editor.jump_forward(),
+                    (m, KeyCode::Char('u')) if m == KeyModifiers::CONTROL => {
+                        let half_height = usize::from(areas.text.height.saturating_sub(1) / 2);
+                        editor.scroll_up(half_height);
+                    }
+                    (m, KeyCode::Char('d')) if m == KeyModifiers::CONTROL => {
+                        let half_height = usize::from(areas.text.height.saturating_sub(1) / 2);
+                        editor.scroll_down(half_height);
+                    }
+                    (m, KeyCode::Char('b')) if m == KeyModifiers::CONTROL => {
+                        let full_height = usize::from(areas.text.height.saturating_sub(2));
+                        editor.scroll_up(full_height);
+                    }
+                    (m, KeyCode::Char('f')) if m == KeyModifiers::CONTROL => {
+                        let full_height = usize::from(areas.text.height.saturating_sub(2));
+                        editor.scroll_down(full_height);
+                    }
+                    (m, KeyCode::Char('g')) if m == KeyModifiers::NONE => editor.mode = Mode::Goto,
+                    (m, KeyCode::Char(']')) if m == KeyModifiers::NONE => editor.mode = Mode::Next,
+                    (m, KeyCode::Char('[')) if m == KeyModifiers::NONE => editor.mode = Mode::Prev,
+                    (m, KeyCode::Char('z')) if m == KeyModifiers::NONE => editor.mode = Mode::View,
+                    (m, KeyCode::Char('z' | 'Z')) if m == KeyModifiers::SHIFT => {
+                        editor.mode = Mode::SetMark;
+                    }
+                    (m, KeyCode::Char('\'')) if m == KeyModifiers::NONE => {
+                        editor.mode = Mode::GotoMark;
+                    }
+                    (m, KeyCode::Char('c' | 'C')) if m == KeyModifiers::SHIFT => {
+                        for _ in 0..count {
+                            editor.copy_selection_below();
+                        }
+                    }
+                    (m, KeyCode::Char(',') | KeyCode::Esc) if m == KeyModifiers::NONE => {
+                        editor.keep_primary_selection();
+                    }
+                    _ => {}
+                }
+                // There's nothing to do in Insert mode without changing the text.
+                if editor.mode == Mode::Insert && !editor.check_writable() {
+                    editor.mode = Mode::Normal;
+                }
+            }
+            Mode::Goto => match (key.modifiers, key.code) {
+                (m, KeyCode::Char('k')) if m == KeyModifiers::NONE => {
+                    editor.move_to(0);
+                    editor.mode = Mode::Normal;
+                }
+                (m, KeyCode::Char('h')) if m == KeyModifiers::NONE => {
+                    editor.for_each_selection(Editor::move_smart_line_start);
+                    editor.mode = Mode::Normal;
+                }
+                (m, KeyCode::Char('l')) if m == KeyModifiers::NONE => {
+                    editor.for_each_selection(Editor::move_line_end);
+                    editor.mode = Mode::Normal;
+                }
+                (m, KeyCode::Char('h' | 'H')) if m == KeyModifiers::SHIFT => {
+                    editor.for_each_selection(Editor::extend_smart_line_start);
+                    editor.mode = Mode::Normal;
+                }
+                (m, KeyCode::Char('l' | 'L')) if m == KeyModifiers::SHIFT => {
+                    editor.for_each_selection(Editor::extend_line_end);
+                    editor.mode = Mode::Normal;
+                }
+                (m, KeyCode::Esc) if m == KeyModifiers::NONE => editor.mode = Mode::Normal,
+                _ => {
+                    editor.message = Some(Err(String::from("Unknown key")));
+                    editor.mode = Mode::Normal;
+                }
+            },
+            Mode::Next => match (key.modifiers, key.code) {
+                (m, KeyCode::Char('p')) if m == KeyModifiers::NONE => {
+                    editor.for_each_selection(Editor::paste_reindent);
+                    editor.mode = Mode::Normal;
+                }
+                (m, KeyCode::Char('c')) if m == KeyModifiers::NONE => {
+                    editor.move_next_hunk();
+                    editor.mode = Mode::Normal;
+                }
+                (m, KeyCode::Esc) if m == KeyModifiers::NONE => editor.mode = Mode::Normal,
+                _ => {
+                    editor.message = Some(Err(String::from("Unknown key")));
+                    editor.mode = Mode::Normal;
+                }
+            },
+            Mode::Prev => match (key.modifiers, key.code) {
+                (m, KeyCode::Char('c')) if m == KeyModifiers::NONE => {
+                    editor.move_prev_hunk();
+                    editor.mode = Mode::Normal;
+                }
+                (m, KeyCode::Esc) if m == KeyModifiers::NONE => editor.mode = Mode::Normal,
+                _ => {
+                    editor.message = Some(Err(String::from("Unknown key")));
+                    editor.mode = Mode::Normal;
+                }
+            },
+            Mode::View => {
+                match (key.modifiers, key.code) {
+                    (m, KeyCode::Char('f')) if m == KeyModifiers::NONE => editor.fold(),
+                    (m, KeyCode::Char('o')) if m == KeyModifiers::NONE => editor.open_fold(),
+                    (m, KeyCode::Char('c')) if m == KeyModifiers::NONE => editor.close_fold(),
+                    (m, KeyCode::Char('a')) if m == KeyModifiers::NONE => editor.toggle_fold(),
+                    (m, KeyCode::Char('d')) if m == KeyModifiers::NONE => editor.delete_fold(),
+                    (m, KeyCode::Char('z')) if m == KeyModifiers::NONE => {
+                        editor.scroll_cursor_center(usize::from(areas.text.height));
+                    }
+                    (m, KeyCode::Char('t')) if m == KeyModifiers::NONE => {
+                        editor.scroll_cursor_top(usize::from(areas.text.height));
+                    }
+                    (m, KeyCode::Char('b')) if m == KeyModifiers::NONE => {
+                        editor.scroll_cursor_bottom(usize::from(areas.text.height));
+                    }
+                    (m, KeyCode::Esc) if m == KeyModifiers::NONE => {}
+                    _ => editor.message = Some(Err(String::from("Unknown key"))),
+                }
+                editor.mode = Mode::Normal;
+            }
+            Mode::Replace => {
+                match (key.modifiers, key.code) {
+                    (m, KeyCode::Char(char))
+                        if m == KeyModifiers::NONE || m == KeyModifiers::SHIFT =>
+                    {
+                        editor.for_each_selection(|editor| editor.replace_with(char));
+                    }
+                    (m, KeyCode::Esc) if m == KeyModifiers::NONE => {}
+                    _ => editor.message = Some(Err(String::from("Unknown key"))),
+                }
+                editor.mode = Mode::Normal;
+            }
+            Mode::Surround => {
+                match (key.modifiers, key.code) {
+                    (m, KeyCode::Char(char))
+                        if m == KeyModifiers::NONE || m == KeyModifiers::SHIFT =>
+                    {
+                        editor.for_each_selection(|editor| editor.surround(char));
+                    }
+                    (m, KeyCode::Esc) if m == KeyModifiers::NONE => {}
+                    _ => editor.message = Some(Err(String::from("Unknown key"))),
+                }
+                editor.mode = Mode::Normal;
+            }
+            Mode::Find { forward, till } => {
+                match (key.modifiers, key.code) {
+                    (m, KeyCode::Char(char))
+                        if m == KeyModifiers::NONE || m == KeyModifiers::SHIFT =>
+                    {
+                        editor.for_each_selection(|editor| editor.extend_find(char, forward, till));
+                    }
+                    (m, KeyCode::Esc) if m == KeyModifiers::NONE => {}
+                    _ => editor.message = Some(Err(String::from("Unknown key"))),
+                }
+                editor.mode = Mode::Normal;
+            }
+            Mode::SetMark | Mode::GotoMark => {
+                match (key.modifiers, key.code) {
+                    (m, KeyCode::Char(name))
+                        if m == KeyModifiers::NONE || m == KeyModifiers::SHIFT =>
+                    {
+                        if editor.mode == Mode::SetMark {
+                            editor.set_mark(name);
+                        } else {
+                            editor.goto_mark(name);
+                        }
+                    }
+                    (m, KeyCode::Esc) if m == KeyModifiers::NONE => {}
+                    _ => editor.message = Some(Err(String::from("Unknown key"))),
+                }
+                editor.mode = Mode::Normal;
+            }
+            Mode::Confirm => match (key.modifiers, key.code) {
+                (m, KeyCode::Char('y')) if m == KeyModifiers::NONE => editor.confirm_replace(),
+                (m, KeyCode::Char('n')) if m == KeyModifiers::NONE => editor.confirm_skip(),
+                (m, KeyCode::Char('a')) if m == KeyModifiers::NONE => {
+                    editor.confirm_replace_all();
+                }
+                (m, KeyCode::Char('q') | KeyCode::Esc) if m == KeyModifiers::NONE => {
+                    editor.stop_confirm();
+                }
+                _ => {}
+            },
+            Mode::Insert => match (key.modifiers, key.code) {
+                (m, KeyCode::Char('a')) if m == KeyModifiers::CONTROL => {
+                    editor.break_insert();
+                    editor.for_each_selection(Editor::move_line_start);
+                }
+                (m, KeyCode::Char('e')) if m == KeyModifiers::CONTROL => {
+                    editor.break_insert();
+                    editor.for_each_selection(Editor::move_line_end);
+                }
+                (m, KeyCode::Char('b')) if m == KeyModifiers::CONTROL => {
+                    editor.break_insert();
+                    editor.for_each_selection(|editor| editor.move_left(1));
+                }
+                (m, KeyCode::Char('f')) if m == KeyModifiers::CONTROL => {
+                    editor.break_insert();
+                    editor.for_each_selection(|editor| editor.move_right(1));
+                }
+                (m, KeyCode::Char('w')) if m == KeyModifiers::CONTROL => {
+                    let len = editor.head - editor.word_before_start();
+                    editor.for_each_selection(Editor::delete_word_before);
+                    editor.uninsert(len);
+                }
+                (m, KeyCode::Left) if m == KeyModifiers::NONE => {
+                    editor.break_insert();
+                    editor.for_each_selection(|editor| editor.move_left(1));
+                }
+                (m, KeyCode::Right) if m == KeyModifiers::NONE => {
+                    editor.break_insert();
+                    editor.for_each_selection(|editor| editor.move_right(1));
+                }
+                (m, KeyCode::Up) if m == KeyModifiers::NONE => {
+                    editor.break_insert();
+                    editor.for_each_selection(|editor| editor.move_up(1));
+                }
+                (m, KeyCode::Down) if m == KeyModifiers::NONE => {
+                    editor.break_insert();
+                    editor.for_each_selection(|editor| editor.move_down(1));
+                }
+                (m, KeyCode::Char(char)) if m == KeyModifiers::NONE || m == KeyModifiers::SHIFT => {
+                    editor.for_each_selection(|editor| editor.insert_char(char));
+                    editor.inserting.push(char);
+                }
+                (m, KeyCode::Tab) if m == KeyModifiers::NONE => {
+                    editor.for_each_selection(|editor| editor.insert(&editor.indent_unit()));
+                    editor.inserting.push_str(&editor.indent_unit());
+                }
+                (m, KeyCode::Enter) if m == KeyModifiers::NONE => {
+                    editor.for_each_selection(|editor| editor.insert("\n"));
+                    editor.inserting.push('\n');
+                }
+                (m, KeyCode::Backspace) if m == KeyModifiers::NONE => {
+                    editor.for_each_selection(Editor::delete_before);
+                    editor.inserting.pop();
+                }
+                (m, KeyCode::Delete) if m == KeyModifiers::NONE => {
+                    editor.for_each_selection(Editor::delete_after);
+                }
+                (m, KeyCode::Esc) if m == KeyModifiers::NONE => editor.leave_insert_mode(),
+                _ => {}
+            },
+            Mode::Command | Mode::Search => {
+                let query = editor.command.clone();
+                match (key.modifiers, key.code) {
+                    (m, KeyCode::Char('a')) if m == KeyModifiers::CONTROL => {
+                        editor.command_cursor = 0;
+                    }
+                    (m, KeyCode::Char('e')) if m == KeyModifiers::CONTROL => {
+                        editor.command_cursor = editor.command.byte_len();
+                    }
+                    (m, KeyCode::Left) if m == KeyModifiers::NONE => {
+                        editor.command_mode_move_left(1);
+                    }
+                    (m, KeyCode::Right) if m == KeyModifiers::NONE => {
+                        editor.command_mode_move_right(1);
+                    }
+                    (m, KeyCode::Char('b')) if m == KeyModifiers::CONTROL => {
+                        editor.command_mode_move_left(1);
+                    }
+                    (m, KeyCode::Char('f')) if m == KeyModifiers::CONTROL => {
+                        editor.command_mode_move_right(1);
+                    }
+                    (m, KeyCode::Char('u')) if m == KeyModifiers::CONTROL => {
+                        editor.command_mode_delete_before();
+                    }
+                    (m, KeyCode::Char('k')) if m == KeyModifiers::CONTROL => {
+                        editor.command_mode_delete_after();
+                    }
+                    (m, KeyCode::Tab)
+                        if m == KeyModifiers::NONE && editor.mode == Mode::Command =>
+                    {
+                        editor.complete();
+                    }
+                    (m, KeyCode::BackTab)
+                        if (m == KeyModifiers::NONE || m == KeyModifiers::SHIFT)
+                            && editor.mode == Mode::Command =>
+                    {
+                        editor.complete_prev();
+                    }
+                    (m, KeyCode::Char(char))
+                        if m == KeyModifiers::NONE || m == KeyModifiers::SHIFT =>
+                    {
+                        let string = char.to_string();
+                        editor.command.insert(editor.command_cursor, &string);
+                        editor.command_cursor += string.len();
+                    }
+                    (m, KeyCode::Backspace) if m == KeyModifiers::NONE => {
+                        if editor.command_cursor > 0 {
+                            debug_assert!(!editor.command.is_empty());
+                            if let Some(prev) = prev_grapheme_boundary(
+                                &editor.command.byte_slice(..),
+                                editor.command_cursor,
+                            ) {
+                                editor.command.delete(prev..editor.command_cursor);
+                                editor.command_cursor = prev;
+                            }
+                        } else if editor.command.is_empty() && editor.mode == Mode::Search {
+                            editor.cancel_search();
+                        } else if editor.command.is_empty() {
+                            debug_assert!(editor.command_cursor == 0);
+                            editor.command = Rope::new();
+                            editor.command_cursor = 0;
+                            editor.mode = Mode::Normal;
+                        }
+                    }
+                    (m, KeyCode::Enter) if m == KeyModifiers::NONE => {
+                        if editor.mode == Mode::Search {
+                            editor.execute_search();
+                        } else if !editor.accept_completion() {
+                            editor.execute_command()?;
+                        }
+                    }
+                    (m, KeyCode::Esc) if m == KeyModifiers::NONE && editor.mode == Mode::Search => {
+                        editor.cancel_search();
+                    }
+                    (m, KeyCode::Esc) if m == KeyModifiers::NONE => {
+                        editor.command = Rope::new();
+                        editor.command_cursor = 0;
+                        editor.mode = Mode::Normal;
+                    }
+                    _ => {}
+                }
+                // Matches follow the query as it's typed.
+                if editor.mode == Mode::Search && editor.command != query {
+                    editor.update_search();
+                }
+            }
+        },
+        Event::Mouse(mouse) => match mouse.kind {
+            MouseEventKind::ScrollUp => editor.scroll_up(3),
+            MouseEventKind::ScrollDown => editor.scroll_down(3),
+            // Move
+            MouseEventKind::Down(MouseButton::Left) => {
+                if let Some(byte_offset) = position_to_byte_offset(
+                    &editor.text,
+                    editor.vertical_scroll,
+                    &editor.folds,
+                    editor.tab_width,
+                    areas.text,
+                    Position::new(mouse.column, mouse.row),
+                ) {
+                    editor.break_insert();
+                    editor.move_to(byte_offset);
+                }
+            }
+            // Extend
+            MouseEventKind::Down(MouseButton::Right)
+            | MouseEventKind::Drag(MouseButton::Left | MouseButton::Right) => {
+                if let Some(byte_offset) = position_to_byte_offset(
+                    &editor.text,
+                    editor.vertical_scroll,
+                    &editor.folds,
+                    editor.tab_width,
+                    areas.text,
+                    Position::new(mouse.column, mouse.row),
+                ) {
+                    editor.break_insert();
+                    editor.extend_to(byte_offset);
+                }
+            }
+            _ => {}
+        },
+        Event::Paste(text) => {
+            // Terminals usually send line breaks in pasted text as carriage returns.
+            let text = text.replace("\r\n", "\n").replace('\r', "\n");
+            match editor.mode {
+                Mode::Insert => {
+                    editor.for_each_selection(|editor| editor.insert(&text));
+                    editor.inserting.push_str(&text);
+                }
+                Mode::Normal if editor.clipboard == Clipboard::System => {
+                    editor.register = text;
+                    editor.for_each_selection(Editor::paste_after);
+                }
+                _ => {}
+            }
+        }
+        // `area` is already the new size. If the window shrank, the cursor might be off screen.
+        Event::Resize(_, _) => editor.scroll_to_head(usize::from(areas.text.height)),
+        _ => {}
+    }
+    // Follow the cursor, but only when it moves, so scrolling doesn't snap back to it.
+    if editor.head != head {
+        let areas = Areas::new(&editor.text, area);
+        editor.scroll_to_head(usize::from(areas.text.height));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::tests::temp_dir;
+    use camino::Utf8PathBuf;
+    use crossterm::event::KeyEvent;
+
+    #[test]
+    fn test_normal_mode_tab() {
+        let mut editor = Editor::try_from(Rope::from("a\nb\n")).unwrap();
+        let area = Rect::new(0, 0, 20, 5);
+        let press = |editor: &mut Editor, modifiers, code| {
+            let event = Event::Key(KeyEvent::new(code, modifiers));
+            update(editor, area, &event).unwrap();
+        };
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Tab);
+        assert_eq!(editor.text, "\ta\nb\n");
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Tab);
+        assert_eq!(editor.text, "\t\ta\nb\n");
+        press(&mut editor, KeyModifiers::SHIFT, KeyCode::BackTab);
+        assert_eq!(editor.text, "\ta\nb\n");
+        assert!(editor.mode == Mode::Normal);
+    }
+
+    #[test]
+    fn test_insert_mode_arrows() {
+        let mut editor = Editor::try_from(Rope::from("abc\nd\nefg\n")).unwrap();
+        let area = Rect::new(0, 0, 20, 5);
+        let press = |editor: &mut Editor, code| {
+            let event = Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+            update(editor, area, &event).unwrap();
+            assert_eq!(editor.anchor, editor.head);
+            editor.head
+        };
+        editor.mode = Mode::Insert;
+        editor.head = 2;
+        editor.reduce();
+        editor.update_desired_column();
+        // Moving down through a shorter line keeps the column.
+        assert_eq!(press(&mut editor, KeyCode::Down), 5);
+        assert_eq!(press(&mut editor, KeyCode::Down), 8);
+        assert_eq!(press(&mut editor, KeyCode::Left), 7);
+        assert_eq!(press(&mut editor, KeyCode::Right), 8);
+        assert_eq!(press(&mut editor, KeyCode::Up), 5);
+        assert!(editor.mode == Mode::Insert);
+    }
+
+    #[test]
+    fn test_insert_mode_undo() {
+        let mut editor = Editor::new().unwrap();
+        let area = Rect::new(0, 0, 20, 5);
+        let press = |editor: &mut Editor, codes: &[KeyCode]| {
+            for code in codes {
+                let event = Event::Key(KeyEvent::new(*code, KeyModifiers::NONE));
+                update(editor, area, &event).unwrap();
+            }
+        };
+        let word = |word: &str| word.chars().map(KeyCode::Char).collect::<Vec<_>>();
+        // Each Insert session is its own undo step, even when it carries on from the last one.
+        press(&mut editor, &[KeyCode::Char('i')]);
+        press(&mut editor, &word("foo"));
+        press(&mut editor, &[KeyCode::Esc, KeyCode::Char('i')]);
+        press(&mut editor, &word("bar"));
+        press(&mut editor, &[KeyCode::Esc, KeyCode::Char('u')]);
+        assert_eq!(editor.text, "foo");
+        // So is typing after moving, even back to the same place.
+        press(
+            &mut editor,
+            &[KeyCode::Char('i'), KeyCode::Left, KeyCode::Right],
+        );
+        press(&mut editor, &word("baz"));
+        press(&mut editor, &[KeyCode::Esc, KeyCode::Char('u')]);
+        assert_eq!(editor.text, "foo");
+    }
+
+    #[test]
+    fn test_inserted_register() {
+        let mut editor = Editor::try_from(Rope::from("x\n")).unwrap();
+        let area = Rect::new(0, 0, 20, 5);
+        let press = |editor: &mut Editor, code, modifiers| {
+            update(editor, area, &Event::Key(KeyEvent::new(code, modifiers))).unwrap();
+        };
+        press(&mut editor, KeyCode::Char('i'), KeyModifiers::NONE);
+        for char in "foo".chars() {
+            press(&mut editor, KeyCode::Char(char), KeyModifiers::NONE);
+        }
+        // Deleted text isn't part of what was typed.
+        press(&mut editor, KeyCode::Backspace, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Enter, KeyModifiers::NONE);
+        assert_eq!(editor.inserted, "");
+        press(&mut editor, KeyCode::Esc, KeyModifiers::NONE);
+        assert_eq!(editor.text, "fo\nx\n");
+        assert_eq!(editor.inserted, "fo\n");
+
+        press(&mut editor, KeyCode::Char('p'), KeyModifiers::ALT);
+        assert_eq!(editor.text, "fo\nfo\nx\n");
+        // The next session replaces it, and leaves the main register alone.
+        press(&mut editor, KeyCode::Char('i'), KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Char('a'), KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Esc, KeyModifiers::NONE);
+        assert_eq!(editor.inserted, "a");
+        assert_eq!(editor.register, "");
+
+        // Deleting a word takes it back out, and pasted text goes in like typing.
+        press(&mut editor, KeyCode::Char('i'), KeyModifiers::NONE);
+        for char in "foo bar".chars() {
+            press(&mut editor, KeyCode::Char(char), KeyModifiers::NONE);
+        }
+        press(&mut editor, KeyCode::Char('w'), KeyModifiers::CONTROL);
+        update(&mut editor, area, &Event::Paste(String::from("baz\r\n"))).unwrap();
+        press(&mut editor, KeyCode::Esc, KeyModifiers::NONE);
+        assert_eq!(editor.inserted, "foo baz\n");
+        // Deleting past the start of the session leaves nothing.
+        press(&mut editor, KeyCode::Char('i'), KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Char(' '), KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Char('w'), KeyModifiers::CONTROL);
+        press(&mut editor, KeyCode::Esc, KeyModifiers::NONE);
+        assert_eq!(editor.inserted, "");
+    }
+
+    #[test]
+    fn test_insert_mode_delete() {
+        let mut editor = Editor::try_from(Rope::from("ab\n")).unwrap();
+        let area = Rect::new(0, 0, 20, 5);
+        let event = Event::Key(KeyEvent::new(KeyCode::Delete, KeyModifiers::NONE));
+        editor.mode = Mode::Insert;
+        editor.head = 1;
+        editor.reduce();
+        update(&mut editor, area, &event).unwrap();
+        assert_eq!(editor.text, "a\n");
+        update(&mut editor, area, &event).unwrap();
+        assert_eq!(editor.text, "a");
+        // There's nothing after the end of the text to delete.
+        update(&mut editor, area, &event).unwrap();
+        assert_eq!(editor.text, "a");
+        assert_eq!((editor.anchor, editor.head), (1, 1));
+    }
+
+    #[test]
+    fn test_read_only() {
+        let mut editor = Editor::try_from(Rope::from("hello world\n")).unwrap();
+        let area = Rect::new(0, 0, 20, 5);
+        let press = |editor: &mut Editor, code| {
+            let event = Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+            update(editor, area, &event).unwrap();
+        };
+        editor.run_command("set readonly").unwrap();
+        press(&mut editor, KeyCode::Char('d'));
+        assert_eq!(editor.text, "hello world\n");
+        assert_eq!(
+            editor.message,
+            Some(Err(String::from("Buffer is read-only")))
+        );
+        press(&mut editor, KeyCode::Char('i'));
+        assert!(editor.mode == Mode::Normal);
+        assert!(!editor.modified);
+        // Nor can it be saved, anywhere.
+        let dir = temp_dir("ui-read-only");
+        let path = dir.join("a.txt");
+        for command in [format!("w {path}"), String::from("w"), String::from("wq")] {
+            editor.message = None;
+            editor.run_command(&command).unwrap();
+            assert_eq!(
+                editor.message,
+                Some(Err(String::from("Buffer is read-only")))
+            );
+        }
+        assert!(!path.exists());
+        assert_eq!(editor.exit_code, None);
+        // Moving around still works.
+        press(&mut editor, KeyCode::Char('w'));
+        assert_eq!((editor.anchor, editor.head), (0, 6));
+        assert_eq!(editor.message, None);
+        editor.run_command("set noreadonly").unwrap();
+        press(&mut editor, KeyCode::Char('d'));
+        assert_eq!(editor.text, "world\n");
+    }
+
+    #[test]
+    fn test_command_mode_kill() {
+        let mut editor = Editor::try_from(Rope::from("a\n")).unwrap();
+        let area = Rect::new(0, 0, 40, 5);
+        let press = |editor: &mut Editor, modifiers, code| {
+            let event = Event::Key(KeyEvent::new(code, modifiers));
+            update(editor, area, &event).unwrap();
+        };
+        for char in ":echo héllo wörld".chars() {
+            press(&mut editor, KeyModifiers::NONE, KeyCode::Char(char));
+        }
+        for _ in 0.."wörld".chars().count() {
+            press(&mut editor, KeyModifiers::NONE, KeyCode::Left);
+        }
+        // Ctrl-k deletes to the end and leaves the cursor where it is.
+        press(&mut editor, KeyModifiers::CONTROL, KeyCode::Char('k'));
+        assert_eq!(editor.command, "echo héllo ");
+        assert_eq!(editor.command_cursor, "echo héllo ".len());
+        for _ in 0.."héllo ".chars().count() {
+            press(&mut editor, KeyModifiers::NONE, KeyCode::Left);
+        }
+        // Ctrl-u deletes to the start and moves the cursor there.
+        press(&mut editor, KeyModifiers::CONTROL, KeyCode::Char('u'));
+        assert_eq!(editor.command, "héllo ");
+        assert_eq!(editor.command_cursor, 0);
+        assert!(editor.mode == Mode::Command);
+    }
+
+    #[test]
+    fn test_complete_path() {
+        let dir = temp_dir("ui-complete");
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        for name in ["main.rs", "map.txt", ".hidden", "src/lib.rs"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let mut editor = Editor::try_from(Rope::from("a\n")).unwrap();
+        editor.pwd = Some(dir.to_path_buf());
+        let area = Rect::new(0, 0, 40, 5);
+        let press = |editor: &mut Editor, code| {
+            let event = Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+            update(editor, area, &event).unwrap();
+        };
+        for char in ":e m".chars() {
+            press(&mut editor, KeyCode::Char(char));
+        }
+        // Several matches fill in what they share, and are listed.
+        press(&mut editor, KeyCode::Tab);
+        assert_eq!(editor.command, "e ma");
+        assert_eq!(
+            editor.completion().unwrap().candidates,
+            ["main.rs", "map.txt"]
+        );
+        press(&mut editor, KeyCode::Char('i'));
+        press(&mut editor, KeyCode::Tab);
+        assert_eq!(editor.command, "e main.rs");
+        editor.command = Rope::from("e s");
+        editor.command_cursor = editor.command.byte_len();
+        press(&mut editor, KeyCode::Tab);
+        press(&mut editor, KeyCode::Tab);
+        assert_eq!(editor.command, "e src/lib.rs");
+        assert_eq!(editor.command_cursor, editor.command.byte_len());
+        // Only commands which take a path complete.
+        editor.command = Rope::from("echo m");
+        editor.command_cursor = editor.command.byte_len();
+        press(&mut editor, KeyCode::Tab);
+        assert_eq!(editor.command, "echo m");
+    }
+
+    #[test]
+    fn test_complete_command() {
+        let mut editor = Editor::try_from(Rope::from("a\n")).unwrap();
+        let area = Rect::new(0, 0, 40, 5);
+        let press = |editor: &mut Editor, code| {
+            let event = Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+            update(editor, area, &event).unwrap();
+        };
+        for char in ":wr".chars() {
+            press(&mut editor, KeyCode::Char(char));
+        }
+        press(&mut editor, KeyCode::Tab);
+        assert_eq!(editor.command, "write");
+        assert_eq!(
+            editor.completion().unwrap().candidates,
+            ["write", "write-quit"]
+        );
+        // Aliases are offered too.
+        editor.command = Rope::from("q");
+        editor.command_cursor = 1;
+        press(&mut editor, KeyCode::Tab);
+        assert_eq!(editor.command, "q");
+        assert_eq!(
+            editor.completion().unwrap().candidates,
+            [
+                "q",
+                "q!",
+                "qa",
+                "qa!",
+                "quit",
+                "quit!",
+                "quit-all",
+                "quit-all!"
+            ]
+        );
+        editor.command = Rope::from("mks");
+        editor.command_cursor = 3;
+        press(&mut editor, KeyCode::Tab);
+        assert_eq!(editor.command, "mksession");
+        assert!(editor.completion().is_none());
+    }
+
+    #[test]
+    fn test_cycle_completions() {
+        let mut editor = Editor::try_from(Rope::from("a\n")).unwrap();
+        let area = Rect::new(0, 0, 40, 5);
+        let press = |editor: &mut Editor, modifiers, code| {
+            let event = Event::Key(KeyEvent::new(code, modifiers));
+            update(editor, area, &event).unwrap();
+        };
+        for char in ":wr".chars() {
+            press(&mut editor, KeyModifiers::NONE, KeyCode::Char(char));
+        }
+        editor.command.insert(2, " x");
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Tab);
+        assert_eq!(editor.command, "write x");
+        assert_eq!(editor.completion().unwrap().index, None);
+        // Each Tab fills in the next match, wrapping around, and Shift-Tab goes back.
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Tab);
+        assert_eq!(editor.command, "write x");
+        assert_eq!(editor.command_cursor, 5);
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Tab);
+        assert_eq!(editor.command, "write-quit x");
+        assert_eq!(editor.command_cursor, 10);
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Tab);
+        assert_eq!(editor.command, "write x");
+        press(&mut editor, KeyModifiers::SHIFT, KeyCode::BackTab);
+        assert_eq!(editor.command, "write-quit x");
+        assert_eq!(editor.completion().unwrap().index, Some(1));
+        // The one filled in is highlighted in the list.
+        let mut buffer = Buffer::empty(area);
+        render(&editor, area, &mut buffer);
+        let status_bar = Areas::new(&editor.text, area).status_bar;
+        assert_eq!(buffer[(status_bar.x, status_bar.y)].symbol(), "w");
+        assert_eq!(buffer[(status_bar.x, status_bar.y)].bg, Color::Reset);
+        assert_eq!(buffer[(status_bar.x + 7, status_bar.y)].bg, DARK_YELLOW);
+        press(&mut editor, KeyModifiers::SHIFT, KeyCode::BackTab);
+        assert_eq!(editor.command, "write x");
+        assert_eq!(editor.completion().unwrap().index, Some(0));
+
+        // Enter keeps the match without running the command.
+        press(&mut editor, KeyModifiers::SHIFT, KeyCode::BackTab);
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Enter);
+        assert_eq!(editor.command, "write-quit x");
+        assert!(editor.mode == Mode::Command);
+        assert!(editor.completion().is_none());
+
+        // Typing starts over from the new prefix, where Shift-Tab starts from the last match.
+        editor.command = Rope::from("q");
+        editor.command_cursor = 1;
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Tab);
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Char('u'));
+        assert!(editor.completion().is_none());
+        press(&mut editor, KeyModifiers::SHIFT, KeyCode::BackTab);
+        assert_eq!(editor.command, "quit-all!");
+        assert_eq!(
+            editor.completion().unwrap().candidates,
+            ["quit", "quit!", "quit-all", "quit-all!"]
+        );
+    }
+
+    #[test]
+    fn test_incremental_search() {
+        let mut editor = Editor::try_from(Rope::from("foo bar\nbar foo\nbaz\n")).unwrap();
+        let area = Rect::new(0, 0, 40, 5);
+        let text_area = Areas::new(&editor.text, area).text;
+        let press = |editor: &mut Editor, code| {
+            let event = Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+            update(editor, area, &event).unwrap();
+            (editor.anchor, editor.head)
+        };
+        editor.move_to(0);
+        press(&mut editor, KeyCode::Char('/'));
+        assert_eq!(press(&mut editor, KeyCode::Char('b')), (4, 5));
+        press(&mut editor, KeyCode::Char('a'));
+        assert_eq!(press(&mut editor, KeyCode::Char('r')), (4, 7));
+        // Every match in view is highlighted while typing.
+        let mut buffer = Buffer::empty(area);
+        render(&editor, area, &mut buffer);
+        assert_eq!(buffer[(text_area.x, text_area.y + 1)].bg, LIGHT_ORANGE);
+        assert_eq!(buffer[(text_area.x, text_area.y + 2)].bg, Color::Reset);
+        // The search options in effect are shown at the right of the prompt.
+        let status_bar = (0..area.width)
+            .map(|x| buffer[(x, 0)].symbol())
+            .collect::<String>();
+        assert_eq!(status_bar, format!("/bar{}smart-case", " ".repeat(26)));
+        // Without a match, the selection goes back to where the search started.
+        assert_eq!(press(&mut editor, KeyCode::Char('z')), (0, 1));
+        assert_eq!(press(&mut editor, KeyCode::Backspace), (4, 7));
+        assert_eq!(press(&mut editor, KeyCode::Esc), (0, 1));
+        assert!(editor.mode == Mode::Normal);
+        assert_eq!(editor.last_search, None);
+        press(&mut editor, KeyCode::Char('/'));
+        for char in "baz".chars() {
+            press(&mut editor, KeyCode::Char(char));
+        }
+        assert_eq!(press(&mut editor, KeyCode::Enter), (16, 19));
+        assert_eq!(editor.last_search.as_deref(), Some("baz"));
+    }
+
+    #[test]
+    fn test_count() {
+        let mut editor = Editor::try_from(Rope::from("a\nb\nc\nd\ne\n")).unwrap();
+        let area = Rect::new(0, 0, 40, 10);
+        let press = |editor: &mut Editor, char| {
+            let event = Event::Key(KeyEvent::new(KeyCode::Char(char), KeyModifiers::NONE));
+            update(editor, area, &event).unwrap();
+        };
+        press(&mut editor, '3');
+        assert_eq!(editor.count, Some(3));
+        let mut buffer = Buffer::empty(area);
+        render(&editor, area, &mut buffer);
+        let status_bar = (0..area.width)
+            .map(|x| buffer[(x, 0)].symbol())
+            .collect::<String>();
+        assert!(status_bar.starts_with("normal 3 · "));
+        press(&mut editor, 'j');
+        assert_eq!(editor.text.line_of_byte(editor.head), 3);
+        assert_eq!(editor.count, None);
+
+        // A count can contain `0`, but can't start with one.
+        press(&mut editor, 'l');
+        press(&mut editor, '0');
+        assert_eq!(editor.count, None);
+        assert_eq!(editor.head, editor.text.byte_of_line(3));
+        press(&mut editor, '1');
+        press(&mut editor, '0');
+        press(&mut editor, 'k');
+        assert_eq!(editor.count, None);
+        assert_eq!(editor.head, 0);
+    }
+
+    #[test]
+    fn test_follow_cursor() {
+        let text = (0..20).map(|n| n.to_string() + "\n").collect::<String>();
+        let mut editor = Editor::try_from(Rope::from(text)).unwrap();
+        // One row is the status bar, leaving ten for text.
+        let area = Rect::new(0, 0, 20, 11);
+        let press = |editor: &mut Editor, modifiers, char| {
+            let event = Event::Key(KeyEvent::new(KeyCode::Char(char), modifiers));
+            update(editor, area, &event).unwrap();
+        };
+        for _ in 0..7 {
+            press(&mut editor, KeyModifiers::NONE, 'j');
+        }
+        assert_eq!(editor.vertical_scroll, 1);
+        press(&mut editor, KeyModifiers::CONTROL, 'd');
+        assert_eq!(editor.vertical_scroll, 5);
+        press(&mut editor, KeyModifiers::NONE, 'k');
+        assert_eq!(editor.vertical_scroll, 3);
+    }
+
+    #[test]
+    fn test_search_scroll() {
+        let text = (0..40)
+            .map(|n| {
+                if [2, 8, 30].contains(&n) {
+                    "x\n"
+                } else {
+                    "-\n"
+                }
+            })
+            .collect::<String>();
+        let mut editor = Editor::try_from(Rope::from(text)).unwrap();
+        // One row is the status bar, leaving ten for text.
+        let area = Rect::new(0, 0, 20, 11);
+        let press = |editor: &mut Editor, modifiers, code| {
+            let event = Event::Key(KeyEvent::new(code, modifiers));
+            update(editor, area, &event).unwrap();
+        };
+        for char in ['/', 'x'] {
+            press(&mut editor, KeyModifiers::NONE, KeyCode::Char(char));
+        }
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Enter);
+        assert_eq!(editor.text.line_of_byte(editor.head), 2);
+        // Already visible outside the margin, so there's no scrolling.
+        assert_eq!(editor.vertical_scroll, 0);
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Char('n'));
+        assert_eq!(editor.text.line_of_byte(editor.head), 8);
+        // Scrolled just enough to keep the margin below it, rather than centered.
+        assert_eq!(editor.vertical_scroll, 2);
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Char('n'));
+        assert_eq!(editor.vertical_scroll, 24);
+        press(&mut editor, KeyModifiers::SHIFT, KeyCode::Char('N'));
+        assert_eq!(editor.vertical_scroll, 5);
+    }
+
+    #[test]
+    fn test_selection_extent() {
+        let text = (0..20).map(|n| n.to_string() + "\n").collect::<String>();
+        let mut editor = Editor::try_from(Rope::from(text)).unwrap();
+        let area = Rect::new(0, 0, 20, 6);
+        let signs_area = Areas::new(&editor.text, area).signs;
+        let gutter = |editor: &Editor| {
+            let mut buffer = Buffer::empty(area);
+            render(editor, area, &mut buffer);
+            let top = buffer[(signs_area.x, signs_area.top())]
+                .symbol()
+                .to_string();
+            let bottom = buffer[(signs_area.x, signs_area.bottom() - 1)]
+                .symbol()
+                .to_string();
+            (top, bottom)
+        };
+
+        editor.vertical_scroll = 5;
+        editor.anchor = editor.text.byte_of_line(6);
+        editor.head = editor.text.byte_of_line(8);
+        assert_eq!(gutter(&editor), (String::from(" "), String::from(" ")));
+
+        // Ends on the last visible line, since the head is exclusive.
+        editor.head = editor.text.byte_of_line(10);
+        assert_eq!(gutter(&editor), (String::from(" "), String::from(" ")));
+
+        editor.head = editor.text.byte_of_line(11);
+        assert_eq!(gutter(&editor), (String::from(" "), String::from("↓")));
+
+        editor.anchor = editor.text.byte_of_line(11);
+        editor.head = editor.text.byte_of_line(2);
+        assert_eq!(gutter(&editor), (String::from("↑"), String::from("↓")));
+
+        // A single line of text is both the first and last visible line.
+        let area = Rect::new(0, 0, 40, 2);
+        let signs_area = Areas::new(&editor.text, area).signs;
+        let mut buffer = Buffer::empty(area);
+        editor.vertical_scroll = 5;
+        editor.anchor = editor.text.byte_of_line(5);
+        editor.head = editor.text.byte_of_line(7);
+        render(&editor, area, &mut buffer);
+        assert_eq!(buffer[(signs_area.x, signs_area.top())].symbol(), "↓");
+    }
+
+    #[test]
+    fn test_fold() {
+        let text = (0..10).map(|n| n.to_string() + "\n").collect::<String>();
+        let mut editor = Editor::try_from(Rope::from(text)).unwrap();
+        let area = Rect::new(0, 0, 30, 5);
+        let text_area = Areas::new(&editor.text, area).text;
+        let press = |editor: &mut Editor, modifiers, char| {
+            let event = Event::Key(KeyEvent::new(KeyCode::Char(char), modifiers));
+            update(editor, area, &event).unwrap();
+        };
+        let rows = |editor: &Editor| {
+            let mut buffer = Buffer::empty(area);
+            render(editor, area, &mut buffer);
+            (text_area.top()..text_area.bottom())
+                .map(|y| {
+                    (0..area.width)
+                        .map(|x| buffer[(x, y)].symbol())
+                        .collect::<String>()
+                        .trim_end()
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        press(&mut editor, KeyModifiers::NONE, 'j');
+        press(&mut editor, KeyModifiers::SHIFT, 'J');
+        press(&mut editor, KeyModifiers::SHIFT, 'J');
+        press(&mut editor, KeyModifiers::NONE, 'z');
+        press(&mut editor, KeyModifiers::NONE, 'f');
+        assert!(editor.mode == Mode::Normal);
+        assert_eq!(
+            rows(&editor),
+            ["  1│0", "  2│1 ··· 1 more line", "  4│3", "  5│4"]
+        );
+
+        // The cursor and mouse clicks map to rows below the fold.
+        press(&mut editor, KeyModifiers::NONE, 'j');
+        assert_eq!(
+            cursor_position(&editor, area),
+            Some(Position::new(text_area.x, text_area.y + 2))
+        );
+        assert_eq!(
+            position_to_byte_offset(
+                &editor.text,
+                editor.vertical_scroll,
+                &editor.folds,
+                editor.tab_width,
+                text_area,
+                Position::new(text_area.x, text_area.y + 3),
+            ),
+            Some(editor.text.byte_of_line(4))
+        );
+
+        press(&mut editor, KeyModifiers::NONE, 'k');
+        press(&mut editor, KeyModifiers::NONE, 'z');
+        press(&mut editor, KeyModifiers::NONE, 'a');
+        assert_eq!(rows(&editor), ["  1│0", "  2│1", "  3│2", "  4│3"]);
+    }
+
+    #[test]
+    fn test_reduce() {
+        let mut editor = Editor::try_from(Rope::from("abcd\nx\nabcd")).unwrap();
+        let area = Rect::new(0, 0, 20, 5);
+        let press = |editor: &mut Editor, modifiers, code| {
+            let event = Event::Key(KeyEvent::new(code, modifiers));
+            update(editor, area, &event).unwrap();
+        };
+        editor.move_to(3);
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Char('j'));
+        // Reducing forgets the column the cursor was moving down from.
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Char(';'));
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Char('j'));
+        assert_eq!((editor.anchor, editor.head), (8, 8));
+
+        press(&mut editor, KeyModifiers::SHIFT, KeyCode::Char('L'));
+        press(&mut editor, KeyModifiers::SHIFT, KeyCode::Char('L'));
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Char(';'));
+        assert_eq!((editor.anchor, editor.head), (10, 10));
+        press(&mut editor, KeyModifiers::SHIFT, KeyCode::Char('H'));
+        press(&mut editor, KeyModifiers::SHIFT, KeyCode::Char('H'));
+        press(&mut editor, KeyModifiers::CONTROL, KeyCode::Char(';'));
+        assert_eq!((editor.anchor, editor.head), (10, 10));
+    }
+
+    #[test]
+    fn test_multiple_selections() {
+        let mut editor = Editor::try_from(Rope::from("ab\ncd\n")).unwrap();
+        let area = Rect::new(0, 0, 20, 4);
+        let text_area = Areas::new(&editor.text, area).text;
+        let press = |editor: &mut Editor, modifiers, code| {
+            let event = Event::Key(KeyEvent::new(code, modifiers));
+            update(editor, area, &event).unwrap();
+        };
+        let cursor_colors = |editor: &Editor| {
+            let mut buffer = Buffer::empty(area);
+            render(editor, area, &mut buffer);
+            (
+                buffer[(text_area.x, text_area.y)].bg,
+                buffer[(text_area.x, text_area.y + 1)].bg,
+            )
+        };
+
+        editor.head = 1;
+        press(&mut editor, KeyModifiers::SHIFT, KeyCode::Char('C'));
+        assert_eq!(cursor_colors(&editor), (ORANGE, DARK_YELLOW));
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Char('d'));
+        assert_eq!(editor.text, "b\nd\n");
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Char(','));
+        assert!(editor.selections.is_empty());
+        assert_eq!(cursor_colors(&editor).0, Color::Reset);
+    }
+
+    #[test]
+    fn test_append() {
+        let mut editor = Editor::try_from(Rope::from("ab\ncd\n")).unwrap();
+        let area = Rect::new(0, 0, 20, 5);
+        let press = |editor: &mut Editor, modifiers, code| {
+            let event = Event::Key(KeyEvent::new(code, modifiers));
+            update(editor, area, &event).unwrap();
+        };
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Char('a'));
+        assert!(editor.mode == Mode::Insert);
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Char('x'));
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Esc);
+        assert_eq!(editor.text, "axb\ncd\n");
+
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Char('j'));
+        press(&mut editor, KeyModifiers::SHIFT, KeyCode::Char('A'));
+        press(&mut editor, KeyModifiers::NONE, KeyCode::Char('y'));
+        assert_eq!(editor.text, "axb\ncdy\n");
+        assert_eq!(editor.anchor, editor.head);
+    }
+
+    #[test]
+    fn test_substitute_confirm() {
+        let mut editor = Editor::try_from(Rope::from("x x x\n")).unwrap();
+        let area = Rect::new(0, 0, 40, 4);
+        let press = |editor: &mut Editor, char| {
+            let event = Event::Key(KeyEvent::new(KeyCode::Char(char), KeyModifiers::NONE));
+            update(editor, area, &event).unwrap();
+        };
+        editor.run_command("s --confirm x y").unwrap();
+        let mut buffer = Buffer::empty(area);
+        render(&editor, area, &mut buffer);
+        let status_bar = (0..area.width)
+            .map(|x| buffer[(x, 0)].symbol())
+            .collect::<String>();
+        assert_eq!(status_bar.trim_end(), "Replace with 'y'? (y/n/a/q)");
+
+        press(&mut editor, 'y');
+        press(&mut editor, 'n');
+        assert_eq!(editor.text, "y x x\n");
+        assert!(editor.mode == Mode::Confirm);
+        press(&mut editor, 'q');
+        assert_eq!(editor.text, "y x x\n");
+        assert!(editor.mode == Mode::Normal);
+        assert_eq!(
+            editor.message,
+            Some(Ok(String::from("Replaced 1 occurrence")))
+        );
+    }
+
+    #[test]
+    fn test_select_all() {
+        let text = (0..20).map(|n| n.to_string() + "\n").collect::<String>();
+        let mut editor = Editor::try_from(Rope::from(text)).unwrap();
+        let area = Rect::new(0, 0, 20, 6);
+        let text_area = Areas::new(&editor.text, area).text;
+        editor.vertical_scroll = 16;
+        let event = Event::Key(KeyEvent::new(KeyCode::Char('%'), KeyModifiers::NONE));
+        update(&mut editor, area, &event).unwrap();
+        assert_eq!((editor.anchor, editor.head), (0, editor.text.byte_len()));
+        assert_eq!(editor.vertical_scroll, 16);
+
+        // The cursor is the final newline, one column wide, after the last line's text.
+        let mut buffer = Buffer::empty(area);
+        render(&editor, area, &mut buffer);
+        let last_row = text_area.y + 3;
+        assert_eq!(buffer[(text_area.x + 1, last_row)].bg, LIGHT_YELLOW);
+        assert_eq!(buffer[(text_area.x + 2, last_row)].bg, DARK_YELLOW);
+        assert_eq!(buffer[(text_area.x + 3, last_row)].bg, Color::Reset);
+    }
+
+    #[test]
+    fn test_long_command() {
+        let mut editor = Editor::try_from(Rope::new()).unwrap();
+        let area = Rect::new(0, 0, 10, 3);
+        let status_bar = |editor: &Editor| {
+            let mut buffer = Buffer::empty(area);
+            render(editor, area, &mut buffer);
+            let text = (0..area.width)
+                .map(|x| buffer[(x, 0)].symbol())
+                .collect::<String>();
+            let cursor_x = (0..area.width).find(|x| buffer[(*x, 0)].bg == DARK_YELLOW);
+            (text, cursor_x)
+        };
+        editor.mode = Mode::Command;
+        editor.command = Rope::from("echo 0123456789");
+        editor.command_cursor = editor.command.byte_len();
+        // Scrolled so the cursor is in the last column.
+        assert_eq!(status_bar(&editor), (String::from("123456789 "), Some(9)));
+        assert_eq!(cursor_position(&editor, area), Some(Position::new(9, 0)));
+
+        editor.command_cursor -= 1;
+        assert_eq!(status_bar(&editor), (String::from("0123456789"), Some(9)));
+
+        // Short commands aren't scrolled.
+        editor.command_cursor = 3;
+        assert_eq!(status_bar(&editor), (String::from(":echo 0123"), Some(4)));
+
+        assert_eq!(skip_columns("a日b", 2, 8), " b");
+        assert_eq!(skip_columns("a日b", 3, 8), "b");
+    }
+
+    #[test]
+    fn test_display_path() {
+        let mut editor = Editor::new().unwrap();
+        assert_eq!(display_path(&editor), "*scratch*");
+
+        editor.path = Some(Utf8PathBuf::from("/home/user/project/src/main.rs"));
+        assert_eq!(display_path(&editor), "/home/user/project/src/main.rs");
+
+        editor.pwd = Some(Utf8PathBuf::from("/home/user/project"));
+        assert_eq!(display_path(&editor), "src/main.rs");
+
+        editor.run_command("set norelative-path").unwrap();
+        assert_eq!(display_path(&editor), "/home/user/project/src/main.rs");
+        editor.run_command("set relative-path!").unwrap();
+        assert_eq!(display_path(&editor), "src/main.rs");
+
+        // `../../../../a` is longer than `/a`.
+        editor.path = Some(Utf8PathBuf::from("/a"));
+        editor.pwd = Some(Utf8PathBuf::from("/home/user/project/src"));
+        assert_eq!(display_path(&editor), "/a");
+    }
+
+    #[test]
+    fn test_goto_line() {
+        let text = (1..=100).map(|n| n.to_string() + "\n").collect::<String>();
+        let mut editor = Editor::try_from(Rope::from(text)).unwrap();
+        let area = Rect::new(0, 0, 20, 11);
+        for code in [
+            KeyCode::Char(':'),
+            KeyCode::Char('5'),
+            KeyCode::Char('0'),
+            KeyCode::Enter,
+        ] {
+            let event = Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+            update(&mut editor, area, &event).unwrap();
+        }
+        assert_eq!(editor.text.line_of_byte(editor.head), 49);
+        // The line is scrolled into view, with `scrolloff` lines below it.
+        assert_eq!(editor.vertical_scroll, 43);
+    }
+
+    #[test]
+    fn test_goto_matching_bracket() {
+        let text = format!("{{\n{}}}\n", "a\n".repeat(50));
+        let mut editor = Editor::try_from(Rope::from(text)).unwrap();
+        let area = Rect::new(0, 0, 20, 11);
+        let event = Event::Key(KeyEvent::new(KeyCode::Char('m'), KeyModifiers::NONE));
+        update(&mut editor, area, &event).unwrap();
+        assert_eq!(editor.text.line_of_byte(editor.head), 51);
+        assert!(editor.vertical_scroll > 0);
+        update(&mut editor, area, &event).unwrap();
+        assert_eq!((editor.anchor, editor.head), (0, 1));
+        assert_eq!(editor.vertical_scroll, 0);
+    }
+
+    #[test]
+    fn test_matching_bracket_highlight() {
+        let mut editor = Editor::try_from(Rope::from("(a)\n")).unwrap();
+        let area = Rect::new(0, 0, 20, 3);
+        let text_area = Areas::new(&editor.text, area).text;
+        let backgrounds = |editor: &Editor| {
+            let mut buffer = Buffer::empty(area);
+            render(editor, area, &mut buffer);
+            (0..3)
+                .map(|x| buffer[(text_area.x + x, text_area.y)].bg)
+                .collect::<Vec<_>>()
+        };
+        editor.move_to(0);
+        assert_eq!(
+            backgrounds(&editor),
+            [DARK_YELLOW, Color::Reset, LIGHT_BLUE]
+        );
+        editor.move_to(1);
+        assert_eq!(
+            backgrounds(&editor),
+            [Color::Reset, DARK_YELLOW, Color::Reset]
+        );
+        // The match is drawn over the selection, but the cursor keeps its color.
+        editor.move_to(0);
+        editor.extend_to(2);
+        editor.flip();
+        assert_eq!(
+            backgrounds(&editor),
+            [DARK_YELLOW, LIGHT_YELLOW, LIGHT_BLUE]
+        );
+    }
+
+    #[test]
+    fn test_marks() {
+        let mut editor = Editor::try_from(Rope::from("a\nb\nc\n")).unwrap();
+        let area = Rect::new(0, 0, 20, 5);
+        let press = |editor: &mut Editor, modifiers, char| {
+            let event = Event::Key(KeyEvent::new(KeyCode::Char(char), modifiers));
+            update(editor, area, &event).unwrap();
+        };
+        press(&mut editor, KeyModifiers::NONE, 'j');
+        press(&mut editor, KeyModifiers::SHIFT, 'Z');
+        assert!(editor.mode == Mode::SetMark);
+        press(&mut editor, KeyModifiers::NONE, 'x');
+        press(&mut editor, KeyModifiers::NONE, 'j');
+        press(&mut editor, KeyModifiers::NONE, '\'');
+        assert!(editor.mode == Mode::GotoMark);
+        press(&mut editor, KeyModifiers::NONE, 'x');
+        assert!(editor.mode == Mode::Normal);
+        assert_eq!(editor.cursor(), 2);
+    }
+
+    #[test]
+    fn test_goto_line_end_at_eof() {
+        let dir = temp_dir("ui-eof");
+        std::fs::write(dir.join("a.txt"), "a\nbc").unwrap();
+        let mut editor = Editor::open(dir.join("a.txt")).unwrap();
+        let area = Rect::new(0, 0, 20, 5);
+        let goto = |editor: &mut Editor, modifiers, char| {
+            for (modifiers, char) in [(KeyModifiers::NONE, 'g'), (modifiers, char)] {
+                let event = Event::Key(KeyEvent::new(KeyCode::Char(char), modifiers));
+                update(editor, area, &event).unwrap();
+            }
+        };
+        // The last line has no newline, so its end is the end of the text.
+        editor.move_to(2);
+        goto(&mut editor, KeyModifiers::NONE, 'l');
+        assert_eq!((editor.anchor, editor.head), (4, 4));
+        goto(&mut editor, KeyModifiers::SHIFT, 'L');
+        assert_eq!((editor.anchor, editor.head), (4, 4));
+        // With a newline, the end of the text starts an empty line.
+        editor.text = Rope::from("a\nbc\n");
+        (editor.anchor, editor.head) = (5, 5);
+        goto(&mut editor, KeyModifiers::NONE, 'l');
+        assert_eq!((editor.anchor, editor.head), (5, 5));
+    }
+
+    #[test]
+    fn test_resize() {
+        let text = (1..=100).map(|n| n.to_string() + "\n").collect::<String>();
+        let mut editor = Editor::try_from(Rope::from(text)).unwrap();
+        editor.set_message(Ok(String::from("hi")));
+        editor.scrolloff = 0;
+        editor.move_to(editor.text.byte_of_line(20));
+        // The cursor is on the last of 21 rows of text.
+        let event = Event::Resize(20, 22);
+        update(&mut editor, Rect::new(0, 0, 20, 22), &event).unwrap();
+        assert_eq!(editor.vertical_scroll, 0);
+        // Shrinking the window scrolls to keep the cursor in view, and keeps the message.
+        let event = Event::Resize(20, 11);
+        update(&mut editor, Rect::new(0, 0, 20, 11), &event).unwrap();
+        assert_eq!(editor.vertical_scroll, 11);
+        assert!(editor.message.is_some());
+    }
+
+    #[test]
+    fn test_scroll_cursor() {
+        let text = (1..=100).map(|n| n.to_string() + "\n").collect::<String>();
+        let mut editor = Editor::try_from(Rope::from(text)).unwrap();
+        let area = Rect::new(0, 0, 20, 11);
+        let view = |editor: &mut Editor, char| {
+            for char in ['z', char] {
+                let event = Event::Key(KeyEvent::new(KeyCode::Char(char), KeyModifiers::NONE));
+                update(editor, area, &event).unwrap();
+            }
+            assert!(editor.mode == Mode::Normal);
+            editor.vertical_scroll
+        };
+        editor.goto_line(50);
+        // The text area is ten lines tall, with three lines of `scrolloff`.
+        assert_eq!(view(&mut editor, 'z'), 45);
+        assert_eq!(view(&mut editor, 't'), 46);
+        assert_eq!(view(&mut editor, 'b'), 43);
+        // The viewport doesn't go past either end of the text.
+        editor.goto_line(2);
+        assert_eq!(view(&mut editor, 'b'), 0);
+        editor.goto_line(100);
+        assert_eq!(view(&mut editor, 'b'), 90);
+        assert_eq!(view(&mut editor, 't'), 96);
+    }
+
+    #[test]
+    fn test_relative_line_numbers() {
+        let mut editor = Editor::try_from(Rope::from("a\nb\nc\nd\n")).unwrap();
+        let area = Rect::new(0, 0, 10, 5);
+        let line_numbers = |editor: &Editor| {
+            let mut buffer = Buffer::empty(area);
+            render(editor, area, &mut buffer);
+            let gutter = Areas::new(&editor.text, area).line_numbers;
+            gutter
+                .rows()
+                .map(|row| {
+                    let row = row
+                        .columns()
+                        .map(|cell| buffer[(cell.x, cell.y)].symbol())
+                        .collect::<String>();
+                    row.trim_start().trim_end_matches('│').to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(line_numbers(&editor), ["1", "2", "3", "4"]);
+        editor.run_command("set line-numbers relative").unwrap();
+        editor.move_to(4);
+        assert_eq!(line_numbers(&editor), ["2", "1", "3", "1"]);
+    }
+
+    #[test]
+    fn test_tab_width() {
+        let mut editor = Editor::try_from(Rope::from("ab\tx \ty\n")).unwrap();
+        let area = Rect::new(0, 0, 20, 3);
+        let text_area = Areas::new(&editor.text, area).text;
+        editor.run_command("set tab-width 4").unwrap();
+        let mut buffer = Buffer::empty(area);
+        render(&editor, area, &mut buffer);
+        let click = |editor: &Editor, column| {
+            position_to_byte_offset(
+                &editor.text,
+                editor.vertical_scroll,
+                &editor.folds,
+                editor.tab_width,
+                text_area,
+                Position::new(text_area.x + column, text_area.y),
+            )
+        };
+        // Each tab reaches the next multiple of four, and drawing, the cursor, and clicks agree.
+        for (byte_offset, column, symbol) in [(3, 4, "x"), (6, 8, "y")] {
+            editor.move_to(byte_offset);
+            let position = Position::new(text_area.x + column, text_area.y);
+            assert_eq!(cursor_position(&editor, area), Some(position));
+            assert_eq!(buffer[position].symbol(), symbol);
+            assert_eq!(click(&editor, column), Some(byte_offset));
+        }
+        // Clicking inside a tab lands on the tab.
+        assert_eq!(click(&editor, 3), Some(2));
+        assert_eq!(click(&editor, 7), Some(5));
+    }
+
+    #[test]
+    fn test_list() {
+        let mut editor = Editor::try_from(Rope::from("a\tb  \n  \nc")).unwrap();
+        let area = Rect::new(0, 0, 20, 4);
+        let text_area = Areas::new(&editor.text, area).text;
+        editor.run_command("set tab-width 4").unwrap();
+        editor.run_command("set list").unwrap();
+        editor.move_to(2);
+        let mut buffer = Buffer::empty(area);
+        render(&editor, area, &mut buffer);
+        let row = |y| {
+            (text_area.left()..text_area.right())
+                .map(|x| buffer[(x, text_area.y + y)].symbol())
+                .collect::<String>()
+        };
+        assert_eq!(row(0).trim_end(), "a→  b··¶");
+        assert_eq!(row(1).trim_end(), "··¶");
+        assert_eq!(row(2).trim_end(), "c");
+        // The tab still reaches the next tab stop.
+        assert_eq!(
+            cursor_position(&editor, area),
+            Some(Position::new(text_area.x + 4, text_area.y))
+        );
+    }
+
+    #[test]
+    fn test_status_position() {
+        let mut editor = Editor::try_from(Rope::from("a\tbc\ndé\n")).unwrap();
+        let area = Rect::new(0, 0, 40, 3);
+        let status_bar = |editor: &Editor| {
+            let mut buffer = Buffer::empty(area);
+            render(editor, area, &mut buffer);
+            (0..area.width)
+                .map(|x| buffer[(x, 0)].symbol())
+                .collect::<String>()
+        };
+        editor.move_to(2);
+        assert!(status_bar(&editor).starts_with("normal · *scratch* 1:9 ·"));
+        editor.extend_to(3);
+        assert!(status_bar(&editor).starts_with("normal · *scratch* 1:10 (2 sel) ·"));
+        (editor.anchor, editor.head) = (8, 0);
+        assert!(status_bar(&editor).starts_with("normal · *scratch* 1:1 (7 sel) ·"));
+    }
+
+    #[test]
+    fn test_buffers_status() {
+        let mut editor = Editor::new().unwrap();
+        let area = Rect::new(0, 0, 40, 3);
+        let status_bar = |editor: &Editor| {
+            let mut buffer = Buffer::empty(area);
+            render(editor, area, &mut buffer);
+            (0..area.width)
+                .map(|x| buffer[(x, 0)].symbol())
+                .collect::<String>()
+        };
+        assert!(status_bar(&editor).starts_with("normal · *scratch* 1:1 ·"));
+        editor.run_command("edit /nonexistent/a.txt").unwrap();
+        editor.message = None;
+        assert!(status_bar(&editor).starts_with("normal · /nonexistent/a.txt [2/2] 1:1 ·"));
+        editor.run_command("buffer-next").unwrap();
+        assert!(status_bar(&editor).starts_with("normal · *scratch* [1/2] 1:1 ·"));
+    }
+
+    #[test]
+    fn test_eol_cursor() {
+        let area = Rect::new(0, 0, 10, 5);
+        let rope = Rope::from("\nab\n日本\nx\r\n0123456789\n");
+        let line_end =
+            |line_index: usize| rope.byte_of_line(line_index) + rope.line(line_index).byte_len();
+        let eol_area =
+            |line_index| byte_offset_to_area(&rope, 0, &[], 8, area, line_end(line_index));
+        assert_eq!(eol_area(0), Some(Rect::new(0, 0, 1, 1)));
+        assert_eq!(eol_area(1), Some(Rect::new(2, 1, 1, 1)));
+        assert_eq!(eol_area(2), Some(Rect::new(4, 2, 1, 1)));
+        assert_eq!(eol_area(3), Some(Rect::new(1, 3, 1, 1)));
+        // The line fills the whole width, so the end-of-line column is off screen.
+        assert_eq!(eol_area(4), None);
+        // EOF after a trailing newline is at the start of the next line.
+        assert_eq!(
+            byte_offset_to_area(&rope, 1, &[], 8, area, rope.byte_len()),
+            Some(Rect::new(0, 4, 1, 1))
+        );
+
+        let mut editor = Editor::try_from(Rope::from("ab\n")).unwrap();
+        editor.head = 2;
+        editor.anchor = 2;
+        let area = Rect::new(0, 0, 20, 3);
+        let text_area = Areas::new(&editor.text, area).text;
+        let mut buffer = Buffer::empty(area);
+        render(&editor, area, &mut buffer);
+        let cell = &buffer[(text_area.x + 2, text_area.y)];
+        assert_eq!(cell.symbol(), " ");
+        assert_eq!(cell.bg, DARK_ORANGE);
+
+        editor.run_command("set eol-cursor newline").unwrap();
+        let mut buffer = Buffer::empty(area);
+        render(&editor, area, &mut buffer);
+        let cell = &buffer[(text_area.x + 2, text_area.y)];
+        assert_eq!(cell.symbol(), "↵");
+        assert_eq!(cell.bg, DARK_ORANGE);
+    }
+}